@@ -1,24 +1,297 @@
-use heck::ToSnakeCase;
+use heck::{ToSnakeCase, ToUpperCamelCase};
+use std::collections::HashMap;
 use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
 
 const EXPORT_LIST: &[&str] = &["PidConfig", "Odometry", "Angles", "DebugConfig", "SharedMem", "VolatileCell"];
+/// Log record types decoded by the Python tools on top of the shared memory ones
+const PYTHON_LIST: &[&str] = &["FlightCommand", "MeasureRecord"];
+const HEADER: &str = "../pru/src/shared-memory.h";
+const LAYOUT_HEADER: &str = "../pru/src/shared-memory-layout.h";
+const PYTHON_MODULE: &str = "../model/drosix_types.py";
 
-fn main() {
-    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-    println!("cargo:rerun-if-changed=build.rs");
-    println!("cargo:rerun-if-changed=src/types.rs");
-    println!("cargo:rerun-if-changed=src/controller.rs");
+/// Size and alignment of a C type
+#[derive(Clone, Copy)]
+struct TypeLayout {
+    size: usize,
+    align: usize,
+}
+
+/// Field of a C structure with its offset and size
+struct Field {
+    name: String,
+    /// Type without typedef, e.g. `float` or `struct angles`
+    kind: String,
+    /// Number of elements of an array
+    count: usize,
+    offset: usize,
+    size: usize,
+}
+
+/// Layout of the structures of the generated header following the C rules, enums are 32 bits like on the PRU
+struct Layout {
+    structs: Vec<(String, Vec<Field>, TypeLayout)>,
+    enums: Vec<(String, Vec<String>)>,
+}
+
+impl Layout {
+    fn parse(header: &str) -> Self {
+        let mut types: HashMap<String, TypeLayout> = [("uint32_t", 4), ("int32_t", 4), ("float", 4), ("uint8_t", 1)]
+            .into_iter()
+            .map(|(name, size)| {
+                (
+                    name.to_owned(),
+                    TypeLayout {
+                        size,
+                        align: size,
+                    },
+                )
+            })
+            .collect();
+        let mut aliases: HashMap<String, String> = HashMap::new();
+        let mut layout = Self {
+            structs: Vec::new(),
+            enums: Vec::new(),
+        };
+        let mut lines = header.lines().map(str::trim);
+        while let Some(line) = lines.next() {
+            if let Some(name) = line.strip_prefix("enum ").and_then(|x| x.strip_suffix(" {")) {
+                let variants = lines.by_ref().take_while(|x| *x != "};").map(|x| x.trim_end_matches(',').into());
+                layout.enums.push((name.into(), variants.collect()));
+                types.insert(
+                    format!("enum {}", name),
+                    TypeLayout {
+                        size: 4,
+                        align: 4,
+                    },
+                );
+            } else if let Some(name) = line.strip_prefix("struct ").and_then(|x| x.strip_suffix(" {")) {
+                let (mut fields, mut offset, mut align) = (Vec::new(), 0usize, 1);
+                for line in lines.by_ref().take_while(|x| *x != "};") {
+                    let (kind, declarator) = line.trim_end_matches(';').rsplit_once(' ').expect("Field declaration");
+                    let (name, count) = match declarator.split_once('[') {
+                        Some((name, count)) => (name, count.trim_end_matches(']').parse().expect("Array length")),
+                        None => (declarator, 1),
+                    };
+                    let kind = aliases.get(kind).cloned().unwrap_or_else(|| kind.to_owned());
+                    let field = *types.get(&kind).unwrap_or_else(|| panic!("Unknown type {}", kind));
+                    offset = offset.next_multiple_of(field.align);
+                    align = align.max(field.align);
+                    fields.push(Field {
+                        name: name.into(),
+                        kind,
+                        count,
+                        offset,
+                        size: field.size * count,
+                    });
+                    offset += field.size * count;
+                }
+                let struct_layout = TypeLayout {
+                    size: offset.next_multiple_of(align),
+                    align,
+                };
+                types.insert(format!("struct {}", name), struct_layout);
+                layout.structs.push((name.into(), fields, struct_layout));
+            } else if let Some(typedef) = line.strip_prefix("typedef ").and_then(|x| x.strip_suffix(';')) {
+                let (kind, name) = typedef.rsplit_once(' ').expect("Typedef declaration");
+                let kind = aliases.get(kind).cloned().unwrap_or_else(|| kind.to_owned());
+                if types.contains_key(&kind) {
+                    aliases.insert(name.into(), kind);
+                }
+            }
+        }
+        layout
+    }
+
+    /// FNV-1a hash of the fields offsets and sizes and of the enum variants
+    fn hash(&self) -> u32 {
+        let mut description = String::new();
+        for (name, fields, _) in &self.structs {
+            for field in fields {
+                let _ = write!(description, "{}.{}:{}:{};", name, field.name, field.offset, field.size);
+            }
+        }
+        for (name, variants) in &self.enums {
+            let _ = write!(description, "{}:{};", name, variants.join(","));
+        }
+        description.bytes().fold(0x811c_9dc5, |hash, byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193))
+    }
+
+    /// C header with the layout hash and the offset checks failing the PRU build on a mismatch
+    fn c_header(&self) -> String {
+        let mut header = String::from(
+            "/* Generated by drone/build.rs from shared-memory.h, do not edit */\n#pragma once\n#include \
+             <stddef.h>\n#include \"shared-memory.h\"\n\n",
+        );
+        let _ = writeln!(header, "#define SHARED_MEM_LAYOUT_HASH 0x{:08x}U\n", self.hash());
+        for (name, fields, layout) in &self.structs {
+            for field in fields {
+                let _ = writeln!(
+                    header,
+                    "typedef char {}_{}_offset[(offsetof(struct {}, {}) == {}U) ? 1 : -1];",
+                    name, field.name, name, field.name, field.offset
+                );
+            }
+            let _ =
+                writeln!(header, "typedef char {}_size[(sizeof(struct {}) == {}U) ? 1 : -1];", name, name, layout.size);
+        }
+        header
+    }
+
+    /// Rust constants and compile-time checks of the exported structures
+    fn rust_checks(&self) -> String {
+        let mut checks = String::from("/// Shared memory layout hash written by the PRU firmware, see `build.rs`\n");
+        let _ = writeln!(checks, "pub const LAYOUT_HASH: u32 = 0x{:08x};", self.hash());
+        let exported =
+            self.structs.iter().filter(|(name, _, _)| EXPORT_LIST.contains(&name.to_upper_camel_case().as_str()));
+        for (name, fields, layout) in exported {
+            let name = name.to_upper_camel_case();
+            for field in fields {
+                let _ = writeln!(
+                    checks,
+                    "const _: () = assert!(std::mem::offset_of!({}, {}) == {});",
+                    name, field.name, field.offset
+                );
+            }
+            let _ = writeln!(checks, "const _: () = assert!(std::mem::size_of::<{}>() == {});", name, layout.size);
+        }
+        checks
+    }
+}
+
+/// `struct` format character and NumPy type of the scalar C types
+fn python_scalar(kind: &str) -> (char, &'static str) {
+    match kind {
+        "float" => ('f', "<f4"),
+        "uint32_t" => ('I', "<u4"),
+        "int32_t" => ('i', "<i4"),
+        "uint8_t" => ('B', "u1"),
+        _ if kind.starts_with("enum ") => ('i', "<i4"),
+        _ => panic!("No Python type for {}", kind),
+    }
+}
+
+impl Layout {
+    fn find(&self, name: &str) -> &(String, Vec<Field>, TypeLayout) {
+        self.structs.iter().find(|(x, _, _)| x == name).unwrap_or_else(|| panic!("Unknown structure {}", name))
+    }
+
+    /// Scalar fields of a structure with their dotted path, `struct` format character, offset and size
+    fn flatten(&self, name: &str, prefix: &str, base: usize, scalars: &mut Vec<(String, char, usize, usize)>) {
+        for field in &self.find(name).1 {
+            let element = field.size / field.count;
+            for index in 0..field.count {
+                let mut path = format!("{}{}", prefix, field.name);
+                if field.count > 1 {
+                    let _ = write!(path, ".{}", index);
+                }
+                let offset = base + field.offset + index * element;
+                match field.kind.strip_prefix("struct ") {
+                    Some(inner) => self.flatten(inner, &(path + "."), offset, scalars),
+                    None => scalars.push((path, python_scalar(&field.kind).0, offset, element)),
+                }
+            }
+        }
+    }
+
+    /// Python module with a NumPy dtype, a `struct` format and the flattened field names of each structure
+    fn python_module(&self, hash: u32) -> String {
+        let mut module = String::from(
+            "# Generated by drone/build.rs from the Rust types, do not edit\n\"\"\"Layouts of the drone shared memory \
+             and log records.\n\nEach structure comes with a NumPy dtype, a `struct` format and the names of its \
+             flattened fields,\ne.g. `ODOMETRY_DTYPE`, `ODOMETRY_FORMAT` and `ODOMETRY_FIELDS` starting with \
+             `attitude.roll`.\n\"\"\"\nfrom enum import IntEnum\n\nimport numpy as np\n\n",
+        );
+        let _ = writeln!(module, "LAYOUT_HASH = 0x{:08x}", hash);
+        for (name, variants) in &self.enums {
+            let prefix = format!("{}_", name.to_uppercase());
+            let _ = writeln!(module, "\n\nclass {}(IntEnum):", name.to_upper_camel_case());
+            for (value, variant) in variants.iter().enumerate() {
+                let _ = writeln!(module, "    {} = {}", variant.trim_start_matches(&prefix), value);
+            }
+            module.push('\n');
+        }
+        for (name, fields, layout) in &self.structs {
+            let constant = name.to_uppercase();
+            let formats: Vec<String> = fields
+                .iter()
+                .map(|field| {
+                    let kind = match field.kind.strip_prefix("struct ") {
+                        Some(inner) => format!("{}_DTYPE", inner.to_uppercase()),
+                        None => format!("\"{}\"", python_scalar(&field.kind).1),
+                    };
+                    match field.count {
+                        1 => kind,
+                        count => format!("({}, ({},))", kind, count),
+                    }
+                })
+                .collect();
+            let names: Vec<String> = fields.iter().map(|field| format!("\"{}\"", field.name)).collect();
+            let offsets: Vec<String> = fields.iter().map(|field| field.offset.to_string()).collect();
+            let _ = writeln!(module, "\n{}_DTYPE = np.dtype(\n    {{", constant);
+            let _ = writeln!(module, "        \"names\": [{}],", names.join(", "));
+            let _ = writeln!(module, "        \"formats\": [{}],", formats.join(", "));
+            let _ = writeln!(module, "        \"offsets\": [{}],", offsets.join(", "));
+            let _ = writeln!(module, "        \"itemsize\": {},\n    }}\n)", layout.size);
+
+            let mut scalars = Vec::new();
+            self.flatten(name, "", 0, &mut scalars);
+            let (mut format, mut cursor) = (String::from("<"), 0);
+            for (_, kind, offset, size) in &scalars {
+                if *offset > cursor {
+                    let _ = write!(format, "{}x", offset - cursor);
+                }
+                format.push(*kind);
+                cursor = offset + size;
+            }
+            if layout.size > cursor {
+                let _ = write!(format, "{}x", layout.size - cursor);
+            }
+            let paths: Vec<String> = scalars.iter().map(|(path, _, _, _)| format!("    \"{}\",", path)).collect();
+            let _ = writeln!(module, "{}_FORMAT = \"{}\"", constant, format);
+            let _ = writeln!(module, "{}_FIELDS = (\n{}\n)", constant, paths.join("\n"));
+            let _ = writeln!(module, "{}_SIZE = {}", constant, layout.size);
+        }
+        module
+    }
+}
 
-    let config = cbindgen::Config::from_root_or_default(&crate_dir);
+/// Generates the C bindings of the given types
+fn bindings(crate_dir: &str, items: &[&str]) -> String {
+    let config = cbindgen::Config::from_root_or_default(crate_dir);
 
     let mut builder = cbindgen::Builder::new().with_crate(crate_dir).with_config(config);
 
-    for item in EXPORT_LIST {
+    for item in items {
         let renamed = item.to_snake_case();
         let cell = String::from("VolatileCell") + "_" + item;
         builder = builder.include_item(item).rename_item(item, &renamed.as_str()).rename_item(cell, renamed + "_t");
     }
     builder = builder.rename_item((String::from("VolatileCell") + "_u32").as_str(), "u32");
 
-    builder.generate().expect("Unable to generate bindings").write_to_file("../pru/src/shared-memory.h");
+    let mut header = Vec::new();
+    builder.generate().expect("Unable to generate bindings").write(&mut header);
+    String::from_utf8(header).expect("Bindings are not UTF-8")
+}
+
+fn main() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/types.rs");
+    println!("cargo:rerun-if-changed=src/controller.rs");
+    println!("cargo:rerun-if-changed=src/log/mod.rs");
+
+    let header = bindings(&crate_dir, EXPORT_LIST);
+    let layout = Layout::parse(&header);
+
+    fs::write(HEADER, &header).expect("Unable to write bindings");
+    fs::write(LAYOUT_HEADER, layout.c_header()).expect("Unable to write layout header");
+    fs::write(Path::new(&out_dir).join("shared_memory_layout.rs"), layout.rust_checks())
+        .expect("Unable to write layout checks");
+
+    let python = Layout::parse(&bindings(&crate_dir, &[EXPORT_LIST, PYTHON_LIST].concat()));
+    fs::write(PYTHON_MODULE, python.python_module(layout.hash())).expect("Unable to write Python module");
 }
@@ -0,0 +1,201 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::types::{Angles, Command};
+
+/// Origin of a command
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// Safety commands of the drone itself, always accepted
+    Failsafe,
+    /// Gamepad, RC receiver or network remote
+    Pilot,
+    /// Backend server and MAVLink ground station
+    Network,
+    Plugin,
+}
+
+/// Command arbitration configuration stored in the parameter file under `[arbiter]`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ArbiterConfig {
+    /// Sources allowed to control the drone from the highest to the lowest priority
+    pub priority: Vec<Source>,
+    /// Stick deflection or thrust above which a flight command takes the control
+    pub takeover: f32,
+    /// Time in ms without significant command after which a source loses the control
+    pub handback: u64,
+}
+
+impl Default for ArbiterConfig {
+    fn default() -> Self {
+        Self {
+            priority: vec![Source::Pilot, Source::Network, Source::Plugin],
+            takeover: 0.1,
+            handback: 2000,
+        }
+    }
+}
+
+impl ArbiterConfig {
+    /// Loads the arbitration configuration from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("arbiter")
+    }
+}
+
+/// Chooses which source is in control of the drone.
+///
+/// A source takes the control with a significant command when nobody is in control or when it has a higher priority
+/// than the source in control, e.g. the pilot moving the sticks overrides a plugin. The source in control keeps it as
+/// long as it sends significant commands and loses it after the handback delay, letting a lower priority source take
+/// it. The other commands are dropped except the failsafe ones and the pilot disarming which are always accepted, a
+/// failsafe requested by a source is only accepted while it is in control, e.g. a plugin leaving its envelope.
+pub struct Arbiter {
+    config: ArbiterConfig,
+    /// Source in control and the time of its last significant command
+    active: Option<(Source, Instant)>,
+}
+
+impl Arbiter {
+    pub fn new(config: ArbiterConfig) -> Self {
+        Self {
+            config,
+            active: None,
+        }
+    }
+
+    /// Source in control
+    pub fn active(&self) -> Option<Source> {
+        self.active.map(|(source, _)| source)
+    }
+
+    /// Rank of a source, lower is higher priority, `None` if the source is not allowed
+    fn rank(&self, source: Source) -> Option<usize> {
+        self.config.priority.iter().position(|x| *x == source)
+    }
+
+    /// A command worth taking the control: anything but a flight command close to the neutral
+    fn is_significant(&self, command: &Command) -> bool {
+        match command {
+            Command::Flight(command) => command.thrust > self.config.takeover || self.is_deflected(&command.angles),
+            Command::Attitude(angles) => self.is_deflected(angles),
+            _ => true,
+        }
+    }
+
+    fn is_deflected(&self, angles: &Angles) -> bool {
+        [angles.roll, angles.pitch, angles.yaw].iter().any(|x| x.abs() > self.config.takeover)
+    }
+
+    /// Returns whether the command shall be applied
+    pub fn accept(&mut self, source: Source, command: &Command, now: Instant) -> bool {
+        if source == Source::Failsafe || matches!(command, Command::Stop) {
+            return true;
+        }
+        let pilot_disarm = source == Source::Pilot && matches!(command, Command::Armed(false));
+        let Some(rank) = self.rank(source) else {
+            return pilot_disarm;
+        };
+        let significant = self.is_significant(command);
+
+        let handback = Duration::from_millis(self.config.handback);
+        let active = self.active.filter(|(_, last)| now.duration_since(*last) < handback);
+        if let (Some((active, _)), None) = (self.active, active) {
+            log::info!("{:?} lost the control", active);
+            self.active = None;
+        }
+        if matches!(command, Command::Failsafe(_)) {
+            return active.is_some_and(|(active, _)| active == source);
+        }
+
+        let accepted = match active {
+            Some((active, _)) if active == source => true,
+            Some((active, _)) => significant && self.rank(active).is_none_or(|x| rank < x),
+            None => significant,
+        };
+        if accepted && significant {
+            if self.active() != Some(source) {
+                log::info!("Control handed over from {:?} to {:?}", self.active(), source);
+            }
+            self.active = Some((source, now));
+        }
+        accepted || pilot_disarm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FlightCommand;
+
+    fn flight(thrust: f32, roll: f32) -> Command {
+        Command::Flight(FlightCommand {
+            thrust,
+            angles: Angles {
+                roll,
+                ..Default::default()
+            },
+        })
+    }
+
+    #[test]
+    fn test_takeover() {
+        let mut arbiter = Arbiter::new(ArbiterConfig::default());
+        let now = Instant::now();
+
+        // The pilot remote streams neutral commands without taking the control
+        assert!(!arbiter.accept(Source::Pilot, &flight(0.0, 0.0), now));
+        assert!(arbiter.accept(Source::Plugin, &Command::Armed(true), now));
+        assert!(arbiter.accept(Source::Plugin, &flight(0.5, 0.0), now));
+        assert!(!arbiter.accept(Source::Pilot, &flight(0.0, 0.05), now));
+        assert_eq!(arbiter.active(), Some(Source::Plugin));
+
+        // Stick movement overrides the plugin
+        assert!(arbiter.accept(Source::Pilot, &flight(0.0, 0.3), now));
+        assert_eq!(arbiter.active(), Some(Source::Pilot));
+        assert!(!arbiter.accept(Source::Plugin, &flight(0.5, 0.0), now));
+        assert!(arbiter.accept(Source::Pilot, &flight(0.0, 0.0), now + Duration::from_millis(100)));
+
+        // The plugin gets the control back once the pilot let go of the sticks
+        let later = now + Duration::from_millis(2100);
+        assert!(arbiter.accept(Source::Plugin, &flight(0.5, 0.0), later));
+        assert_eq!(arbiter.active(), Some(Source::Plugin));
+    }
+
+    #[test]
+    fn test_pilot_disarm() {
+        let mut arbiter = Arbiter::new(ArbiterConfig {
+            priority: vec![Source::Plugin],
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(arbiter.accept(Source::Plugin, &Command::Armed(true), now));
+        // The pilot is not allowed to control the drone but can always disarm it
+        assert!(!arbiter.accept(Source::Pilot, &flight(0.5, 0.5), now));
+        assert!(!arbiter.accept(Source::Pilot, &Command::Armed(true), now));
+        assert!(arbiter.accept(Source::Pilot, &Command::Armed(false), now));
+        assert!(arbiter.accept(Source::Failsafe, &Command::Armed(false), now));
+        assert!(!arbiter.accept(Source::Network, &Command::Armed(true), now));
+        assert_eq!(arbiter.active(), Some(Source::Plugin));
+    }
+
+    #[test]
+    fn test_failsafe_request() {
+        let mut arbiter = Arbiter::new(ArbiterConfig::default());
+        let now = Instant::now();
+        let failsafe = Command::Failsafe("plugin violation".into());
+        assert!(!arbiter.accept(Source::Plugin, &failsafe, now));
+        assert!(arbiter.accept(Source::Plugin, &flight(0.5, 0.0), now));
+        assert!(arbiter.accept(Source::Plugin, &failsafe, now));
+
+        // Once the pilot took over the plugin cannot trigger a failsafe landing
+        assert!(arbiter.accept(Source::Pilot, &flight(0.0, 0.3), now));
+        assert!(!arbiter.accept(Source::Plugin, &failsafe, now));
+        assert!(arbiter.accept(Source::Failsafe, &failsafe, now));
+    }
+}
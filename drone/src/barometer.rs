@@ -0,0 +1,231 @@
+use anyhow::Result;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use serde::Deserialize;
+
+use crate::config;
+
+const BMP280_ID: u8 = 0xd0;
+const BMP280_CALIB: u8 = 0x88;
+const BMP280_CTRL_MEAS: u8 = 0xf4;
+const BMP280_CONFIG: u8 = 0xf5;
+const BMP280_PRESS_MSB: u8 = 0xf7;
+const BMP280_DEVICE_ID: u8 = 0x58;
+/// Temperature oversampling x2, pressure oversampling x16, normal mode
+const BMP280_MEASURE: u8 = 0x57;
+/// 0.5ms standby, IIR filter coefficient 16
+const BMP280_FILTER: u8 = 0x10;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    InvalidDevice(u8),
+}
+
+impl<E: std::fmt::Debug> std::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::I2c(x) => write!(f, "Barometer bus error: {:?}", x),
+            Self::InvalidDevice(x) => write!(f, "Invalid barometer id: {:#x}", x),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug> std::error::Error for Error<E> {}
+
+/// Barometer configuration stored in the parameter file under `[barometer]`.
+#[derive(Deserialize, Copy, Clone, Debug)]
+#[serde(default)]
+pub struct BarometerConfig {
+    /// Reads the barometer alongside the IMU
+    pub enabled: bool,
+    /// I2C address of the BMP280 (0x76 or 0x77)
+    pub address: u8,
+    /// Polling period in ms
+    pub period: u64,
+}
+
+impl Default for BarometerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: 0x76,
+            period: 40,
+        }
+    }
+}
+
+impl BarometerConfig {
+    /// Loads the barometer configuration from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("barometer")
+    }
+}
+
+/// Temperature compensated barometer measure
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pressure {
+    /// Pressure in Pa
+    pub pressure: f32,
+    /// Temperature in °C
+    pub temperature: f32,
+}
+
+/// Factory trimming parameters used for the temperature compensation
+#[derive(Debug, Default)]
+struct Trimming {
+    t: [f64; 3],
+    p: [f64; 9],
+}
+
+/// BMP280 barometer
+pub struct Bmp280<I2C> {
+    i2c: I2C,
+    address: u8,
+    trimming: Trimming,
+}
+
+impl<I2C, E> Bmp280<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Reads the trimming parameters then starts the continuous measurement.
+    pub fn new(mut i2c: I2C, address: u8) -> Result<Self, Error<E>> {
+        let mut id = [0];
+        i2c.write_read(address, &[BMP280_ID], &mut id).map_err(Error::I2c)?;
+        if id[0] != BMP280_DEVICE_ID {
+            return Err(Error::InvalidDevice(id[0]));
+        }
+
+        let mut calib = [0; 24];
+        i2c.write_read(address, &[BMP280_CALIB], &mut calib).map_err(Error::I2c)?;
+        let word = |i: usize| [calib[2 * i], calib[2 * i + 1]];
+        // dig_T1 and dig_P1 are unsigned, the other parameters are signed
+        let param = |i: usize| match i {
+            0 | 3 => f64::from(u16::from_le_bytes(word(i))),
+            _ => f64::from(i16::from_le_bytes(word(i))),
+        };
+        let trimming = Trimming {
+            t: [0, 1, 2].map(param),
+            p: [3, 4, 5, 6, 7, 8, 9, 10, 11].map(param),
+        };
+
+        i2c.write(address, &[BMP280_CONFIG, BMP280_FILTER]).map_err(Error::I2c)?;
+        i2c.write(address, &[BMP280_CTRL_MEAS, BMP280_MEASURE]).map_err(Error::I2c)?;
+
+        Ok(Self {
+            i2c,
+            address,
+            trimming,
+        })
+    }
+
+    /// Reads the last pressure and temperature measure.
+    pub fn read(&mut self) -> Result<Pressure, Error<E>> {
+        let mut data = [0; 6];
+        self.i2c.write_read(self.address, &[BMP280_PRESS_MSB], &mut data).map_err(Error::I2c)?;
+        let adc = |raw: &[u8]| (u32::from(raw[0]) << 12) | (u32::from(raw[1]) << 4) | (u32::from(raw[2]) >> 4);
+        Ok(self.trimming.compensate(adc(&data[3..6]), adc(&data[0..3])))
+    }
+}
+
+impl Trimming {
+    /// Floating point compensation from the BMP280 datasheet
+    fn compensate(&self, adc_t: u32, adc_p: u32) -> Pressure {
+        let [t1, t2, t3] = self.t;
+        let [p1, p2, p3, p4, p5, p6, p7, p8, p9] = self.p;
+        let adc_t = f64::from(adc_t);
+        let adc_p = f64::from(adc_p);
+
+        let var1 = (adc_t / 16384.0 - t1 / 1024.0) * t2;
+        let var2 = (adc_t / 131072.0 - t1 / 8192.0).powi(2) * t3;
+        let t_fine = var1 + var2;
+        let temperature = t_fine / 5120.0;
+
+        let var1 = t_fine / 2.0 - 64000.0;
+        let var2 = var1 * var1 * p6 / 32768.0 + var1 * p5 * 2.0;
+        let var2 = var2 / 4.0 + p4 * 65536.0;
+        let var1 = (p3 * var1 * var1 / 524288.0 + p2 * var1) / 524288.0;
+        let var1 = (1.0 + var1 / 32768.0) * p1;
+        let pressure = if var1 == 0.0 {
+            // Avoids a division by zero with invalid trimming parameters
+            0.0
+        } else {
+            let p = (1048576.0 - adc_p - var2 / 4096.0) * 6250.0 / var1;
+            p + (p9 * p * p / 2147483648.0 + p * p8 / 32768.0 + p7) / 16.0
+        };
+
+        Pressure {
+            pressure: pressure as f32,
+            temperature: temperature as f32,
+        }
+    }
+}
+
+/// Altitude in m above the reference pressure using the international barometric formula
+pub fn altitude(pressure: f32, reference: f32) -> f32 {
+    44330.0 * (1.0 - (pressure / reference).powf(1.0 / 5.255))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::eh0::i2c::{Mock as I2cMock, Transaction as I2cTransaction};
+
+    const ADDRESS: u8 = 0x76;
+
+    /// Trimming parameters given as example in the BMP280 datasheet
+    fn calibration() -> Vec<u8> {
+        let t: [u16; 1] = [27504];
+        let t_signed: [i16; 2] = [26435, -1000];
+        let p: [u16; 1] = [36477];
+        let p_signed: [i16; 8] = [-10685, 3024, 2855, 140, -7, 15500, -14600, 6000];
+        t.iter()
+            .flat_map(|x| x.to_le_bytes())
+            .chain(t_signed.iter().flat_map(|x| x.to_le_bytes()))
+            .chain(p.iter().flat_map(|x| x.to_le_bytes()))
+            .chain(p_signed.iter().flat_map(|x| x.to_le_bytes()))
+            .collect()
+    }
+
+    fn setup() -> Vec<I2cTransaction> {
+        vec![
+            I2cTransaction::write_read(ADDRESS, vec![BMP280_ID], vec![BMP280_DEVICE_ID]),
+            I2cTransaction::write_read(ADDRESS, vec![BMP280_CALIB], calibration()),
+            I2cTransaction::write(ADDRESS, vec![BMP280_CONFIG, BMP280_FILTER]),
+            I2cTransaction::write(ADDRESS, vec![BMP280_CTRL_MEAS, BMP280_MEASURE]),
+        ]
+    }
+
+    #[test]
+    fn test_compensation() {
+        // adc_P = 415148 and adc_T = 519888 from the datasheet example
+        let mut expectations = setup();
+        expectations.push(I2cTransaction::write_read(
+            ADDRESS,
+            vec![BMP280_PRESS_MSB],
+            vec![0x65, 0x5a, 0xc0, 0x7e, 0xed, 0x00],
+        ));
+        let mut i2c = I2cMock::new(&expectations);
+
+        let mut barometer = Bmp280::new(i2c.clone(), ADDRESS).unwrap();
+        let measure = barometer.read().unwrap();
+        assert!((measure.temperature - 25.08).abs() < 0.01, "temperature {}", measure.temperature);
+        assert!((measure.pressure - 100653.27).abs() < 0.5, "pressure {}", measure.pressure);
+        i2c.done();
+    }
+
+    #[test]
+    fn test_invalid_device() {
+        let mut i2c = I2cMock::new(&[I2cTransaction::write_read(ADDRESS, vec![BMP280_ID], vec![0x60])]);
+        assert!(matches!(Bmp280::new(i2c.clone(), ADDRESS), Err(Error::InvalidDevice(0x60))));
+        i2c.done();
+    }
+
+    #[test]
+    fn test_altitude() {
+        assert_eq!(altitude(101325.0, 101325.0), 0.0);
+        // Roughly 8.4m per hPa near sea level
+        let altitude = altitude(101325.0 - 100.0, 101325.0);
+        assert!((altitude - 8.4).abs() < 0.1, "altitude {}", altitude);
+    }
+}
@@ -0,0 +1,275 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+/// AM335x ADC reference voltage in V
+const ADC_REFERENCE: f32 = 1.8;
+/// AM335x ADC 12 bits range
+const ADC_RANGE: f32 = 4095.0;
+/// Time constant of the voltage low pass filter in s
+const VOLTAGE_FILTER: f64 = 1.0;
+
+/// Battery configuration stored in the parameter file under `[battery]`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BatteryConfig {
+    /// Monitors the battery
+    pub enabled: bool,
+    /// Number of cells in series
+    pub cell: u8,
+    /// Capacity in mAh
+    pub capacity: f32,
+    /// IIO raw voltage channel
+    pub voltage_path: PathBuf,
+    /// Ratio of the voltage divider in front of the ADC
+    pub voltage_divider: f32,
+    /// IIO raw current sensor channel if any
+    pub current_path: Option<PathBuf>,
+    /// Current sensor output at 0A in V
+    pub current_offset: f32,
+    /// Current sensor gain in A/V
+    pub current_scale: f32,
+    /// Cell voltage triggering a warning in V
+    pub low: f32,
+    /// Cell voltage triggering a forced landing in V
+    pub critical: f32,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cell: 3,
+            capacity: 2600.0,
+            voltage_path: PathBuf::from("/sys/bus/iio/devices/iio:device0/in_voltage0_raw"),
+            voltage_divider: 11.0,
+            current_path: None,
+            current_offset: 0.0,
+            current_scale: 1.0,
+            low: 3.5,
+            critical: 3.3,
+        }
+    }
+}
+
+impl BatteryConfig {
+    /// Loads the battery configuration from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("battery")
+    }
+}
+
+/// Voltage based thrust compensation stored in the parameter file under `[thrust_compensation]`.
+/// The motor speed is roughly proportional to the throttle times the battery voltage so the thrust command is scaled
+/// by the ratio between the nominal and the measured cell voltage.
+#[derive(Deserialize, Copy, Clone, Debug)]
+#[serde(default)]
+pub struct ThrustCompensation {
+    pub enabled: bool,
+    /// Cell voltage at which the hover throttle was tuned in V
+    pub nominal: f32,
+    /// Maximum scaling applied to the thrust command
+    pub max: f32,
+}
+
+impl Default for ThrustCompensation {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            nominal: 4.2,
+            max: 1.3,
+        }
+    }
+}
+
+impl ThrustCompensation {
+    /// Loads the thrust compensation from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("thrust_compensation")
+    }
+
+    /// Scaling factor to apply to the thrust command for the given cell voltage
+    pub fn scale(&self, cell_voltage: f32) -> f32 {
+        if self.enabled && cell_voltage > 0.0 {
+            (self.nominal / cell_voltage).min(self.max)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Battery charge level ordered from the safest to the most critical
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum BatteryLevel {
+    #[default]
+    Normal,
+    Low,
+    Critical,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct BatteryState {
+    /// Filtered pack voltage in V
+    pub voltage: f32,
+    /// Filtered cell voltage in V
+    pub cell_voltage: f32,
+    /// Current in A
+    pub current: f32,
+    /// Consumed charge in mAh
+    pub consumed: f32,
+    /// Remaining charge in %
+    pub remaining: f32,
+    pub level: BatteryLevel,
+}
+
+/// Battery monitor reading the BeagleBone IIO ADC
+pub struct BatteryMonitor {
+    config: BatteryConfig,
+    state: BatteryState,
+    initialized: bool,
+}
+
+impl BatteryMonitor {
+    pub fn new(config: BatteryConfig) -> Self {
+        Self {
+            config,
+            state: BatteryState {
+                remaining: 100.0,
+                ..Default::default()
+            },
+            initialized: false,
+        }
+    }
+
+    /// Last battery state
+    pub fn state(&self) -> BatteryState {
+        self.state
+    }
+
+    /// Reads the ADC and updates the battery state with the time elapsed since the previous update in s.
+    /// Returns the new level when it gets worse. The level never recovers because the voltage rises back as soon as
+    /// the load drops.
+    pub fn update(&mut self, dt: f64) -> Result<Option<BatteryLevel>> {
+        let voltage = read_adc(&self.config.voltage_path)? * self.config.voltage_divider;
+        if self.initialized {
+            let alpha = (dt / (VOLTAGE_FILTER + dt)) as f32;
+            self.state.voltage += alpha * (voltage - self.state.voltage);
+        } else {
+            self.state.voltage = voltage;
+            self.initialized = true;
+        }
+        self.state.cell_voltage = self.state.voltage / f32::from(self.config.cell.max(1));
+
+        if let Some(path) = self.config.current_path.as_ref() {
+            self.state.current = (read_adc(path)? - self.config.current_offset) * self.config.current_scale;
+            self.state.consumed += self.state.current * (dt / 3.6) as f32;
+            self.state.remaining = (100.0 * (1.0 - self.state.consumed / self.config.capacity)).clamp(0.0, 100.0);
+        }
+
+        let level = if self.state.cell_voltage <= self.config.critical {
+            BatteryLevel::Critical
+        } else if self.state.cell_voltage <= self.config.low {
+            BatteryLevel::Low
+        } else {
+            BatteryLevel::Normal
+        };
+        if level > self.state.level {
+            self.state.level = level;
+            Ok(Some(level))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Reads an IIO raw channel and converts it into V at the ADC input
+fn read_adc(path: &Path) -> Result<f32> {
+    let raw = fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let raw: f32 = raw.trim().parse().with_context(|| format!("Parsing {}", path.display()))?;
+    Ok(raw * ADC_REFERENCE / ADC_RANGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the raw ADC value matching the given voltage at the ADC input
+    fn write_adc(path: &Path, voltage: f32) {
+        fs::write(path, format!("{}\n", (voltage / ADC_REFERENCE * ADC_RANGE).round())).unwrap();
+    }
+
+    #[test]
+    fn test_battery_monitor() {
+        let directory = tempfile::tempdir().unwrap();
+        let voltage_path = directory.path().join("in_voltage0_raw");
+        let current_path = directory.path().join("in_voltage1_raw");
+        let config = BatteryConfig {
+            enabled: true,
+            voltage_path: voltage_path.clone(),
+            current_path: Some(current_path.clone()),
+            current_offset: 0.1,
+            current_scale: 20.0,
+            ..Default::default()
+        };
+        let mut battery = BatteryMonitor::new(config);
+
+        // 12.1V pack drawing 26A
+        write_adc(&voltage_path, 1.1);
+        write_adc(&current_path, 1.4);
+        assert_eq!(battery.update(0.1).unwrap(), None);
+        let state = battery.state();
+        assert!((state.voltage - 12.1).abs() < 0.01, "voltage {}", state.voltage);
+        assert!((state.cell_voltage - 4.033).abs() < 0.01, "cell {}", state.cell_voltage);
+        assert!((state.current - 26.0).abs() < 0.05, "current {}", state.current);
+
+        // One minute at 26A consumes 433mAh
+        for _ in 0..599 {
+            battery.update(0.1).unwrap();
+        }
+        let state = battery.state();
+        assert!((state.consumed - 433.3).abs() < 1.0, "consumed {}", state.consumed);
+        assert!((state.remaining - 83.3).abs() < 0.1, "remaining {}", state.remaining);
+
+        // Sagging down to 3.4V per cell then 3.2V per cell
+        write_adc(&voltage_path, 10.2 / 11.0);
+        let levels: Vec<_> = (0..50).filter_map(|_| battery.update(0.1).unwrap()).collect();
+        assert_eq!(levels, vec![BatteryLevel::Low]);
+        write_adc(&voltage_path, 9.6 / 11.0);
+        let levels: Vec<_> = (0..50).filter_map(|_| battery.update(0.1).unwrap()).collect();
+        assert_eq!(levels, vec![BatteryLevel::Critical]);
+
+        // The level does not recover when the load drops
+        write_adc(&voltage_path, 1.1);
+        let levels: Vec<_> = (0..50).filter_map(|_| battery.update(0.1).unwrap()).collect();
+        assert!(levels.is_empty());
+        assert_eq!(battery.state().level, BatteryLevel::Critical);
+    }
+
+    #[test]
+    fn test_thrust_compensation() {
+        let compensation = ThrustCompensation {
+            enabled: true,
+            ..Default::default()
+        };
+        assert_eq!(compensation.scale(4.2), 1.0);
+        assert!((compensation.scale(3.5) - 1.2).abs() < 1e-5);
+        assert_eq!(compensation.scale(3.0), 1.3);
+        // No measure yet
+        assert_eq!(compensation.scale(0.0), 1.0);
+        assert_eq!(ThrustCompensation::default().scale(3.5), 1.0);
+    }
+
+    #[test]
+    fn test_missing_adc() {
+        let mut battery = BatteryMonitor::new(BatteryConfig {
+            voltage_path: PathBuf::from("/nonexistent/in_voltage0_raw"),
+            ..Default::default()
+        });
+        assert!(battery.update(0.1).is_err());
+    }
+}
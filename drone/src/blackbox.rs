@@ -0,0 +1,352 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::config;
+use crate::health::IMU_PERIOD;
+use crate::types::{Angles, FlightCommand, Odometry};
+
+/// Blackbox configuration stored in the parameter file under `[blackbox]`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BlackboxConfig {
+    pub enabled: bool,
+    /// Recorded time before an event in s
+    pub duration: f32,
+    pub directory: PathBuf,
+    /// Number of dumps kept, the oldest are removed
+    pub keep: usize,
+    /// Attitude in degrees above which the drone is considered crashed
+    pub crash_tilt: f32,
+}
+
+impl Default for BlackboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            duration: 10.0,
+            directory: PathBuf::from("blackbox"),
+            keep: 20,
+            crash_tilt: 60.0,
+        }
+    }
+}
+
+impl BlackboxConfig {
+    /// Loads the blackbox configuration from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("blackbox")
+    }
+}
+
+/// State of a control cycle
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Frame {
+    /// Time since the flight controller start in s
+    pub time: f32,
+    pub armed: bool,
+    pub command: FlightCommand,
+    /// Attitude and rate measures
+    pub sensor: Odometry,
+    /// Altitude above the ground reference in m
+    pub altitude: f32,
+    pub position_pid: Angles,
+    pub velocity_pid: Angles,
+    /// PWM values of the motors
+    pub motors: [u32; 4],
+}
+
+const HEADER: &str = "time,armed,thrust_cmd,roll_cmd,pitch_cmd,yaw_cmd,roll,pitch,yaw,roll_rate,pitch_rate,yaw_rate,\
+                      thrust,altitude,roll_ppid,pitch_ppid,yaw_ppid,roll_vpid,pitch_vpid,yaw_vpid,motor0,motor1,motor2,\
+                      motor3";
+
+impl Frame {
+    fn write_csv<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        let (command, sensor) = (self.command, self.sensor);
+        write!(output, "{:.4},{}", self.time, u8::from(self.armed))?;
+        let values = [
+            command.thrust,
+            command.angles.roll,
+            command.angles.pitch,
+            command.angles.yaw,
+            sensor.attitude.roll,
+            sensor.attitude.pitch,
+            sensor.attitude.yaw,
+            sensor.rate.roll,
+            sensor.rate.pitch,
+            sensor.rate.yaw,
+            sensor.thrust,
+            self.altitude,
+            self.position_pid.roll,
+            self.position_pid.pitch,
+            self.position_pid.yaw,
+            self.velocity_pid.roll,
+            self.velocity_pid.pitch,
+            self.velocity_pid.yaw,
+        ];
+        for value in values {
+            write!(output, ",{}", value)?;
+        }
+        for motor in self.motors {
+            write!(output, ",{}", motor)?;
+        }
+        writeln!(output)
+    }
+}
+
+/// Event triggering a dump of the blackbox
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    Disarm,
+    Failsafe,
+    Crash,
+    /// Flight controller stopped or panicked
+    Shutdown,
+}
+
+/// Frames of a dump ordered from the oldest
+struct Dump {
+    trigger: Trigger,
+    frames: Vec<Frame>,
+    /// Index of the oldest frame
+    head: usize,
+}
+
+impl Dump {
+    fn write(&self, config: &BlackboxConfig) -> Result<PathBuf> {
+        fs::create_dir_all(&config.directory).context("Creating blackbox directory")?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let name = format!("blackbox-{}-{:?}.csv", timestamp, self.trigger).to_lowercase();
+        let path = config.directory.join(name);
+        let mut output = BufWriter::new(File::create(&path).context("Creating blackbox dump")?);
+        writeln!(output, "{}", HEADER)?;
+        for frame in self.frames[self.head..].iter().chain(&self.frames[..self.head]) {
+            frame.write_csv(&mut output)?;
+        }
+        output.into_inner()?.sync_all()?;
+        remove_old_dumps(&config.directory, config.keep)?;
+        Ok(path)
+    }
+}
+
+/// Removes the oldest dumps, the file names sort by date
+fn remove_old_dumps(directory: &Path, keep: usize) -> Result<()> {
+    let mut dumps: Vec<PathBuf> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("blackbox-")))
+        .collect();
+    dumps.sort();
+    for path in dumps.iter().rev().skip(keep) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Ring buffer of the last control cycles owned by the flight controller.
+///
+/// Recording never allocates nor locks: on an event the full buffer is swapped with a spare one and handed over to a
+/// writer thread which gives it back once written to disk. An event happening while the writer is busy is logged and
+/// not dumped.
+pub struct Blackbox {
+    config: BlackboxConfig,
+    start: Instant,
+    /// Number of frames in the ring
+    capacity: usize,
+    frames: Vec<Frame>,
+    /// Next frame to write, also the oldest once the buffer is full
+    head: usize,
+    /// Frames recorded since the last dump
+    pending: bool,
+    /// Crash already dumped, cleared on disarm
+    crashed: bool,
+    /// Buffer given back by the writer once a dump is written
+    spare_rx: Receiver<Vec<Frame>>,
+    spare: Option<Vec<Frame>>,
+    dump_tx: Sender<Dump>,
+}
+
+impl Blackbox {
+    pub fn new(config: BlackboxConfig) -> Self {
+        let capacity = (config.duration / IMU_PERIOD.as_secs_f32()).round().max(1.0) as usize;
+        let (dump_tx, dump_rx) = channel::<Dump>();
+        let (spare_tx, spare_rx) = channel();
+        spare_tx.send(Vec::with_capacity(capacity)).unwrap();
+        let writer_config = config.clone();
+        thread::Builder::new()
+            .name("blackbox".into())
+            .spawn(move || {
+                for mut dump in dump_rx.iter() {
+                    match dump.write(&writer_config) {
+                        Ok(path) => log::info!("Blackbox dumped to {}", path.display()),
+                        Err(err) => log::error!("Blackbox dump: {:#}", err),
+                    }
+                    dump.frames.clear();
+                    if spare_tx.send(dump.frames).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Spawning blackbox writer");
+        Self {
+            config,
+            start: Instant::now(),
+            capacity,
+            frames: Vec::with_capacity(capacity),
+            head: 0,
+            pending: false,
+            crashed: false,
+            spare_rx,
+            spare: None,
+            dump_tx,
+        }
+    }
+
+    /// Records a control cycle and returns whether the drone crashed, the crash is dumped
+    pub fn record(&mut self, mut frame: Frame) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+        frame.time = self.start.elapsed().as_secs_f32();
+        if self.frames.len() < self.capacity {
+            self.frames.push(frame);
+        } else {
+            self.frames[self.head] = frame;
+        }
+        self.head = (self.head + 1) % self.capacity;
+        self.pending = true;
+
+        let tilt = frame.sensor.attitude.roll.abs().max(frame.sensor.attitude.pitch.abs());
+        let crash = frame.armed && !self.crashed && tilt > self.config.crash_tilt.to_radians();
+        if crash {
+            self.crashed = true;
+            self.trigger(Trigger::Crash);
+        }
+        if !frame.armed {
+            self.crashed = false;
+        }
+        crash
+    }
+
+    /// Hands the recorded frames over to the writer
+    pub fn trigger(&mut self, trigger: Trigger) {
+        if !self.pending {
+            return;
+        }
+        let Some(spare) = self.spare.take().or_else(|| self.spare_rx.try_recv().ok()) else {
+            log::warn!("Blackbox busy, {:?} not dumped", trigger);
+            return;
+        };
+        let frames = std::mem::replace(&mut self.frames, spare);
+        let head = if frames.len() < self.capacity {
+            0
+        } else {
+            self.head
+        };
+        self.head = 0;
+        self.pending = false;
+        let _ = self.dump_tx.send(Dump {
+            trigger,
+            frames,
+            head,
+        });
+    }
+}
+
+impl Drop for Blackbox {
+    /// The writer thread may not outlive the process so the last frames are written from here
+    fn drop(&mut self) {
+        if self.pending {
+            let head = if self.frames.len() < self.capacity {
+                0
+            } else {
+                self.head
+            };
+            let dump = Dump {
+                trigger: Trigger::Shutdown,
+                frames: std::mem::take(&mut self.frames),
+                head,
+            };
+            if let Err(err) = dump.write(&self.config) {
+                log::error!("Blackbox dump: {:#}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn frame(armed: bool, roll: f32) -> Frame {
+        let mut frame = Frame {
+            armed,
+            ..Default::default()
+        };
+        frame.sensor.attitude.roll = roll;
+        frame
+    }
+
+    /// Waits until the writer gave the buffer back, i.e. the dump is written
+    fn wait_writer(blackbox: &mut Blackbox) {
+        blackbox.spare = Some(blackbox.spare_rx.recv_timeout(Duration::from_secs(1)).unwrap());
+    }
+
+    /// Dumps of the directory with their trigger, the dumps of the same millisecond are not ordered
+    fn dumps(directory: &Path) -> Vec<(String, String)> {
+        fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .map(|path| {
+                let trigger = path.file_stem().unwrap().to_string_lossy().rsplit('-').next().unwrap().to_string();
+                (trigger, fs::read_to_string(path).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_blackbox() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path().join("blackbox");
+        let mut blackbox = Blackbox::new(BlackboxConfig {
+            duration: 0.05,
+            directory: directory.clone(),
+            ..Default::default()
+        });
+        for i in 0..8 {
+            let mut frame = frame(true, 0.1);
+            frame.motors[0] = i;
+            assert!(!blackbox.record(frame));
+        }
+        // Ring of 5 frames
+        blackbox.trigger(Trigger::Disarm);
+        wait_writer(&mut blackbox);
+        let (trigger, dump) = &dumps(&directory)[0];
+        assert_eq!(trigger, "disarm");
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 6, "{}", dump);
+        assert_eq!(lines[0], HEADER);
+        let motor: Vec<u32> = lines[1..].iter().map(|line| line.split(',').nth(20).unwrap().parse().unwrap()).collect();
+        assert_eq!(motor, [3, 4, 5, 6, 7]);
+
+        // Nothing new to dump
+        blackbox.trigger(Trigger::Disarm);
+        // A crash is dumped once
+        assert!(blackbox.record(frame(true, 1.2)));
+        assert!(!blackbox.record(frame(true, 1.2)));
+        wait_writer(&mut blackbox);
+        let after_crash = dumps(&directory);
+        let (_, crash) = after_crash.iter().find(|(trigger, _)| trigger == "crash").unwrap();
+        assert_eq!(after_crash.len(), 2);
+        assert_eq!(crash.lines().count(), 2);
+        blackbox.record(frame(false, 0.0));
+        drop(blackbox);
+        assert_eq!(dumps(&directory).len(), 3);
+    }
+}
@@ -0,0 +1,434 @@
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use mio::{Interest, Token};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, store, CONFIG_FILE};
+use crate::polling::Poller;
+use crate::sensor::{quat_to_angles, Error, ImuSample, Sensors};
+use crate::types::Angles;
+
+/// Standard gravity in m/s²
+pub const GRAVITY: f32 = 9.80665;
+
+const CALIBRATION_KEY: &str = "imu_calibration";
+const MAG_CALIBRATION_KEY: &str = "mag_calibration";
+/// Duration of the magnetometer calibration
+const MAG_CALIBRATION_DURATION: Duration = Duration::from_secs(30);
+/// Longest time without a new magnetometer measure during its calibration
+const MAG_SAMPLE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Number of samples averaged for each calibration step (5s at 100Hz)
+const CALIBRATION_SAMPLES: usize = 500;
+/// Maximum gyroscope standard deviation (rad/s) for the IMU to be considered still
+const STILL_GYRO_STD: f32 = 0.02;
+/// Number of samples used by the health check (2s at 100Hz)
+const HEALTH_WINDOW: u32 = 200;
+/// Maximum residual gyroscope bias (rad/s) tolerated by the health check
+const HEALTH_GYRO_BIAS: f32 = 0.01;
+/// Maximum accelerometer norm error (m/s²) tolerated by the health check
+const HEALTH_ACCEL_NORM: f32 = 0.3;
+
+/// IMU calibration computed by the `calibrate-imu` procedure.
+/// It is stored in the parameter file under `[imu_calibration]`.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct ImuCalibration {
+    /// Gyroscope bias in rad/s
+    pub gyro_bias: [f32; 3],
+    /// Accelerometer offsets in m/s²
+    pub accel_offset: [f32; 3],
+    /// Accelerometer scale factors
+    pub accel_scale: [f32; 3],
+    /// Attitude measured when the drone rests on a level surface
+    pub level: Angles,
+}
+
+impl Default for ImuCalibration {
+    fn default() -> Self {
+        Self {
+            gyro_bias: [0.0; 3],
+            accel_offset: [0.0; 3],
+            accel_scale: [1.0; 3],
+            level: Angles::default(),
+        }
+    }
+}
+
+impl ImuCalibration {
+    /// Loads the calibration from the parameter file if any.
+    pub fn load() -> Result<Option<Self>> {
+        config::section(CALIBRATION_KEY)
+    }
+
+    /// Stores the calibration in the given parameter file keeping the other parameters.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        store(path, CALIBRATION_KEY, self)
+    }
+
+    /// Removes the bias from a raw gyroscope measure.
+    pub fn gyro(&self, raw: &[f32; 3]) -> [f32; 3] {
+        [raw[0] - self.gyro_bias[0], raw[1] - self.gyro_bias[1], raw[2] - self.gyro_bias[2]]
+    }
+
+    /// Applies offsets and scale factors to a raw accelerometer measure.
+    pub fn accel(&self, raw: &[f32; 3]) -> [f32; 3] {
+        [
+            (raw[0] - self.accel_offset[0]) * self.accel_scale[0],
+            (raw[1] - self.accel_offset[1]) * self.accel_scale[1],
+            (raw[2] - self.accel_offset[2]) * self.accel_scale[2],
+        ]
+    }
+
+    /// Removes the level trim from an attitude.
+    pub fn attitude(&self, attitude: Angles) -> Angles {
+        Angles {
+            roll: attitude.roll - self.level.roll,
+            pitch: attitude.pitch - self.level.pitch,
+            yaw: attitude.yaw,
+        }
+    }
+
+    /// Computes accelerometer offsets and scale factors from the mean measures of the six positions.
+    /// `up[i]` (resp. `down[i]`) is the mean measure while the IMU axis `i` points upward (resp. downward).
+    pub fn six_positions(&mut self, up: &[[f32; 3]; 3], down: &[[f32; 3]; 3]) -> Result<()> {
+        for axis in 0..3 {
+            let span = up[axis][axis] - down[axis][axis];
+            if span < GRAVITY {
+                bail!("Axis {} did not see gravity: up {} down {}", axis, up[axis][axis], down[axis][axis]);
+            }
+            self.accel_offset[axis] = (up[axis][axis] + down[axis][axis]) / 2.0;
+            self.accel_scale[axis] = 2.0 * GRAVITY / span;
+        }
+        Ok(())
+    }
+}
+
+/// Magnetometer hard and soft iron calibration computed by the `calibrate-mag` procedure.
+/// It is stored in the parameter file under `[mag_calibration]`.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct MagCalibration {
+    /// Hard iron offsets in µT
+    pub offset: [f32; 3],
+    /// Soft iron correction matrix
+    pub transform: [[f32; 3]; 3],
+}
+
+impl Default for MagCalibration {
+    fn default() -> Self {
+        Self {
+            offset: [0.0; 3],
+            transform: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+}
+
+impl MagCalibration {
+    /// Loads the calibration from the parameter file if any.
+    pub fn load() -> Result<Option<Self>> {
+        config::section(MAG_CALIBRATION_KEY)
+    }
+
+    /// Stores the calibration in the given parameter file keeping the other parameters.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        store(path, MAG_CALIBRATION_KEY, self)
+    }
+
+    /// Removes the hard and soft iron distortions from a raw magnetometer measure.
+    pub fn apply(&self, raw: &[f32; 3]) -> [f32; 3] {
+        let centered = [raw[0] - self.offset[0], raw[1] - self.offset[1], raw[2] - self.offset[2]];
+        self.transform.map(|row| row[0] * centered[0] + row[1] * centered[1] + row[2] * centered[2])
+    }
+
+    /// Fits an axis aligned ellipsoid on measures covering all the orientations.
+    pub fn fit(samples: &[[f32; 3]]) -> Result<Self> {
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for sample in samples {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(sample[axis]);
+                max[axis] = max[axis].max(sample[axis]);
+            }
+        }
+        let radius = [0, 1, 2].map(|axis| (max[axis] - min[axis]) / 2.0);
+        if radius.iter().any(|r| *r < f32::EPSILON) {
+            bail!("Magnetometer measures do not cover all the orientations");
+        }
+        let mean_radius = radius.iter().sum::<f32>() / 3.0;
+        let mut calibration = Self {
+            offset: [0, 1, 2].map(|axis| (max[axis] + min[axis]) / 2.0),
+            ..Default::default()
+        };
+        for (axis, (row, radius)) in calibration.transform.iter_mut().zip(radius).enumerate() {
+            row[axis] = mean_radius / radius;
+        }
+        Ok(calibration)
+    }
+}
+
+/// Watches live IMU data and warns when it departs from the calibration.
+/// The check only runs while the IMU is still, so flying does not trigger false warnings.
+#[derive(Default)]
+pub struct CalibrationHealth {
+    samples: u32,
+    gyro_sum: [f32; 3],
+    gyro_sq_sum: [f32; 3],
+    accel_norm_sum: f32,
+}
+
+impl CalibrationHealth {
+    /// Accumulates a calibrated sample and checks the window once full.
+    pub fn update(&mut self, gyro: &[f32; 3], accel: &[f32; 3]) {
+        for ((sum, sq_sum), x) in self.gyro_sum.iter_mut().zip(self.gyro_sq_sum.iter_mut()).zip(gyro) {
+            *sum += x;
+            *sq_sum += x * x;
+        }
+        self.accel_norm_sum += norm(accel);
+        self.samples += 1;
+
+        if self.samples == HEALTH_WINDOW {
+            self.check();
+            *self = Self::default();
+        }
+    }
+
+    fn check(&self) {
+        let n = self.samples as f32;
+        let mean = self.gyro_sum.map(|x| x / n);
+        let still = (0..3).all(|axis| self.gyro_sq_sum[axis] / n - mean[axis] * mean[axis] < STILL_GYRO_STD.powi(2));
+        if !still {
+            return;
+        }
+        if mean.iter().any(|x| x.abs() > HEALTH_GYRO_BIAS) {
+            log::warn!("Gyroscope bias departs from calibration: {:?} rad/s", mean);
+        }
+        let accel_norm = self.accel_norm_sum / n;
+        if (accel_norm - GRAVITY).abs() > HEALTH_ACCEL_NORM {
+            log::warn!("Accelerometer norm departs from calibration: {} m/s²", accel_norm);
+        }
+    }
+}
+
+fn norm(v: &[f32; 3]) -> f32 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+/// Interactive IMU calibration procedure.
+/// Computes the gyroscope bias, the accelerometer offsets and scales and the level trim then stores them in the
+/// parameter file.
+pub fn calibrate_imu() -> Result<()> {
+    const IMU: Token = Token(0);
+    let mut poller = Poller::new(8)?;
+    let mut sensors = Sensors::new()?;
+    poller.register(&sensors.imu_event(), IMU, Interest::READABLE)?;
+    let mut calibration = ImuCalibration::default();
+
+    prompt("Place the drone level and keep it still")?;
+    let samples = collect(&mut poller, &mut sensors)?;
+    let gyro = samples.iter().map(|sample| sample.gyro).collect::<Vec<_>>();
+    let (bias, std) = mean_std(&gyro);
+    if std.iter().any(|x| *x > STILL_GYRO_STD) {
+        bail!("The drone moved during gyroscope calibration: std {:?} rad/s", std);
+    }
+    calibration.gyro_bias = bias;
+    println!("Gyroscope bias: {:?} rad/s", bias);
+
+    // The drone positions are given in the drone frame while the accelerometer is calibrated on the IMU axes
+    let orientation = *sensors.orientation();
+    let mut up = [[0.0; 3]; 3];
+    let mut down = [[0.0; 3]; 3];
+    for (axis, name) in ["nose", "left side", "top"].iter().enumerate() {
+        let (imu_axis, same_direction) = orientation.imu_axis(axis);
+        prompt(&format!("Place the drone with its {} pointing upward", name))?;
+        let upward = mean_accel(&collect(&mut poller, &mut sensors)?);
+        prompt(&format!("Place the drone with its {} pointing downward", name))?;
+        let downward = mean_accel(&collect(&mut poller, &mut sensors)?);
+        (up[imu_axis], down[imu_axis]) = if same_direction {
+            (upward, downward)
+        } else {
+            (downward, upward)
+        };
+    }
+    calibration.six_positions(&up, &down)?;
+    println!("Accelerometer offsets: {:?} m/s², scales: {:?}", calibration.accel_offset, calibration.accel_scale);
+
+    prompt("Place the drone level on its landing gear")?;
+    let samples = collect(&mut poller, &mut sensors)?;
+    let n = samples.len() as f32;
+    let orientation = sensors.orientation();
+    let level = samples.iter().map(|sample| quat_to_angles(&orientation.attitude(&sample.quaternion))).fold(
+        Angles::default(),
+        |acc, x| Angles {
+            roll: acc.roll + x.roll / n,
+            pitch: acc.pitch + x.pitch / n,
+            yaw: 0.0,
+        },
+    );
+    calibration.level = level;
+    println!("Level trim: {:?}", level);
+
+    calibration.save(CONFIG_FILE)?;
+    println!("Calibration stored in {}", CONFIG_FILE);
+    Ok(())
+}
+
+/// Interactive magnetometer calibration procedure.
+/// Computes the hard and soft iron corrections then stores them in the parameter file.
+pub fn calibrate_mag() -> Result<()> {
+    const IMU: Token = Token(0);
+    let mut poller = Poller::new(8)?;
+    let mut sensors = Sensors::new()?;
+    if sensors.magnetometer_event().is_none() {
+        bail!("Magnetometer disabled, set `enabled = true` under [magnetometer]");
+    }
+    poller.register(&sensors.imu_event(), IMU, Interest::READABLE)?;
+
+    prompt(&format!("Rotate the drone slowly in all directions for {}s", MAG_CALIBRATION_DURATION.as_secs()))?;
+    sensors.clean_imu()?;
+    let start = Instant::now();
+    let mut last_sample = start;
+    let mut samples = Vec::new();
+    while start.elapsed() < MAG_CALIBRATION_DURATION {
+        if poller.poll(Some(Duration::from_secs(1)))?.is_empty() {
+            bail!("IMU event timed out");
+        }
+        match sensors.read_imu() {
+            Ok(_) => (),
+            Err(err) if matches!(err.downcast_ref::<Error>(), Some(Error::NotAvailable)) => continue,
+            Err(err) => return Err(err),
+        }
+        if let Some(sample) = sensors.read_raw_mag()? {
+            samples.push(sample);
+            last_sample = Instant::now();
+        } else if last_sample.elapsed() > MAG_SAMPLE_TIMEOUT {
+            bail!("No magnetometer measure for {:?}", MAG_SAMPLE_TIMEOUT);
+        }
+    }
+
+    let calibration = MagCalibration::fit(&samples)?;
+    println!("Hard iron offsets: {:?} µT, soft iron: {:?}", calibration.offset, calibration.transform);
+    calibration.save(CONFIG_FILE)?;
+    println!("Calibration stored in {}", CONFIG_FILE);
+    Ok(())
+}
+
+fn prompt(msg: &str) -> Result<()> {
+    print!("{} then press enter...", msg);
+    std::io::stdout().flush()?;
+    std::io::stdin().lock().read_line(&mut String::new())?;
+    Ok(())
+}
+
+fn collect(poller: &mut Poller, sensors: &mut Sensors) -> Result<Vec<ImuSample>> {
+    sensors.clean_imu()?;
+    let mut samples = Vec::with_capacity(CALIBRATION_SAMPLES);
+    while samples.len() < CALIBRATION_SAMPLES {
+        if poller.poll(Some(Duration::from_secs(1)))?.is_empty() {
+            bail!("IMU event timed out");
+        }
+        match sensors.read_imu() {
+            Ok(sample) => samples.push(sample),
+            Err(err) if matches!(err.downcast_ref::<Error>(), Some(Error::NotAvailable)) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(samples)
+}
+
+fn mean_accel(samples: &[ImuSample]) -> [f32; 3] {
+    mean_std(&samples.iter().map(|sample| sample.accel).collect::<Vec<_>>()).0
+}
+
+fn mean_std(samples: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    let n = samples.len() as f32;
+    let mut mean = [0.0; 3];
+    let mut std = [0.0; 3];
+    for sample in samples {
+        for axis in 0..3 {
+            mean[axis] += sample[axis] / n;
+        }
+    }
+    for sample in samples {
+        for axis in 0..3 {
+            std[axis] += (sample[axis] - mean[axis]).powi(2) / n;
+        }
+    }
+    (mean, std.map(f32::sqrt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_six_positions() {
+        let offset = [0.2, -0.1, 0.4];
+        let scale = [1.02, 0.98, 1.01];
+        let raw = |axis: usize, sign: f32| {
+            let mut measure = offset;
+            measure[axis] += sign * GRAVITY / scale[axis];
+            measure
+        };
+        let up = [raw(0, 1.0), raw(1, 1.0), raw(2, 1.0)];
+        let down = [raw(0, -1.0), raw(1, -1.0), raw(2, -1.0)];
+
+        let mut calibration = ImuCalibration::default();
+        calibration.six_positions(&up, &down).unwrap();
+        for axis in 0..3 {
+            assert!((calibration.accel_offset[axis] - offset[axis]).abs() < 1e-5);
+            assert!((calibration.accel_scale[axis] - scale[axis]).abs() < 1e-5);
+        }
+        let level = calibration.accel(&up[2]);
+        assert!(level[0].abs() < 1e-5 && level[1].abs() < 1e-5);
+        assert!((level[2] - GRAVITY).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_six_positions_without_gravity() {
+        let mut calibration = ImuCalibration::default();
+        assert!(calibration.six_positions(&[[0.0; 3]; 3], &[[0.0; 3]; 3]).is_err());
+    }
+
+    #[test]
+    fn test_mag_fit() {
+        let offset = [12.0, -7.0, 30.0];
+        let radius = [40.0, 50.0, 45.0];
+        let mut samples = Vec::new();
+        for i in 0..36 {
+            for j in 0..18 {
+                let (a, b) = ((i as f32 * 10.0).to_radians(), (j as f32 * 10.0 - 85.0).to_radians());
+                let unit = [a.cos() * b.cos(), a.sin() * b.cos(), b.sin()];
+                samples.push([0, 1, 2].map(|axis| offset[axis] + radius[axis] * unit[axis]));
+            }
+        }
+        let calibration = MagCalibration::fit(&samples).unwrap();
+        for sample in samples {
+            let corrected = calibration.apply(&sample);
+            let norm = corrected.iter().map(|x| x * x).sum::<f32>().sqrt();
+            assert!((norm - 45.0).abs() < 0.5, "norm {}", norm);
+        }
+    }
+
+    #[test]
+    fn test_save_keeps_parameters() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(CONFIG_FILE);
+        fs::write(&path, "debug_config = \"None\"\n").unwrap();
+        let calibration = ImuCalibration {
+            gyro_bias: [0.01, -0.02, 0.03],
+            level: Angles {
+                roll: 0.1,
+                pitch: -0.2,
+                yaw: 0.0,
+            },
+            ..Default::default()
+        };
+        calibration.save(&path).unwrap();
+
+        let parameters: toml::Table = fs::read_to_string(&path).unwrap().parse().unwrap();
+        assert_eq!(parameters["debug_config"].as_str(), Some("None"));
+        assert_eq!(parameters[CALIBRATION_KEY].clone().try_into::<ImuCalibration>().unwrap(), calibration);
+    }
+}
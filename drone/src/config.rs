@@ -1,8 +1,102 @@
-use config::Config;
-use std::sync::LazyLock;
+use anyhow::{Context, Result};
+use config::{Config, ConfigError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex, PoisonError};
+use toml_edit::ser::ValueSerializer;
+use toml_edit::{DocumentMut, Item, TableLike, Value};
 
-const CONFIG_FILE: &'static str = "drosix.toml";
+pub const CONFIG_FILE: &'static str = "drosix.toml";
 
 pub static DROSIX_CONFIG: LazyLock<Config> = LazyLock::new(|| {
     Config::builder().add_source(config::File::with_name(CONFIG_FILE)).build().expect("Loading Drosix config")
 });
+
+/// Serializes the writes of the parameter file between the server, the MAVLink endpoint and the calibrations
+static STORE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Loads a section of the parameter file, `None` when the section is missing and an error when it is invalid.
+pub fn section<T: DeserializeOwned>(key: &str) -> Result<Option<T>> {
+    match DROSIX_CONFIG.get(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(ConfigError::NotFound(_)) => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Invalid [{}] parameters", key)),
+    }
+}
+
+/// Loads a section of the parameter file, the defaults are used when the section is missing.
+pub fn load<T: DeserializeOwned + Default>(key: &str) -> Result<T> {
+    Ok(section(key)?.unwrap_or_default())
+}
+
+/// Stores a parameter in the given parameter file keeping the other parameters and the comments.
+///
+/// The key is the dotted path of the parameter, e.g. `roll_pid.kpa`, the missing tables are created.
+pub fn store<P: AsRef<Path>, T: Serialize>(path: P, key: &str, value: &T) -> Result<()> {
+    let _lock = STORE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut parameters = match fs::read_to_string(&path) {
+        Ok(content) => content.parse::<DocumentMut>().context("Parsing parameter file")?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => DocumentMut::new(),
+        Err(err) => return Err(err).context("Reading parameter file"),
+    };
+    let value = value.serialize(ValueSerializer::new()).with_context(|| format!("Serializing {}", key))?;
+
+    let (parents, name) = key.rsplit_once('.').map_or(("", key), |(parents, name)| (parents, name));
+    let mut table: &mut dyn TableLike = parameters.as_table_mut();
+    for parent in parents.split('.').filter(|x| !x.is_empty()) {
+        table = table
+            .entry(parent)
+            .or_insert(toml_edit::table())
+            .as_table_like_mut()
+            .with_context(|| format!("Parameter {} is not a table", parent))?;
+    }
+    update(table.entry(name).or_insert(Item::None), value);
+    fs::write(path, parameters.to_string()).context("Writing parameter file")
+}
+
+/// Replaces an item keeping the comments and the formatting of the values already present
+fn update(item: &mut Item, value: Value) {
+    match (item, value) {
+        (Item::Table(table), Value::InlineTable(values)) => {
+            table.retain(|key, _| values.contains_key(key));
+            for (key, value) in values {
+                update(table.entry(&key).or_insert(Item::None), value);
+            }
+        },
+        (Item::Value(old), mut value) => {
+            *value.decor_mut() = old.decor().clone();
+            *old = value;
+        },
+        (item, Value::InlineTable(values)) => *item = Item::Table(values.into_table()),
+        (item, value) => *item = Item::Value(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(CONFIG_FILE);
+        fs::write(&path, "# Parameters\ndebug_config = \"None\" # none\n\n[roll_pid]\nkpa = 1.0 # gain\nkpr = 2.0\n")
+            .unwrap();
+
+        store(&path, "roll_pid.kpa", &1.5).unwrap();
+        store(&path, "debug_config", &"Rate").unwrap();
+        store(&path, "battery", &[("cells", 3)].into_iter().collect::<std::collections::BTreeMap<_, _>>()).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Parameters\ndebug_config = \"Rate\" # none\n\n[roll_pid]\nkpa = 1.5 # gain\nkpr = 2.0\n\n[battery]\ncells = \
+             3\n"
+        );
+
+        // Only a missing file starts from scratch
+        assert!(store(directory.path(), "debug_config", &"None").is_err());
+        store(directory.path().join("new.toml"), "debug_config", &"None").unwrap();
+    }
+}
@@ -3,8 +3,9 @@ use prusst::util::VolatileCell;
 use prusst::{Channel, Evtout, EvtoutIrq, Host, Intc, IntcConfig, MemSegment, PruLoader, Sysevt};
 
 use std::fs::File;
+use std::mem::{offset_of, size_of};
 
-use crate::types::{Angles, DebugConfig, Odometry, PidConfig};
+use crate::types::{Angles, DebugConfig, Odometry, PidAxis, PidConfig};
 
 const MOTORS_FW: &str = "/lib/firmware/motor.bin";
 const PID_FW: &str = "/lib/firmware/controller.bin";
@@ -14,6 +15,8 @@ const PID_FW: &str = "/lib/firmware/controller.bin";
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct SharedMem {
+    /// Layout hash written by the PID controller firmware at start, see [`LAYOUT_HASH`]
+    pub layout_hash: VolatileCell<u32>,
     /// Period in ms
     pub period: VolatileCell<u32>,
     /// PID parameters for roll
@@ -43,6 +46,7 @@ pub struct SharedMem {
 impl Default for SharedMem {
     fn default() -> Self {
         SharedMem {
+            layout_hash: VolatileCell::new(0),
             period: VolatileCell::new(10),
             pid_roll: VolatileCell::new(PidConfig::default()),
             pid_pitch: VolatileCell::new(PidConfig::default()),
@@ -59,6 +63,24 @@ impl Default for SharedMem {
     }
 }
 
+include!(concat!(env!("OUT_DIR"), "/shared_memory_layout.rs"));
+
+// `dump_raw` reads both PID outputs at once
+const _: () = assert!(offset_of!(SharedMem, v_pid) == offset_of!(SharedMem, p_pid) + size_of::<VolatileCell<Angles>>());
+
+/// Checks the layout hash written by the firmware against the one of this build
+fn check_layout_hash(hash: u32) -> Result<()> {
+    match hash {
+        LAYOUT_HASH => Ok(()),
+        0 => bail!("The PID controller firmware did not report its shared memory layout, rebuild the PRU firmwares"),
+        _ => bail!(
+            "PRU firmwares built for the shared memory layout {:08x} instead of {:08x}, rebuild the PRU firmwares",
+            hash,
+            LAYOUT_HASH
+        ),
+    }
+}
+
 const EVENT_MAP: [(Sysevt, Channel); 9] = [
     (Sysevt::S17, Channel::C0), /* CONTROLLER_STOP */
     (Sysevt::S18, Channel::C0), /* PID_NEW_DATA */
@@ -120,6 +142,16 @@ impl<'a> PruController<'a> {
         self.shared_mem.pid_thrust.set(thrust);
     }
 
+    /// Updates the PID of a single axis.
+    /// The PRU reloads the PID parameters when arming.
+    pub fn set_axis_pid(&mut self, axis: PidAxis, pid: PidConfig) {
+        match axis {
+            PidAxis::Roll => self.shared_mem.pid_roll.set(pid),
+            PidAxis::Pitch => self.shared_mem.pid_pitch.set(pid),
+            PidAxis::Yaw => self.shared_mem.pid_yaw.set(pid),
+        }
+    }
+
     /// Starts the PRU (load and launch firmwares).
     pub fn start(pru0: &mut PruLoader, pru1: &mut PruLoader) -> Result<()> {
         // Load PRU code
@@ -218,17 +250,33 @@ impl<'a> PruController<'a> {
         unsafe {
             std::slice::from_raw_parts(
                 (&self.shared_mem.p_pid as *const VolatileCell<Angles>) as *const u8,
-                std::mem::size_of::<VolatileCell<Angles>>() + std::mem::size_of::<VolatileCell<Angles>>(),
+                size_of::<VolatileCell<Angles>>() + size_of::<VolatileCell<Angles>>(),
             )
         }
     }
 
+    /// Checks that the running firmwares were built with the same shared memory layout
+    pub fn check_layout(&self) -> Result<()> {
+        check_layout_hash(self.shared_mem.layout_hash.get())
+    }
+
     pub fn read_pid(&self) -> (Angles, Angles) {
         (self.shared_mem.p_pid.get(), self.shared_mem.v_pid.get())
     }
 
+    /// PWM values of the motors computed by the last PID loop
+    pub fn read_motors(&self) -> [u32; 4] {
+        self.shared_mem.pid_output.each_ref().map(VolatileCell::get)
+    }
+
+    /// Number of PRU cycles taken by the last PID loop
     pub fn read_cycle(&self) -> u32 {
-        dbg!(self.shared_mem.cycle.get())
+        self.shared_mem.cycle.get()
+    }
+
+    /// Number of PRU stall cycles during the last PID loop
+    pub fn read_stall(&self) -> u32 {
+        self.shared_mem.stall.get()
     }
 }
 
@@ -246,6 +294,13 @@ mod tests {
     use prusst::Pruss;
     use std::time::Duration;
 
+    #[test]
+    fn test_layout_hash() {
+        assert!(check_layout_hash(LAYOUT_HASH).is_ok());
+        assert!(check_layout_hash(0).is_err());
+        assert!(check_layout_hash(LAYOUT_HASH ^ 1).is_err());
+    }
+
     #[test]
     fn test_controller() {
         // Setup interrupt infrastructure
@@ -0,0 +1,71 @@
+# Type stubs of the `drosix` module embedded by `plugin.rs`, keep both in sync.
+# Point the IDE or mypy to this directory, e.g. `MYPYPATH=drone/src`.
+
+from typing import Callable, Optional, Tuple, Type
+
+# Thrust, roll, pitch and yaw like `Command.Position`
+Attitude = Tuple[float, float, float, float]
+
+class Command:
+    class Raw(Command):
+        """Motor values bypassing the PID, only accepted when disarmed"""
+        def __init__(self, _0: int, _1: int, _2: int, _3: int) -> None: ...
+    class Position(Command):
+        """Thrust in [0, 1], roll, pitch and yaw in [-1, 1] of the full command"""
+        def __init__(self, _0: float, _1: float, _2: float, _3: float) -> None: ...
+    class Velocity(Command):
+        """Not implemented"""
+        def __init__(self, _0: float, _1: float, _2: float, _3: float) -> None: ...
+
+class Comm: ...
+
+class Plugin:
+    def __init__(self, comm: Comm) -> None: ...
+    def on_start(self) -> None:
+        """Called once before the first tick, runs the deprecated `start` entry point of the older plugins"""
+    def on_tick(self, dt: float) -> None:
+        """Called periodically with the time elapsed since the previous tick in s"""
+    def on_armed(self) -> None: ...
+    def on_disarmed(self) -> None: ...
+    def on_stop(self) -> None:
+        """Called once when the plugin or the drone stops"""
+    def stop(self) -> None:
+        """Asks the scheduler to stop the plugin after the current hook"""
+    @property
+    def stopping(self) -> bool:
+        """Whether the plugin is stopping, long running hooks shall return when it is set"""
+    def log(self, msg: str) -> None: ...
+    def send(self, cmd: Command) -> None:
+        """Raises `CommandRejected` when the command is outside of the safety envelope"""
+
+class Telemetry:
+    """Telemetry snapshot, angles in rad"""
+    armed: bool
+    landing: bool
+    control: Optional[str]
+    """Source in control: pilot, network, plugin or None"""
+    thrust: float
+    roll: float
+    pitch: float
+    yaw: float
+    altitude: float
+    battery: Optional[float]
+    """Battery remaining charge in %, None without battery monitoring"""
+
+class MissionAborted(Exception):
+    """Mission step cancelled by the drone"""
+
+class CommandRejected(MissionAborted):
+    """Command outside of the plugin safety envelope"""
+
+def register(cls: Type[Plugin]) -> None:
+    """Sets the plugin class instantiated by the drone"""
+
+def arm(timeout: float = 1.0) -> None: ...
+def disarm(timeout: float = 1.0) -> None: ...
+def takeoff(thrust: float, duration: float) -> None: ...
+def hold(attitude: Attitude, seconds: float) -> None: ...
+def ramp(from_: Attitude, to: Attitude, seconds: float, /) -> None: ...
+def land(duration: float = 3.0, timeout: float = 1.0) -> None: ...
+def wait_until(predicate: Callable[[Telemetry], bool], timeout: float) -> None: ...
+def telemetry() -> Telemetry: ...
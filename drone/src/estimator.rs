@@ -0,0 +1,265 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::config;
+use crate::orientation::{quat_from_euler, quat_mul};
+
+/// Attitude estimator selection stored in the parameter file under `[estimator]`.
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum EstimatorConfig {
+    /// Use the quaternion computed by the MPU9250 DMP
+    #[default]
+    Dmp,
+    /// Mahony complementary filter
+    Mahony {
+        /// Proportional gain
+        kp: f64,
+        /// Integral gain used to estimate the gyroscope bias
+        ki: f64,
+    },
+    /// Madgwick gradient descent filter
+    Madgwick {
+        /// Gradient descent step gain in rad/s
+        beta: f64,
+    },
+}
+
+impl EstimatorConfig {
+    /// Loads the estimator selection from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("estimator")
+    }
+
+    /// Creates the host-side estimator if one is selected.
+    pub fn build(self) -> Option<Box<dyn Estimator>> {
+        match self {
+            Self::Dmp => None,
+            Self::Mahony {
+                kp,
+                ki,
+            } => Some(Box::new(Mahony::new(kp, ki))),
+            Self::Madgwick {
+                beta,
+            } => Some(Box::new(Madgwick::new(beta))),
+        }
+    }
+}
+
+/// Fuses gyroscope, accelerometer and optionally magnetometer measures into an attitude quaternion.
+/// All the measures are expressed in the drone frame.
+pub trait Estimator {
+    /// Updates the attitude and returns the new quaternion `[w, x, y, z]`.
+    /// - `gyro` angular rate in rad/s
+    /// - `accel` acceleration in any unit, the direction only is used
+    /// - `mag` magnetic field in any unit, the direction only is used
+    /// - `dt` elapsed time since the previous update in s
+    fn update(&mut self, gyro: &[f32; 3], accel: &[f32; 3], mag: Option<&[f32; 3]>, dt: f64) -> [f64; 4];
+}
+
+/// Mahony complementary filter: the gyroscope rate is corrected by a PI controller on the error between the
+/// measured and the estimated reference directions.
+pub struct Mahony {
+    q: Option<[f64; 4]>,
+    kp: f64,
+    ki: f64,
+    integral: [f64; 3],
+}
+
+impl Mahony {
+    pub fn new(kp: f64, ki: f64) -> Self {
+        Self {
+            q: None,
+            kp,
+            ki,
+            integral: [0.0; 3],
+        }
+    }
+}
+
+impl Estimator for Mahony {
+    fn update(&mut self, gyro: &[f32; 3], accel: &[f32; 3], mag: Option<&[f32; 3]>, dt: f64) -> [f64; 4] {
+        let q = *self.q.get_or_insert_with(|| initial_attitude(accel));
+        let mut rate = gyro.map(f64::from);
+        if let Some(error) = reference_error(&q, accel, mag) {
+            for ((rate, integral), error) in rate.iter_mut().zip(self.integral.iter_mut()).zip(error) {
+                *integral += self.ki * error * dt;
+                *rate += self.kp * error + *integral;
+            }
+        }
+        let q = integrate(&q, &rate, dt);
+        self.q = Some(q);
+        q
+    }
+}
+
+/// Madgwick filter: the gyroscope rate is corrected by a normalized gradient descent step toward the measured
+/// reference directions. The step is computed in the drone frame.
+pub struct Madgwick {
+    q: Option<[f64; 4]>,
+    beta: f64,
+}
+
+impl Madgwick {
+    pub fn new(beta: f64) -> Self {
+        Self {
+            q: None,
+            beta,
+        }
+    }
+}
+
+impl Estimator for Madgwick {
+    fn update(&mut self, gyro: &[f32; 3], accel: &[f32; 3], mag: Option<&[f32; 3]>, dt: f64) -> [f64; 4] {
+        let q = *self.q.get_or_insert_with(|| initial_attitude(accel));
+        let mut rate = gyro.map(f64::from);
+        if let Some(error) = reference_error(&q, accel, mag).and_then(|error| normalize(&error)) {
+            for (rate, error) in rate.iter_mut().zip(error) {
+                *rate += 2.0 * self.beta * error;
+            }
+        }
+        let q = integrate(&q, &rate, dt);
+        self.q = Some(q);
+        q
+    }
+}
+
+/// Attitude matching the gravity direction with a null yaw
+fn initial_attitude(accel: &[f32; 3]) -> [f64; 4] {
+    let [ax, ay, az] = accel.map(f64::from);
+    let roll = ay.atan2(az);
+    let pitch = (-ax).atan2((ay * ay + az * az).sqrt());
+    quat_from_euler(roll, pitch, 0.0)
+}
+
+/// Sum of the cross products between the measured and the estimated reference directions
+fn reference_error(q: &[f64; 4], accel: &[f32; 3], mag: Option<&[f32; 3]>) -> Option<[f64; 3]> {
+    let accel = normalize(&accel.map(f64::from))?;
+    let up = to_body(q, &[0.0, 0.0, 1.0]);
+    let mut error = cross(&accel, &up);
+
+    if let Some(mag) = mag.and_then(|mag| normalize(&mag.map(f64::from))) {
+        // Earth magnetic field flattened on the north axis
+        let h = to_earth(q, &mag);
+        let north = to_body(q, &[(h[0] * h[0] + h[1] * h[1]).sqrt(), 0.0, h[2]]);
+        let mag_error = cross(&mag, &north);
+        for (error, mag_error) in error.iter_mut().zip(mag_error) {
+            *error += mag_error;
+        }
+    }
+    Some(error)
+}
+
+fn integrate(q: &[f64; 4], rate: &[f64; 3], dt: f64) -> [f64; 4] {
+    let dq = quat_mul(q, &[0.0, rate[0], rate[1], rate[2]]);
+    let q = [0, 1, 2, 3].map(|i| q[i] + 0.5 * dq[i] * dt);
+    let norm = q.iter().map(|x| x * x).sum::<f64>().sqrt();
+    q.map(|x| x / norm)
+}
+
+/// Expresses an earth frame vector in the drone frame
+fn to_body(q: &[f64; 4], v: &[f64; 3]) -> [f64; 3] {
+    let [w, x, y, z] = *q;
+    to_earth(&[w, -x, -y, -z], v)
+}
+
+/// Expresses a drone frame vector in the earth frame
+fn to_earth(q: &[f64; 4], v: &[f64; 3]) -> [f64; 3] {
+    let r = quat_mul(&quat_mul(q, &[0.0, v[0], v[1], v[2]]), &[q[0], -q[1], -q[2], -q[3]]);
+    [r[1], r[2], r[3]]
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn normalize(v: &[f64; 3]) -> Option<[f64; 3]> {
+    let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    (norm > f64::EPSILON).then(|| v.map(|x| x / norm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::quat_to_angles;
+
+    const DT: f64 = 0.01;
+    /// Earth magnetic field with a 60° inclination
+    const FIELD: [f64; 3] = [0.5, 0.0, -0.866];
+
+    fn estimators() -> Vec<Box<dyn Estimator>> {
+        vec![Box::new(Mahony::new(1.0, 0.1)), Box::new(Madgwick::new(0.1))]
+    }
+
+    /// Simulated accelerometer and magnetometer measures for the given attitude
+    fn measures(q: &[f64; 4]) -> ([f32; 3], [f32; 3]) {
+        let accel = to_body(q, &[0.0, 0.0, 9.81]).map(|x| x as f32);
+        let mag = to_body(q, &FIELD).map(|x| x as f32);
+        (accel, mag)
+    }
+
+    fn run(estimator: &mut dyn Estimator, q: &[f64; 4], gyro: [f32; 3], mag: bool, steps: usize) -> [f32; 3] {
+        let (accel, field) = measures(q);
+        let mut attitude = [1.0, 0.0, 0.0, 0.0];
+        for _ in 0..steps {
+            attitude = estimator.update(&gyro, &accel, mag.then_some(&field), DT);
+        }
+        let angles = quat_to_angles(&attitude);
+        [angles.roll, angles.pitch, angles.yaw]
+    }
+
+    #[test]
+    fn test_converges_to_tilt() {
+        for mut estimator in estimators() {
+            run(estimator.as_mut(), &[1.0, 0.0, 0.0, 0.0], [0.0; 3], false, 10);
+            let [roll, pitch, _] = run(estimator.as_mut(), &quat_from_euler(0.3, -0.2, 0.0), [0.0; 3], false, 3000);
+            assert!((roll - 0.3).abs() < 0.01, "roll {}", roll);
+            assert!((pitch + 0.2).abs() < 0.01, "pitch {}", pitch);
+        }
+    }
+
+    #[test]
+    fn test_integrates_gyro() {
+        for mut estimator in estimators() {
+            let mut attitude = [1.0, 0.0, 0.0, 0.0];
+            for step in 0..100 {
+                let yaw = 0.5 * step as f64 * DT;
+                let (accel, _) = measures(&quat_from_euler(0.0, 0.0, yaw));
+                attitude = estimator.update(&[0.0, 0.0, 0.5], &accel, None, DT);
+            }
+            let yaw = quat_to_angles(&attitude).yaw;
+            assert!((yaw - 0.5).abs() < 0.01, "yaw {}", yaw);
+        }
+    }
+
+    #[test]
+    fn test_rejects_gyro_bias() {
+        let mut estimator = Mahony::new(1.0, 0.1);
+        let [roll, pitch, _] = run(&mut estimator, &[1.0, 0.0, 0.0, 0.0], [0.02, -0.01, 0.0], false, 6000);
+        assert!(roll.abs() < 0.005, "roll {}", roll);
+        assert!(pitch.abs() < 0.005, "pitch {}", pitch);
+    }
+
+    #[test]
+    fn test_magnetometer_heading() {
+        for mut estimator in estimators() {
+            let [roll, pitch, yaw] = run(estimator.as_mut(), &quat_from_euler(0.1, 0.2, 0.7), [0.0; 3], true, 20000);
+            assert!((roll - 0.1).abs() < 0.01, "roll {}", roll);
+            assert!((pitch - 0.2).abs() < 0.01, "pitch {}", pitch);
+            assert!((yaw - 0.7).abs() < 0.01, "yaw {}", yaw);
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        let config: EstimatorConfig = toml::from_str("kind = \"mahony\"\nkp = 2.0\nki = 0.1").unwrap();
+        assert_eq!(
+            config,
+            EstimatorConfig::Mahony {
+                kp: 2.0,
+                ki: 0.1
+            }
+        );
+        assert!(EstimatorConfig::Dmp.build().is_none());
+    }
+}
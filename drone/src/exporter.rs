@@ -0,0 +1,247 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::config;
+use crate::server::read_request;
+use crate::stats::{Histogram, BUCKETS};
+use crate::types::{Telemetry, TelemetryHub};
+
+/// Period at which the exporter checks for a scrape and for the stop flag
+const POLL_PERIOD: Duration = Duration::from_millis(20);
+
+/// Metrics exporter configuration stored in the parameter file under `[exporter]`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ExporterConfig {
+    pub enabled: bool,
+    pub address: String,
+}
+
+impl Default for ExporterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "0.0.0.0:9464".into(),
+        }
+    }
+}
+
+impl ExporterConfig {
+    /// Loads the exporter configuration from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("exporter")
+    }
+}
+
+/// Metric families in the Prometheus text format
+#[derive(Default)]
+struct Metrics(String);
+
+impl Metrics {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP drosix_{} {}\n# TYPE drosix_{} {}", name, help, name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &str, value: f64) {
+        if labels.is_empty() {
+            let _ = writeln!(self.0, "drosix_{} {}", name, value);
+        } else {
+            let _ = writeln!(self.0, "drosix_{}{{{}}} {}", name, labels, value);
+        }
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.family(name, "gauge", help);
+        self.sample(name, "", value);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, value: f64) {
+        let name = format!("{}_total", name);
+        self.family(&name, "counter", help);
+        self.sample(&name, "", value);
+    }
+
+    /// Histogram of durations in µs exported in s
+    fn histogram(&mut self, name: &str, labels: &str, histogram: &Histogram) {
+        let mut count = 0;
+        for (bound, samples) in BUCKETS.iter().zip(histogram.buckets) {
+            count += samples;
+            self.sample(
+                &format!("{}_bucket", name),
+                &format!("{},le=\"{}\"", labels, *bound as f64 / 1e6),
+                count.into(),
+            );
+        }
+        self.sample(&format!("{}_bucket", name), &format!("{},le=\"+Inf\"", labels), histogram.count.into());
+        self.sample(&format!("{}_sum", name), labels, histogram.sum as f64 / 1e6);
+        self.sample(&format!("{}_count", name), labels, histogram.count.into());
+    }
+}
+
+/// Renders the last telemetry, including the loop statistics accumulated since the start, and the log counters
+fn render(telemetry: &Telemetry, dropped: (u64, u64)) -> String {
+    let loop_stats = &telemetry.total_stats;
+    let mut metrics = Metrics::default();
+    metrics.gauge("armed", "Whether the motors are armed", u8::from(telemetry.armed).into());
+
+    metrics.family("loop_duration_seconds", "histogram", "Flight controller loop timings");
+    let stages = [
+        ("imu_to_pru", &loop_stats.imu_to_pru),
+        ("pru_cycle", &loop_stats.pru_cycle),
+        ("pru_stall", &loop_stats.pru_stall),
+        ("command_lag", &loop_stats.command_lag),
+    ];
+    for (stage, histogram) in stages {
+        metrics.histogram("loop_duration_seconds", &format!("stage=\"{}\"", stage), histogram);
+    }
+    metrics.counter("loop_overruns", "Loop iterations longer than the IMU period", loop_stats.overruns.into());
+
+    let imu = &telemetry.imu;
+    metrics.counter("imu_samples", "Valid IMU samples", imu.samples as f64);
+    metrics.counter("imu_missed", "Poll timeouts without IMU interrupt", imu.missed as f64);
+    metrics.counter("imu_not_ready", "IMU interrupts without data", imu.not_ready as f64);
+    metrics.counter("imu_errors", "IMU read errors", imu.errors as f64);
+    metrics.counter("imu_fifo_resets", "IMU FIFO resets", imu.fifo_resets as f64);
+    metrics.counter("imu_reinits", "IMU re-initializations", imu.reinits as f64);
+    metrics.gauge("imu_jitter_seconds", "Average deviation from the IMU period", f64::from(imu.jitter) / 1e3);
+    metrics.gauge("imu_max_period_seconds", "Longest IMU sample period", f64::from(imu.max_period) / 1e3);
+
+    if let Some(battery) = &telemetry.battery {
+        metrics.gauge("battery_voltage_volts", "Filtered pack voltage", battery.voltage.into());
+        metrics.gauge("battery_cell_voltage_volts", "Filtered cell voltage", battery.cell_voltage.into());
+        metrics.gauge("battery_current_amperes", "Battery current", battery.current.into());
+        metrics.gauge("battery_consumed_mah", "Consumed charge in mAh", battery.consumed.into());
+        metrics.gauge("battery_remaining_ratio", "Remaining charge", f64::from(battery.remaining) / 100.0);
+        metrics.gauge("battery_level", "Battery level: 0 normal, 1 low, 2 critical", (battery.level as u8).into());
+    }
+
+    metrics.counter("log_dropped_records", "Log records dropped because the log sink lagged", dropped.0 as f64);
+    metrics.counter("log_dropped_measures", "Measures dropped because the log sink lagged", dropped.1 as f64);
+
+    #[cfg(feature = "profiling")]
+    {
+        let profiles = crate::log::profiles();
+        metrics.family("function_seconds", "summary", "Profiled function timings");
+        for (function, profile) in profiles.iter() {
+            let labels = format!("function=\"{}\"", function);
+            metrics.sample("function_seconds_sum", &labels, profile.sum);
+            metrics.sample("function_seconds_count", &labels, profile.count as f64);
+        }
+        metrics.family("function_max_seconds", "gauge", "Longest call of the last profiling period");
+        for (function, profile) in profiles.iter() {
+            metrics.sample("function_max_seconds", &format!("function=\"{}\"", function), profile.max);
+        }
+    }
+    metrics.0
+}
+
+/// HTTP endpoint exporting the flight controller metrics in the Prometheus text format on `GET /metrics`
+pub struct Exporter {
+    listener: TcpListener,
+    telemetry: TelemetryHub,
+}
+
+impl Exporter {
+    pub fn bind(address: &str, telemetry: TelemetryHub) -> Result<Self> {
+        let listener = TcpListener::bind(address).with_context(|| format!("Binding exporter to {}", address))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            telemetry,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves the scrapes one at a time until the stop flag is raised
+    pub fn run(&mut self, stop: Arc<AtomicBool>) {
+        log::info!("Metrics exporter listening on {:?}", self.listener.local_addr());
+        while !stop.load(Ordering::Relaxed) {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    if let Err(err) = self.handle_connection(stream, &self.telemetry.latest().1) {
+                        log::warn!("Scrape from {}: {:#}", peer, err);
+                    }
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_PERIOD),
+                Err(err) => log::warn!("Accepting connection: {}", err),
+            }
+        }
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream, telemetry: &Telemetry) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let request = read_request(&mut stream)?;
+        let (status, content_type, body) = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", render(telemetry, crate::log::dropped())),
+            _ => ("404 Not Found", "text/plain", format!("Not found: {}\n", request.path)),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battery::BatteryState;
+    use std::io::Read;
+
+    #[test]
+    fn test_exporter() {
+        let telemetry = TelemetryHub::default();
+        let mut exporter = Exporter::bind("127.0.0.1:0", telemetry.clone()).unwrap();
+        let address = exporter.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let exporter_stop = Arc::clone(&stop);
+        let exporter = thread::spawn(move || exporter.run(exporter_stop));
+
+        let mut state = Telemetry::default();
+        state.total_stats.pru_cycle.record(100);
+        state.total_stats.pru_cycle.record(150);
+        state.battery = Some(BatteryState {
+            remaining: 50.0,
+            ..Default::default()
+        });
+        telemetry.publish(state);
+
+        let scrape = |path: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: drosix\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = scrape("/metrics");
+        stop.store(true, Ordering::Relaxed);
+        exporter.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("# TYPE drosix_loop_duration_seconds histogram\n"), "{}", response);
+        assert!(response.contains("drosix_loop_duration_seconds_bucket{stage=\"pru_cycle\",le=\"0.00005\"} 0\n"));
+        assert!(response.contains("drosix_loop_duration_seconds_bucket{stage=\"pru_cycle\",le=\"0.0001\"} 1\n"));
+        assert!(response.contains("drosix_loop_duration_seconds_bucket{stage=\"pru_cycle\",le=\"0.0002\"} 2\n"));
+        assert!(response.contains("drosix_loop_duration_seconds_count{stage=\"pru_cycle\"} 2\n"));
+        assert!(response.contains("drosix_battery_remaining_ratio 0.5\n"));
+        assert!(response.contains("# TYPE drosix_log_dropped_records_total counter\n"), "{}", response);
+        assert!(response.contains("drosix_log_dropped_records_total 0\n"));
+    }
+}
@@ -1,38 +1,93 @@
+use crate::arbiter::{Arbiter, ArbiterConfig};
+use crate::battery::{BatteryConfig, BatteryLevel, BatteryMonitor, ThrustCompensation};
+use crate::blackbox::{Blackbox, BlackboxConfig, Frame, Trigger};
 use crate::config::DROSIX_CONFIG;
 use crate::controller::PruController;
+use crate::health::{ImuHealth, Recovery, IMU_PERIOD};
 use crate::log::{scope, MeasureRecord};
-use crate::polling::Poller;
+use crate::polling::{Poller, Timer};
 use crate::sensor::{Error, Sensors};
-use crate::types::{Command, FlightCommand, Odometry, PidConfig};
+use crate::stats::LoopStats;
+use crate::types::{Command, CommandReceiver, FlightCommand, Odometry, PidConfig, Telemetry};
 
 use mio::{Interest, Token};
 
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::Sender;
 
 use anyhow::{Context, Result};
 
 use prusst::Pruss;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const IMU: Token = Token(0);
 const CONTROLLER: Token = Token(1);
 const DEBUG: Token = Token(2);
+const BARO: Token = Token(3);
+const TELEMETRY: Token = Token(4);
+const MAG: Token = Token(5);
+
+/// Roll and pitch in degrees of a full flight command
+pub const MAX_TILT: f32 = 15.0;
+/// Longest time without IMU interrupt before considering it missed
+const IMU_TIMEOUT: Duration = Duration::from_millis(20);
+/// Telemetry and battery monitoring period
+const TELEMETRY_PERIOD: Duration = Duration::from_millis(100);
+/// Loop timing statistics aggregation period
+const STATS_PERIOD: Duration = Duration::from_secs(1);
+/// Time taken by a failsafe landing to bring the thrust down to zero
+const LANDING_DURATION: Duration = Duration::from_secs(5);
+
+/// Failsafe descent reducing the thrust down to zero before disarming
+struct Landing {
+    start: Instant,
+    thrust: f32,
+}
 
 pub struct FlightController {
     command: FlightCommand,
     measures: Odometry,
-    server_rx: Receiver<Command>,
-    server_tx: Sender<()>,
+    /// Altitude above the ground reference in m
+    altitude: f32,
+    mag: [f32; 3],
+    armed: bool,
+    landing: Option<Landing>,
+    battery: Option<BatteryMonitor>,
+    thrust_compensation: ThrustCompensation,
+    imu_health: ImuHealth,
+    /// Loop timing statistics of the current period
+    stats: LoopStats,
+    /// Loop timing statistics of the last complete period
+    last_stats: LoopStats,
+    total_stats: LoopStats,
+    stats_start: Instant,
+    arbiter: Arbiter,
+    blackbox: Blackbox,
+    server_rx: CommandReceiver,
+    server_tx: Sender<Telemetry>,
 }
 
 impl<'a> FlightController {
-    pub fn new(server_rx: Receiver<Command>, server_tx: Sender<()>) -> Self {
-        Self {
+    pub fn new(server_rx: CommandReceiver, server_tx: Sender<Telemetry>) -> Result<Self> {
+        let battery_config = BatteryConfig::load()?;
+        Ok(Self {
             command: FlightCommand::default(),
             measures: Odometry::default(),
+            altitude: 0.0,
+            mag: [0.0; 3],
+            armed: false,
+            landing: None,
+            battery: battery_config.enabled.then(|| BatteryMonitor::new(battery_config)),
+            thrust_compensation: ThrustCompensation::load()?,
+            imu_health: ImuHealth::default(),
+            stats: LoopStats::default(),
+            last_stats: LoopStats::default(),
+            total_stats: LoopStats::default(),
+            stats_start: Instant::now(),
+            arbiter: Arbiter::new(ArbiterConfig::load()?),
+            blackbox: Blackbox::new(BlackboxConfig::load()?),
             server_rx,
             server_tx,
-        }
+        })
     }
 
     pub fn run(&mut self) -> Result<()> {
@@ -47,6 +102,14 @@ impl<'a> FlightController {
         let mut sensors = Sensors::new()?;
 
         poller.register(&sensors.imu_event(), IMU, Interest::READABLE)?;
+        if let Some(barometer) = sensors.barometer_event() {
+            poller.register(&barometer, BARO, Interest::READABLE)?;
+        }
+        if let Some(magnetometer) = sensors.magnetometer_event() {
+            poller.register(&magnetometer, MAG, Interest::READABLE)?;
+        }
+        let mut telemetry = Timer::new(TELEMETRY_PERIOD)?;
+        poller.register(&telemetry, TELEMETRY, Interest::READABLE)?;
 
         controller.set_pid(
             DROSIX_CONFIG.get("roll_pid")?,
@@ -64,28 +127,48 @@ impl<'a> FlightController {
 
         PruController::start(&mut pru.pru0, &mut pru.pru1)?;
 
+        let mut last_imu = Instant::now();
         'control_loop: loop {
-            let events = poller.poll(Some(Duration::from_millis(20)))?;
-            if events.is_empty() {
-                log::warn!("IMU event timed out");
-                sensors.handle_imu_event()?;
-                sensors.clean_imu()?;
-            }
+            let events = poller.poll(Some(IMU_TIMEOUT.saturating_sub(last_imu.elapsed())))?;
+            let wake = Instant::now();
+            let mut imu_event = false;
             for event in events.iter() {
                 match event.token() {
                     IMU => {
-                        self.fly(&mut sensors, &mut controller).or_else(|err| match err.downcast_ref::<Error>() {
-                            // TODO Handle error as critical if the drone is armed or flying
-                            Some(Error::NotCalibarated) => Ok(()),
-                            Some(Error::NotAvailable) => Ok(log::warn!("IMU data not available")),
-                            _ => Err(err),
-                        })?
+                        imu_event = true;
+                        let recovery = match self.fly(&mut sensors, &mut controller) {
+                            Ok(()) => {
+                                self.stats.imu_to_pru.record_duration(wake.elapsed());
+                                self.stats.record_pru(controller.read_cycle(), controller.read_stall());
+                                self.imu_health.sample(wake);
+                                self.record_blackbox(&controller);
+                                Recovery::None
+                            },
+                            Err(err) => match err.downcast_ref::<Error>() {
+                                Some(Error::NotCalibarated) => {
+                                    self.imu_health.sample(wake);
+                                    Recovery::None
+                                },
+                                Some(Error::NotAvailable) => {
+                                    log::warn!("IMU data not available");
+                                    self.imu_health.not_ready()
+                                },
+                                _ => {
+                                    log::error!("{:#}", err);
+                                    self.imu_health.error()
+                                },
+                            },
+                        };
+                        self.recover(recovery, &mut sensors, &mut controller);
                     },
                     CONTROLLER => {
                         if !controller.handle_event() {
                             log::info!("Flight controller stopped");
                             break 'control_loop;
                         }
+                        if let Err(err) = controller.check_layout() {
+                            log::error!("{:#}", err);
+                        }
                     },
                     DEBUG => {
                         controller.handle_debug();
@@ -95,15 +178,42 @@ impl<'a> FlightController {
                             sensor: self.measures,
                             position_pid,
                             velocity_pid,
+                            mag: self.mag,
                         });
                         #[cfg(feature = "profiling")]
                         metrics::histogram!("drosix", "function" => "PRU pid")
                             .record(controller.read_cycle() as f64 / 200e6);
                     },
+                    BARO => sensors.handle_barometer_event().unwrap_or_else(|err| log::warn!("{}", err)),
+                    MAG => sensors.handle_magnetometer_event().unwrap_or_else(|err| log::warn!("{}", err)),
+                    TELEMETRY => {
+                        let periods = telemetry.handle_event()?;
+                        self.monitor_battery(periods as f64 * TELEMETRY_PERIOD.as_secs_f64());
+                        if self.stats_start.elapsed() >= STATS_PERIOD {
+                            let period = self.last_stats.period + 1;
+                            self.last_stats = std::mem::take(&mut self.stats);
+                            self.last_stats.period = period;
+                            self.total_stats.merge(&self.last_stats);
+                            self.total_stats.period = period;
+                            self.stats_start = Instant::now();
+                        }
+                        self.publish_telemetry();
+                    },
                     _ => (),
                 }
             }
-            self.handle_command(&mut controller);
+            if imu_event {
+                last_imu = Instant::now();
+            } else if last_imu.elapsed() >= IMU_TIMEOUT {
+                log::warn!("IMU event timed out");
+                let recovery = self.imu_health.missed();
+                self.recover(recovery, &mut sensors, &mut controller);
+                last_imu = Instant::now();
+            }
+            self.handle_command(&mut controller, &mut sensors);
+            if wake.elapsed() > IMU_PERIOD {
+                self.stats.overruns += 1;
+            }
         }
 
         Ok(())
@@ -114,46 +224,197 @@ impl<'a> FlightController {
     fn fly(&mut self, sensors: &mut Sensors, controller: &mut PruController) -> Result<()> {
         let mut measures = sensors.handle_imu_event()?;
         self.measures = measures;
+        self.altitude = sensors.altitude();
+        self.mag = sensors.magnetometer();
+
+        if let Some(landing) = self.landing.as_ref() {
+            let progress = landing.start.elapsed().as_secs_f32() / LANDING_DURATION.as_secs_f32();
+            if progress >= 1.0 {
+                log::warn!("Failsafe landing completed");
+                self.disarm(controller);
+            } else {
+                self.command = FlightCommand {
+                    thrust: landing.thrust * (1.0 - progress),
+                    ..Default::default()
+                };
+            }
+        }
 
-        measures.thrust = 0.0;
-        measures.thrust += self.command.thrust * 99999.0;
-        measures.attitude.roll *= -1.0;
-        measures.attitude.roll += self.command.angles.roll * f32::to_radians(15.0);
-        measures.attitude.pitch *= -1.0;
-        measures.attitude.pitch += self.command.angles.pitch * f32::to_radians(15.0);
-        measures.attitude.yaw *= -1.0;
+        let thrust_scale =
+            self.battery.as_ref().map_or(1.0, |battery| self.thrust_compensation.scale(battery.state().cell_voltage));
+        // The PRU expects the attitude error and the measured rates
+        measures.thrust = self.command.thrust * thrust_scale * 99999.0;
+        measures.attitude.roll = self.command.angles.roll * MAX_TILT.to_radians() - measures.attitude.roll;
+        measures.attitude.pitch = self.command.angles.pitch * MAX_TILT.to_radians() - measures.attitude.pitch;
+        measures.attitude.yaw = -measures.attitude.yaw;
 
         controller.set_pid_inputs(measures);
         Ok(())
     }
 
-    fn handle_command(&mut self, controller: &mut PruController) {
-        match self.server_rx.try_recv() {
-            Ok(Command::Flight(command)) => {
-                self.command = command;
+    fn handle_command(&mut self, controller: &mut PruController, sensors: &mut Sensors) {
+        let command = self.server_rx.try_recv().map(|(timestamp, command, source)| {
+            self.stats.command_lag.record_duration(timestamp.elapsed());
+            (command, source)
+        });
+        let command = match command {
+            Ok((command, source)) if self.arbiter.accept(source, &command, Instant::now()) => command,
+            _ => return,
+        };
+        match command {
+            Command::Flight(command) => {
+                // The failsafe landing overrides the pilot commands
+                if self.landing.is_none() {
+                    self.command = command;
+                }
             },
-            Ok(Command::SwitchDebug(dbg)) => {
+            Command::Attitude(angles) => {
+                if self.landing.is_none() {
+                    self.command.angles = angles;
+                }
+            },
+            Command::SwitchDebug(dbg) => {
                 log::info!("Switching debug mode to {:?}", dbg);
                 controller.switch_debug(dbg);
             },
-            Ok(Command::Armed(true)) => {
+            Command::Armed(true) => {
                 log::info!("Arming");
-                controller.set_armed();
+                if self.battery.as_ref().is_some_and(|battery| battery.state().level == BatteryLevel::Critical) {
+                    log::error!("Cannot arm with a critical battery");
+                } else if let Err(err) = controller.check_layout() {
+                    log::error!("Cannot arm: {:#}", err);
+                } else {
+                    sensors.set_ground_reference();
+                    controller.set_armed();
+                    self.armed = true;
+                }
             },
-            Ok(Command::Armed(false)) => {
+            Command::Armed(false) => {
                 log::info!("Disarming");
-                controller.clear_armed();
-                self.command = FlightCommand::default();
+                self.disarm(controller);
             },
-            Ok(Command::SetMotor {
+            Command::SetMotor {
                 motor,
                 value,
-            }) => controller.set_motor_speed(motor, value).unwrap_or_else(|e| log::warn!("{}", e)),
-            Ok(Command::Stop) => {
+            } => {
+                // The raw motor values bypass the PIDs so they are only applied when disarmed
+                if self.armed {
+                    log::warn!("Ignoring motor {} command while armed", motor);
+                } else {
+                    controller.set_motor_speed(motor, value).unwrap_or_else(|e| log::warn!("{}", e));
+                }
+            },
+            Command::SetPid {
+                axis,
+                pid,
+            } => {
+                log::info!("Setting {:?} PID to {:?}", axis, pid);
+                if self.armed {
+                    log::warn!("{:?} PID will be applied at the next arming", axis);
+                }
+                controller.set_axis_pid(axis, pid);
+            },
+            Command::Failsafe(reason) => self.failsafe_landing(&reason),
+            Command::Stop => {
                 log::warn!("Stoping flight controller");
                 controller.stop();
             },
-            _ => {},
         }
     }
+
+    fn disarm(&mut self, controller: &mut PruController) {
+        if self.armed {
+            self.blackbox.trigger(Trigger::Disarm);
+        }
+        controller.clear_armed();
+        self.armed = false;
+        self.landing = None;
+        self.command = FlightCommand::default();
+    }
+
+    /// Starts a failsafe landing if the drone is armed
+    fn failsafe_landing(&mut self, reason: &str) {
+        if self.armed && self.landing.is_none() {
+            log::error!("Failsafe landing: {}", reason);
+            self.blackbox.trigger(Trigger::Failsafe);
+            self.landing = Some(Landing {
+                start: Instant::now(),
+                thrust: self.command.thrust,
+            });
+        }
+    }
+
+    /// Applies the recovery action requested by the IMU health monitor
+    fn recover(&mut self, recovery: Recovery, sensors: &mut Sensors, controller: &mut PruController) {
+        match recovery {
+            Recovery::None => (),
+            Recovery::ResetFifo => {
+                sensors.clean_imu().unwrap_or_else(|err| log::error!("Resetting IMU FIFO: {:#}", err))
+            },
+            Recovery::Reinit if self.armed => {
+                // The re-initialization blocks the loop for hundreds of ms, it is only attempted on the ground
+                log::error!("IMU not recovered by the FIFO resets");
+                self.recover(Recovery::Failsafe, sensors, controller);
+            },
+            Recovery::Reinit => {
+                log::error!("Re-initializing IMU");
+                sensors.reinit_imu().unwrap_or_else(|err| log::error!("Re-initializing IMU: {:#}", err));
+            },
+            Recovery::Failsafe => {
+                // Without attitude the drone cannot be stabilized so the motors are stopped
+                if self.armed {
+                    log::error!("Failsafe: IMU lost, disarming");
+                    self.disarm(controller);
+                }
+            },
+        }
+    }
+
+    fn record_blackbox(&mut self, controller: &PruController) {
+        let (position_pid, velocity_pid) = controller.read_pid();
+        let crash = self.blackbox.record(Frame {
+            armed: self.armed,
+            command: self.command,
+            sensor: self.measures,
+            altitude: self.altitude,
+            position_pid,
+            velocity_pid,
+            motors: controller.read_motors(),
+            ..Default::default()
+        });
+        if crash {
+            log::error!("Crash detected");
+        }
+    }
+
+    fn monitor_battery(&mut self, dt: f64) {
+        let Some(battery) = self.battery.as_mut() else {
+            return;
+        };
+        match battery.update(dt) {
+            Ok(Some(BatteryLevel::Low)) => log::warn!("Battery low: {:.2}V per cell", battery.state().cell_voltage),
+            Ok(Some(BatteryLevel::Critical)) => {
+                let reason = format!("battery critical {:.2}V per cell", battery.state().cell_voltage);
+                self.failsafe_landing(&reason);
+            },
+            Ok(_) => (),
+            Err(err) => log::warn!("{:#}", err),
+        }
+    }
+
+    fn publish_telemetry(&self) {
+        // Nobody may be listening to the telemetry
+        let _ = self.server_tx.send(Telemetry {
+            armed: self.armed,
+            landing: self.landing.is_some(),
+            control: self.arbiter.active(),
+            command: self.command,
+            odometry: self.measures,
+            altitude: self.altitude,
+            battery: self.battery.as_ref().map(|battery| battery.state()),
+            imu: self.imu_health.counters(),
+            stats: self.last_stats,
+            total_stats: self.total_stats,
+        });
+    }
 }
@@ -0,0 +1,180 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::arbiter::Source;
+use crate::config::CONFIG_FILE;
+use crate::flight_controller::MAX_TILT;
+use crate::log::{LogSink, Logger};
+use crate::plugin::{run_plugin, PluginConfig};
+use crate::types::{command_channel, Command, CommandReceiver, Telemetry, TelemetryHub};
+
+/// Telemetry publication period of the simulated flight controller
+const TELEMETRY_PERIOD: Duration = Duration::from_millis(100);
+
+/// Command sent by the plugin under test
+#[derive(Serialize, Debug)]
+pub struct Record {
+    /// Time since the plugin start in s
+    pub time: f32,
+    pub command: Command,
+}
+
+/// Flight controller stand-in applying the commands instantly: the attitude follows the flight command and a
+/// failsafe landing disarms at once. It returns the recorded commands once `done` is set.
+fn simulate(command_rx: CommandReceiver, telemetry: TelemetryHub, done: Arc<AtomicBool>) -> Vec<Record> {
+    let start = Instant::now();
+    let mut state = Telemetry::default();
+    let mut records = Vec::new();
+    let mut last_publication = Instant::now();
+    telemetry.publish(state);
+
+    while !done.load(Ordering::Relaxed) {
+        match command_rx.recv_timeout(TELEMETRY_PERIOD) {
+            Ok((timestamp, command, _)) => {
+                match &command {
+                    Command::Armed(armed) => {
+                        state.armed = *armed;
+                        state.control = armed.then_some(Source::Plugin);
+                        state.command = Default::default();
+                    },
+                    Command::Flight(command) if state.armed => {
+                        state.command = *command;
+                        state.odometry.thrust = command.thrust;
+                        state.odometry.attitude.roll = command.angles.roll * MAX_TILT.to_radians();
+                        state.odometry.attitude.pitch = command.angles.pitch * MAX_TILT.to_radians();
+                    },
+                    Command::Failsafe(reason) => {
+                        log::error!("Failsafe landing: {}", reason);
+                        state.armed = false;
+                        state.control = None;
+                    },
+                    _ => (),
+                }
+                records.push(Record {
+                    time: timestamp.duration_since(start).as_secs_f32(),
+                    command,
+                });
+                telemetry.publish(state);
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if last_publication.elapsed() > TELEMETRY_PERIOD {
+            last_publication = Instant::now();
+            telemetry.publish(state);
+        }
+    }
+    records
+}
+
+/// Runs a plugin offline against a simulated flight controller and returns the commands it sent.
+/// The plugin is stopped after `timeout` if it did not stop by itself.
+pub fn plugin_test<P: AsRef<Path>>(path: P, config: PluginConfig, timeout: Duration) -> Result<Vec<Record>> {
+    let (command_tx, command_rx) = command_channel();
+    let telemetry = TelemetryHub::default();
+    let stop = Arc::new(AtomicBool::new(false));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let simulator = {
+        let telemetry = telemetry.clone();
+        let done = Arc::clone(&done);
+        thread::spawn(move || simulate(command_rx, telemetry, done))
+    };
+    let watchdog = {
+        let stop = Arc::clone(&stop);
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            let start = Instant::now();
+            while !done.load(Ordering::Relaxed) {
+                if start.elapsed() > timeout {
+                    log::warn!("Stopping the plugin after {:?}", timeout);
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        })
+    };
+
+    let result = run_plugin(path, config, command_tx.with_source(Source::Plugin), telemetry, stop);
+    // Let the simulator drain the last commands
+    thread::sleep(TELEMETRY_PERIOD);
+    done.store(true, Ordering::Relaxed);
+    let _ = watchdog.join();
+    let records = simulator.join().expect("Simulator panicked");
+    result.map(|()| records)
+}
+
+/// `drone plugin-test` entry point printing the recorded commands as JSON lines on stdout.
+/// The plugin limits and the log output come from the parameter file when there is one, the logs are printed first.
+pub fn run_plugin_test(path: &str, timeout: Duration) -> Result<()> {
+    let (config, mut log_sink) = if Path::new(CONFIG_FILE).exists() {
+        let log_sink = Logger::init()?;
+        (PluginConfig::load()?, Some(log_sink))
+    } else {
+        (PluginConfig::default(), None)
+    };
+    let path = path.to_owned();
+    let test = thread::spawn(move || plugin_test(path, config, timeout));
+    while !test.is_finished() {
+        log_sink.iter_mut().for_each(LogSink::handle_logs);
+        thread::sleep(Duration::from_millis(10));
+    }
+    log_sink.iter_mut().for_each(LogSink::handle_logs);
+
+    let records = test.join().expect("Plugin test panicked")?;
+    let mut stdout = io::stdout().lock();
+    for record in records {
+        writeln!(stdout, "{}", serde_json::to_string(&record)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::tests::PLUGIN_TEST;
+
+    const MISSION: &str = r#"
+import drosix
+
+class Mission(drosix.Plugin):
+    def on_start(self):
+        drosix.arm()
+        drosix.takeoff(0.6, 0.2)
+        drosix.hold((0.6, 0.0, 0.5, 0.0), 0.2)
+        drosix.wait_until(lambda telemetry: telemetry.pitch > 0.1, 1.0)
+        drosix.land(0.2)
+        self.stop()
+
+drosix.register(Mission)
+"#;
+
+    #[test]
+    fn test_plugin_test() {
+        let _plugin = PLUGIN_TEST.lock().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("plugin.py");
+        std::fs::write(&path, MISSION).unwrap();
+        let records = plugin_test(&path, PluginConfig::default(), Duration::from_secs(5)).unwrap();
+
+        assert!(matches!(records.first().unwrap().command, Command::Armed(true)), "{:?}", records);
+        assert!(matches!(records.last().unwrap().command, Command::Armed(false)), "{:?}", records);
+        let pitch = records
+            .iter()
+            .any(|record| matches!(&record.command, Command::Flight(command) if command.angles.pitch == 0.5));
+        assert!(pitch, "{:?}", records);
+        assert!(records.windows(2).all(|x| x[0].time <= x[1].time));
+        // The record stream is what `drone plugin-test` prints
+        let line = serde_json::to_string(&records[0]).unwrap();
+        assert!(line.contains("\"Armed\":true"), "{}", line);
+    }
+}
@@ -0,0 +1,172 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Nominal IMU sample period (DMP at 100Hz)
+pub const IMU_PERIOD: Duration = Duration::from_millis(10);
+/// Weight of a new sample in the jitter average
+const JITTER_FILTER: f32 = 0.05;
+/// Consecutive failures tolerated before resetting the FIFO
+const RESET_FIFO_AFTER: u32 = 2;
+/// Consecutive failures before re-initializing the MPU9250
+const REINIT_AFTER: u32 = 5;
+/// Consecutive failures before triggering the failsafe
+const FAILSAFE_AFTER: u32 = 8;
+/// Consecutive failures between two re-initialization attempts once in failsafe
+const REINIT_RETRY: u32 = 50;
+
+/// IMU health counters exported in the telemetry
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImuHealthCounters {
+    /// Valid samples
+    pub samples: u64,
+    /// Poll timeouts without IMU interrupt
+    pub missed: u64,
+    /// Interrupts without data in the DMP FIFO
+    pub not_ready: u64,
+    /// IMU read errors
+    pub errors: u64,
+    pub fifo_resets: u64,
+    pub reinits: u64,
+    /// Average deviation from the nominal sample period in ms
+    pub jitter: f32,
+    /// Longest sample period in ms
+    pub max_period: f32,
+}
+
+/// Recovery action requested by the health monitor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Recovery {
+    None,
+    ResetFifo,
+    /// Blocking re-initialization, the flight controller triggers the failsafe instead while armed
+    Reinit,
+    Failsafe,
+}
+
+/// Tracks the IMU sample timing and failures and escalates the recovery actions while the failures keep coming:
+/// reset the FIFO, re-initialize the MPU9250 and finally trigger the failsafe.
+#[derive(Default)]
+pub struct ImuHealth {
+    counters: ImuHealthCounters,
+    last_sample: Option<Instant>,
+    failures: u32,
+}
+
+impl ImuHealth {
+    pub fn counters(&self) -> ImuHealthCounters {
+        self.counters
+    }
+
+    /// Records a valid sample
+    pub fn sample(&mut self, now: Instant) {
+        if let Some(last) = self.last_sample {
+            let period = now.duration_since(last).as_secs_f32() * 1e3;
+            let deviation = (period - IMU_PERIOD.as_secs_f32() * 1e3).abs();
+            self.counters.jitter += JITTER_FILTER * (deviation - self.counters.jitter);
+            self.counters.max_period = self.counters.max_period.max(period);
+        }
+        if self.failures >= FAILSAFE_AFTER {
+            log::warn!("IMU recovered after {} failures", self.failures);
+        }
+        self.last_sample = Some(now);
+        self.counters.samples += 1;
+        self.failures = 0;
+    }
+
+    /// Records a poll timeout without IMU interrupt
+    pub fn missed(&mut self) -> Recovery {
+        self.counters.missed += 1;
+        self.escalate()
+    }
+
+    /// Records an interrupt without data available
+    pub fn not_ready(&mut self) -> Recovery {
+        self.counters.not_ready += 1;
+        self.escalate()
+    }
+
+    /// Records an IMU read error
+    pub fn error(&mut self) -> Recovery {
+        self.counters.errors += 1;
+        self.escalate()
+    }
+
+    fn escalate(&mut self) -> Recovery {
+        self.failures += 1;
+        // The sample period is meaningless across a failure
+        self.last_sample = None;
+        let recovery = match self.failures {
+            x if x < RESET_FIFO_AFTER => Recovery::None,
+            x if x < REINIT_AFTER => Recovery::ResetFifo,
+            REINIT_AFTER => Recovery::Reinit,
+            x if x < FAILSAFE_AFTER => Recovery::ResetFifo,
+            FAILSAFE_AFTER => Recovery::Failsafe,
+            x if (x - FAILSAFE_AFTER).is_multiple_of(REINIT_RETRY) => Recovery::Reinit,
+            _ => Recovery::None,
+        };
+        match recovery {
+            Recovery::ResetFifo => self.counters.fifo_resets += 1,
+            Recovery::Reinit => self.counters.reinits += 1,
+            _ => (),
+        }
+        recovery
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escalation() {
+        let mut health = ImuHealth::default();
+        let recoveries: Vec<_> = (0..FAILSAFE_AFTER).map(|_| health.missed()).collect();
+        assert_eq!(
+            recoveries,
+            vec![
+                Recovery::None,
+                Recovery::ResetFifo,
+                Recovery::ResetFifo,
+                Recovery::ResetFifo,
+                Recovery::Reinit,
+                Recovery::ResetFifo,
+                Recovery::ResetFifo,
+                Recovery::Failsafe
+            ]
+        );
+        // Keeps trying to re-initialize the IMU once in failsafe
+        let recoveries: Vec<_> = (0..REINIT_RETRY).map(|_| health.not_ready()).collect();
+        assert_eq!(recoveries.iter().filter(|x| **x != Recovery::None).count(), 1);
+        assert_eq!(recoveries.last(), Some(&Recovery::Reinit));
+
+        let counters = health.counters();
+        assert_eq!(counters.missed, u64::from(FAILSAFE_AFTER));
+        assert_eq!(counters.not_ready, u64::from(REINIT_RETRY));
+        assert_eq!(counters.fifo_resets, 5);
+        assert_eq!(counters.reinits, 2);
+
+        // A valid sample resets the escalation
+        health.sample(Instant::now());
+        assert_eq!(health.error(), Recovery::None);
+        assert_eq!(health.error(), Recovery::ResetFifo);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let mut health = ImuHealth::default();
+        let start = Instant::now();
+        for i in 0..200 {
+            // Alternates 8ms and 12ms periods
+            let offset = if i % 2 == 0 {
+                0
+            } else {
+                2
+            };
+            health.sample(start + Duration::from_millis(10 * i + offset));
+        }
+        let counters = health.counters();
+        assert_eq!(counters.samples, 200);
+        assert!((counters.jitter - 2.0).abs() < 0.01, "jitter {}", counters.jitter);
+        assert!((counters.max_period - 12.0).abs() < 0.01, "max period {}", counters.max_period);
+    }
+}
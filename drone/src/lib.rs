@@ -1,9 +1,24 @@
+pub mod arbiter;
+pub mod barometer;
+pub mod battery;
+pub mod blackbox;
+pub mod calibration;
 pub mod config;
 pub mod controller;
+pub mod estimator;
+pub mod exporter;
 pub mod flight_controller;
+pub mod harness;
+pub mod health;
 pub mod log;
+pub mod magnetometer;
+pub mod mavlink;
+pub mod mission;
+pub mod orientation;
 pub mod plugin;
 pub mod polling;
 pub mod remote;
 pub mod sensor;
+pub mod server;
+pub mod stats;
 pub mod types;
@@ -0,0 +1,381 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::config;
+use crate::types::{Angles, FlightCommand, Odometry};
+use sink::Sink;
+pub use sink::{RecordKind, SinkConfig};
+
+#[cfg(feature = "profiling")]
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+#[cfg(feature = "profiling")]
+use rstats::Stats;
+
+mod sink;
+
+static SCOPE: OnceLock<&'static Logger> = OnceLock::new();
+
+/// Period at which the dropped records are reported
+const DROPPED_PERIOD: Duration = Duration::from_secs(5);
+
+/// Level filter parsed from a spec like `warn,drone::sensor=debug`: a bare level applies to every target and
+/// `target=level` to the targets starting with `target`, the longest matching target wins. Without bare level the
+/// default level applies.
+#[derive(Debug, PartialEq)]
+pub struct Filter {
+    default: LevelFilter,
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut filter = Self::default();
+        for directive in spec.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let level = |level: &str| level.parse().map_err(|_| anyhow!("Invalid log level {}", level));
+            match directive.split_once('=') {
+                Some((target, level_spec)) => filter.directives.push((target.trim().into(), level(level_spec.trim())?)),
+                None => filter.default = level(directive)?,
+            }
+        }
+        // Longest targets first
+        filter.directives.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        Ok(filter)
+    }
+
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|(prefix, _)| {
+                target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// Most verbose level of the filter
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives.iter().map(|(_, level)| *level).fold(self.default, Ord::max)
+    }
+}
+
+impl Default for Filter {
+    /// Info in debug builds and warn in release builds
+    fn default() -> Self {
+        Self {
+            default: if cfg!(debug_assertions) {
+                LevelFilter::Info
+            } else {
+                LevelFilter::Warn
+            },
+            directives: Vec::new(),
+        }
+    }
+}
+
+struct SyncRecord {
+    timestamp: Instant,
+    level: Level,
+    target: String,
+    thread: String,
+    content: String,
+}
+
+/// PID debug measures, written raw in the logs and decoded by `model/drosix_types.py`
+#[repr(C)]
+pub struct MeasureRecord {
+    pub command: FlightCommand,
+    pub sensor: Odometry,
+    pub position_pid: Angles,
+    pub velocity_pid: Angles,
+    pub mag: [f32; 3],
+}
+
+struct SyncMeasure {
+    timestamp: Instant,
+    measure: MeasureRecord,
+}
+
+impl std::fmt::Display for MeasureRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let raw = unsafe {
+            let slice = std::slice::from_raw_parts((self as *const Self) as *const u8, std::mem::size_of::<Self>());
+            std::str::from_utf8_unchecked(slice)
+        };
+        write!(f, "{}", raw)
+    }
+}
+
+/// Sinks of the parameter file, a single sink may be configured directly in the `[log]` table, stdout by default
+fn sink_configs() -> Result<Vec<SinkConfig>> {
+    if let Some(sinks) = config::section("log.sinks")? {
+        return Ok(sinks);
+    }
+    match config::section::<toml::Table>("log")? {
+        Some(mut table) if table.contains_key("sink") => {
+            table.remove("filter");
+            Ok(vec![toml::Value::Table(table).try_into().context("Invalid [log] sink")?])
+        },
+        _ => Ok(vec![SinkConfig::stdout()]),
+    }
+}
+
+pub fn scope(measure: MeasureRecord) {
+    SCOPE.get().map(|x| x.scope(measure));
+}
+
+/// Log records and measures dropped since the start
+pub fn dropped() -> (u64, u64) {
+    SCOPE.get().map_or((0, 0), |logger| logger.dropped())
+}
+
+/// Timing of a profiled function
+#[cfg(feature = "profiling")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Profile {
+    /// Calls since the start
+    pub count: u64,
+    /// Time spent since the start in s
+    pub sum: f64,
+    /// Longest call of the last summary period in s
+    pub max: f64,
+}
+
+#[cfg(feature = "profiling")]
+static PROFILES: std::sync::Mutex<std::collections::BTreeMap<String, Profile>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+/// Timings of the profiled functions, updated with the profiling log lines
+#[cfg(feature = "profiling")]
+pub fn profiles() -> Vec<(String, Profile)> {
+    PROFILES.lock().unwrap().iter().map(|(function, profile)| (function.clone(), *profile)).collect()
+}
+
+pub struct LogSink {
+    logger: &'static Logger,
+    log_rx: Receiver<SyncRecord>,
+    measure_rx: Receiver<SyncMeasure>,
+    start: Instant,
+    last_dropped_report: Instant,
+    /// Dropped log records and measures at the last report
+    reported_dropped: (u64, u64),
+    sinks: Vec<Sink>,
+    #[cfg(feature = "profiling")]
+    snapchotter: Snapshotter,
+    #[cfg(feature = "profiling")]
+    previous: Instant,
+}
+
+pub struct Logger {
+    filter: Filter,
+    log_tx: SyncSender<SyncRecord>,
+    measure_tx: SyncSender<SyncMeasure>,
+    /// Records dropped because the sink was full
+    dropped_logs: AtomicU64,
+    dropped_measures: AtomicU64,
+}
+
+impl Logger {
+    /// Installs the logger, an invalid `[log]` section is reported as there is no log output yet
+    pub fn init() -> Result<LogSink> {
+        let (log_tx, log_rx) = sync_channel(20);
+        let (measure_tx, measure_rx) = sync_channel(3);
+        let start = Instant::now();
+        let filter = config::section::<String>("log.filter")?
+            .map(|spec| Filter::parse(&spec))
+            .transpose()
+            .context("Invalid [log] filter")?
+            .unwrap_or_default();
+        let configs = sink_configs()?;
+        let max_level = filter.max_level();
+        let logger = Box::new(Self {
+            filter,
+            log_tx,
+            measure_tx,
+            dropped_logs: AtomicU64::new(0),
+            dropped_measures: AtomicU64::new(0),
+        });
+        let logger_ref = Box::leak(logger);
+        log::set_logger(logger_ref).map(|()| log::set_max_level(max_level)).expect("Cannot install global logger");
+        // Can only happen once in this function
+        SCOPE.set(logger_ref).map_err(|_| ()).expect("Cannot install global measurer");
+        #[cfg(feature = "profiling")]
+        let snapchotter = {
+            let recorder = DebuggingRecorder::new();
+            let snapchotter = recorder.snapshotter();
+            recorder.install().expect("Cannot install global recorder");
+            snapchotter
+        };
+        let sinks = configs
+            .into_iter()
+            .filter_map(|config| Sink::open(config).inspect_err(|err| eprintln!("Log sink: {:#}", err)).ok())
+            .collect();
+        Ok(LogSink {
+            logger: logger_ref,
+            log_rx,
+            measure_rx,
+            start,
+            last_dropped_report: start,
+            reported_dropped: (0, 0),
+            sinks,
+            #[cfg(feature = "profiling")]
+            snapchotter,
+            #[cfg(feature = "profiling")]
+            previous: start,
+        })
+    }
+
+    fn dropped(&self) -> (u64, u64) {
+        (self.dropped_logs.load(Ordering::Relaxed), self.dropped_measures.load(Ordering::Relaxed))
+    }
+
+    fn scope(&self, measure: MeasureRecord) {
+        let sent = self.measure_tx.try_send(SyncMeasure {
+            timestamp: Instant::now(),
+            measure,
+        });
+        if sent.is_err() {
+            self.dropped_measures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let thread = thread::current();
+            let sent = self.log_tx.try_send(SyncRecord {
+                timestamp: Instant::now(),
+                level: record.level(),
+                target: record.target().into(),
+                thread: thread.name().map_or_else(|| format!("{:?}", thread.id()), Into::into),
+                content: std::fmt::format(*record.args()),
+            });
+            if sent.is_err() {
+                self.dropped_logs.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl LogSink {
+    /// Writes a line to the sinks taking this kind of record
+    fn write(&mut self, kind: RecordKind, level: Option<Level>, line: &str) {
+        for sink in self.sinks.iter_mut().filter(|sink| sink.accepts(kind, level)) {
+            sink.write_line(kind, line, level).inspect_err(|err| eprintln!("{}", err)).ok();
+        }
+    }
+
+    pub fn handle_logs(&mut self) {
+        let mut line = String::new();
+        while let Ok(record) = self.log_rx.try_recv() {
+            line.clear();
+            let _ = write!(
+                line,
+                "[{:<9.5}] {:<5} {} {}: {}",
+                record.timestamp.duration_since(self.start).as_secs_f32(),
+                record.level,
+                record.thread,
+                record.target,
+                record.content
+            );
+            self.write(RecordKind::Logs, Some(record.level), &line);
+        }
+
+        if self.last_dropped_report.elapsed() >= DROPPED_PERIOD {
+            self.last_dropped_report = Instant::now();
+            let dropped = self.logger.dropped();
+            let (logs, measures) = (dropped.0 - self.reported_dropped.0, dropped.1 - self.reported_dropped.1);
+            self.reported_dropped = dropped;
+            if logs > 0 || measures > 0 {
+                line = format!(
+                    "[{:<9.5}] {:<5} {} log records and {} measures dropped in the last {:?}",
+                    self.start.elapsed().as_secs_f32(),
+                    Level::Warn,
+                    logs,
+                    measures,
+                    DROPPED_PERIOD
+                );
+                self.write(RecordKind::Logs, Some(Level::Warn), &line);
+            }
+        }
+
+        while let Ok(measure) = self.measure_rx.try_recv() {
+            line.clear();
+            let _ = write!(
+                line,
+                "[{:<9.5}] MEASURE {}",
+                measure.timestamp.duration_since(self.start).as_secs_f32(),
+                measure.measure
+            );
+            self.write(RecordKind::Measures, None, &line);
+        }
+        #[cfg(feature = "profiling")]
+        {
+            let delta = self.previous.elapsed().as_secs_f32();
+            if delta > 0.5 {
+                let snapchot = self.snapchotter.snapshot();
+                for (key, _, _, metric) in snapchot.into_vec().iter() {
+                    if let DebugValue::Histogram(histogram) = metric {
+                        let max = histogram.iter().max().map(|x| x.into_inner()).unwrap_or(0.0);
+                        let stats = histogram.ameanstd().unwrap_or_default();
+                        let freq = histogram.len() as f32 / delta;
+                        line = format!(
+                            "[{:<9.5}] {:<5}: {} frequency: {:>6.2}Hz, max: {:>6.2e}s, mean {:>6.2e}s ± {:>4.2e}s",
+                            self.start.elapsed().as_secs_f32(),
+                            Level::Trace,
+                            key.key().labels().next().unwrap().value(),
+                            freq,
+                            max,
+                            stats.centre,
+                            stats.spread
+                        );
+                        self.write(RecordKind::Stats, None, &line);
+
+                        let function = key.key().labels().next().unwrap().value().to_owned();
+                        let mut profiles = PROFILES.lock().unwrap();
+                        let profile = profiles.entry(function).or_default();
+                        profile.count += histogram.len() as u64;
+                        profile.sum += histogram.iter().map(|x| x.into_inner()).sum::<f64>();
+                        profile.max = max;
+                    }
+                }
+                self.previous = Instant::now();
+            }
+        }
+
+        for sink in self.sinks.iter_mut() {
+            sink.flush().inspect_err(|err| eprintln!("{}", err)).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::parse("warn, drone::sensor=debug,drone::sensor::imu=error,drone=info").unwrap();
+        assert_eq!(filter.level("drone::flight_controller"), LevelFilter::Info);
+        assert_eq!(filter.level("drone::sensor"), LevelFilter::Debug);
+        assert_eq!(filter.level("drone::sensor::imu"), LevelFilter::Error);
+        assert_eq!(filter.level("drone::sensors"), LevelFilter::Info);
+        assert_eq!(filter.level("mio::poll"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+        assert_eq!(Filter::parse("").unwrap(), Filter::default());
+        assert_eq!(Filter::parse("drone=debug").unwrap().level("mio"), Filter::default().level("mio"));
+        assert!(Filter::parse("drone=loud").is_err());
+    }
+}
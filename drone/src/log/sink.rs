@@ -0,0 +1,455 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use log::{Level, LevelFilter};
+use serde::de::{DeserializeOwned, Error};
+use serde::{Deserialize, Deserializer};
+
+/// Magic starting the log datagrams, see `log-receiver` for the datagram layout
+const MAGIC: [u8; 2] = *b"DL";
+/// Largest record payload sent in a datagram, longer records are truncated
+const MAX_DATAGRAM: usize = 65_507 - 8;
+/// Period of the `periodic` fsync policy
+const FSYNC_PERIOD: Duration = Duration::from_secs(1);
+
+/// Kind of record written to the sinks
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum RecordKind {
+    Logs,
+    /// PID debug measures
+    Measures,
+    /// Profiling statistics
+    Stats,
+}
+
+/// When the log files are synchronized to the storage
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Fsync {
+    Never,
+    /// Every second and after a warning or an error
+    #[default]
+    Periodic,
+    /// After each batch of records
+    Always,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "sink", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SinkKind {
+    Stdout,
+    File {
+        #[serde(default = "default_directory")]
+        directory: PathBuf,
+        /// Size in bytes above which a new file is started
+        #[serde(default = "default_max_size")]
+        max_size: u64,
+        /// Number of files kept, the oldest are removed
+        #[serde(default = "default_keep")]
+        keep: usize,
+        #[serde(default)]
+        fsync: Fsync,
+    },
+    Udp {
+        /// Address and port the records are sent to, broadcast by default
+        #[serde(default = "default_destination")]
+        destination: String,
+        /// Local port, any by default
+        #[serde(default)]
+        port: u16,
+    },
+}
+
+fn default_destination() -> String {
+    "255.255.255.255:9000".into()
+}
+
+fn default_directory() -> PathBuf {
+    PathBuf::from("logs")
+}
+
+fn default_max_size() -> u64 {
+    1 << 20
+}
+
+fn default_keep() -> usize {
+    20
+}
+
+fn default_level() -> String {
+    "trace".into()
+}
+
+fn default_records() -> Vec<RecordKind> {
+    vec![RecordKind::Logs, RecordKind::Measures, RecordKind::Stats]
+}
+
+/// Log sink configuration, the sinks are listed in the parameter file as `[[log.sinks]]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SinkConfig {
+    pub kind: SinkKind,
+    /// Lowest level of the log records written to this sink, on top of the `[log]` filter
+    pub level: String,
+    pub records: Vec<RecordKind>,
+}
+
+impl<'de> Deserialize<'de> for SinkConfig {
+    /// The sink kind only takes its own parameters so that a misspelled or outdated one, e.g. the `path` of the former
+    /// file sink, is reported instead of ignored
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut table = toml::Table::deserialize(deserializer)?;
+        let level = take(&mut table, "level")?.unwrap_or_else(default_level);
+        let records = take(&mut table, "records")?.unwrap_or_else(default_records);
+        Ok(Self {
+            kind: toml::Value::Table(table).try_into().map_err(D::Error::custom)?,
+            level,
+            records,
+        })
+    }
+}
+
+/// Removes a parameter from the table
+fn take<T: DeserializeOwned, E: Error>(table: &mut toml::Table, key: &str) -> Result<Option<T>, E> {
+    table.remove(key).map(|value| value.try_into().map_err(E::custom)).transpose()
+}
+
+impl SinkConfig {
+    pub fn stdout() -> Self {
+        Self {
+            kind: SinkKind::Stdout,
+            level: default_level(),
+            records: default_records(),
+        }
+    }
+}
+
+enum Output {
+    Stream(BufWriter<Box<dyn Write>>),
+    File(RotatingFile),
+    Udp(UdpOutput),
+}
+
+/// Log output receiving the records matching its level and kinds
+pub struct Sink {
+    level: LevelFilter,
+    records: Vec<RecordKind>,
+    output: Output,
+}
+
+impl Sink {
+    pub fn open(config: SinkConfig) -> Result<Self> {
+        let level = config.level.parse().map_err(|_| anyhow!("Invalid log level {}", config.level))?;
+        let output = match config.kind {
+            SinkKind::Stdout => Output::Stream(BufWriter::with_capacity(1000, Box::new(std::io::stdout()))),
+            SinkKind::File {
+                directory,
+                max_size,
+                keep,
+                fsync,
+            } => Output::File(RotatingFile::open(directory, max_size, keep, fsync)?),
+            SinkKind::Udp {
+                destination,
+                port,
+            } => Output::Udp(UdpOutput::open(&destination, port)?),
+        };
+        Ok(Self {
+            level,
+            records: config.records,
+            output,
+        })
+    }
+
+    /// Whether the sink takes the records of this kind and level, the records without level always match it
+    pub fn accepts(&self, kind: RecordKind, level: Option<Level>) -> bool {
+        self.records.contains(&kind) && level.is_none_or(|level| level <= self.level)
+    }
+
+    pub fn write_line(&mut self, kind: RecordKind, line: &str, level: Option<Level>) -> std::io::Result<()> {
+        match &mut self.output {
+            Output::Stream(output) => writeln!(output, "{}", line),
+            Output::File(file) => file.write_line(line, level),
+            Output::Udp(udp) => udp.send(kind, line),
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.output {
+            Output::Stream(output) => output.flush(),
+            Output::File(file) => file.flush(),
+            Output::Udp(_) => Ok(()),
+        }
+    }
+}
+
+/// Log files named `drosix-<flight>-<part>-<unix time>.log` in a directory: each start of the drone increments the
+/// flight counter and a new part is started when the current one exceeds the maximum size.
+pub struct RotatingFile {
+    directory: PathBuf,
+    flight: u32,
+    part: u32,
+    max_size: u64,
+    keep: usize,
+    fsync: Fsync,
+    file: BufWriter<File>,
+    size: u64,
+    /// Data written since the last fsync
+    unsynced: bool,
+    /// Warning or error written since the last fsync
+    urgent: bool,
+    last_sync: Instant,
+}
+
+impl RotatingFile {
+    pub fn open(directory: PathBuf, max_size: u64, keep: usize, fsync: Fsync) -> Result<Self> {
+        fs::create_dir_all(&directory).context("Creating log directory")?;
+        let flight = log_files(&directory)?.iter().filter_map(|(flight, _)| *flight).max().map_or(1, |x| x + 1);
+        let file = Self::create(&directory, flight, 0)?;
+        let rotating = Self {
+            directory,
+            flight,
+            part: 0,
+            max_size,
+            keep,
+            fsync,
+            file,
+            size: 0,
+            unsynced: false,
+            urgent: false,
+            last_sync: Instant::now(),
+        };
+        rotating.remove_old_files()?;
+        Ok(rotating)
+    }
+
+    fn create(directory: &Path, flight: u32, part: u32) -> Result<BufWriter<File>> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let path = directory.join(format!("drosix-{:04}-{:03}-{}.log", flight, part, timestamp));
+        Ok(BufWriter::new(File::create(&path).with_context(|| format!("Creating {}", path.display()))?))
+    }
+
+    fn remove_old_files(&self) -> Result<()> {
+        let files = log_files(&self.directory)?;
+        for (_, path) in files.iter().rev().skip(self.keep.max(1)) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.sync()?;
+        self.part += 1;
+        self.file = Self::create(&self.directory, self.flight, self.part)?;
+        self.size = 0;
+        self.remove_old_files()
+    }
+
+    fn write_line(&mut self, line: &str, level: Option<Level>) -> std::io::Result<()> {
+        let length = line.len() as u64 + 1;
+        if self.size > 0 && self.size + length > self.max_size {
+            self.rotate().map_err(std::io::Error::other)?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += length;
+        self.unsynced = true;
+        self.urgent |= level.is_some_and(|level| level <= Level::Warn);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        self.unsynced = false;
+        self.urgent = false;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let sync = self.unsynced
+            && match self.fsync {
+                Fsync::Never => false,
+                Fsync::Periodic => self.urgent || self.last_sync.elapsed() >= FSYNC_PERIOD,
+                Fsync::Always => true,
+            };
+        if sync {
+            self.sync()
+        } else {
+            self.file.flush()
+        }
+    }
+}
+
+/// Log files of a directory sorted from the oldest with their flight counter
+fn log_files(directory: &Path) -> Result<Vec<(Option<u32>, PathBuf)>> {
+    let mut files: Vec<(Option<u32>, PathBuf)> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?;
+            let flight = name.strip_prefix("drosix-")?.split('-').next()?.parse().ok();
+            name.ends_with(".log").then_some((flight, path))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Sends each record in its own datagram: the magic, the record kind, a reserved byte and a little endian sequence
+/// number incremented for every record, followed by the record without line ending.
+struct UdpOutput {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    sequence: u32,
+    datagram: Vec<u8>,
+}
+
+impl UdpOutput {
+    fn open(destination: &str, port: u16) -> Result<Self> {
+        let destination = destination
+            .to_socket_addrs()
+            .with_context(|| format!("Resolving {}", destination))?
+            .next()
+            .ok_or_else(|| anyhow!("No address for {}", destination))?;
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port))).context("Binding log socket")?;
+        socket.set_broadcast(true)?;
+        Ok(Self {
+            socket,
+            destination,
+            sequence: 0,
+            datagram: Vec::new(),
+        })
+    }
+
+    fn send(&mut self, kind: RecordKind, record: &str) -> std::io::Result<()> {
+        let record = &record.as_bytes()[..record.len().min(MAX_DATAGRAM)];
+        self.datagram.clear();
+        self.datagram.extend([MAGIC[0], MAGIC[1], kind as u8, 0]);
+        self.datagram.extend(self.sequence.to_le_bytes());
+        self.datagram.extend(record);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.socket.send_to(&self.datagram, self.destination).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Sinks {
+        sinks: Vec<SinkConfig>,
+    }
+
+    #[test]
+    fn test_config() {
+        let sinks: Sinks = toml::from_str(
+            r#"
+            [[sinks]]
+            sink = "stdout"
+            level = "warn"
+            records = ["logs"]
+
+            [[sinks]]
+            sink = "file"
+            fsync = "always"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(sinks.sinks[0].kind, SinkKind::Stdout);
+        assert_eq!(sinks.sinks[0].records, [RecordKind::Logs]);
+        assert_eq!(
+            sinks.sinks[1].kind,
+            SinkKind::File {
+                directory: default_directory(),
+                max_size: default_max_size(),
+                keep: default_keep(),
+                fsync: Fsync::Always,
+            }
+        );
+
+        let sink = Sink::open(sinks.sinks[0].clone()).unwrap();
+        assert!(sink.accepts(RecordKind::Logs, Some(Level::Error)));
+        assert!(!sink.accepts(RecordKind::Logs, Some(Level::Info)));
+        assert!(!sink.accepts(RecordKind::Measures, None));
+
+        // Through the parameter file loader
+        let parameters = ::config::Config::builder()
+            .add_source(::config::File::from_str(
+                "[[log.sinks]]\nsink = \"file\"\nkeep = 3\nrecords = [\"logs\"]\n",
+                ::config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap();
+        let sinks: Vec<SinkConfig> = parameters.get("log.sinks").unwrap();
+        assert!(matches!(
+            sinks[0].kind,
+            SinkKind::File {
+                keep: 3,
+                ..
+            }
+        ));
+        assert_eq!(sinks[0].records, [RecordKind::Logs]);
+
+        // Parameters of the former file sink
+        assert!(toml::from_str::<SinkConfig>("sink = \"file\"\npath = \"drosix.log\"").is_err());
+    }
+
+    #[test]
+    fn test_udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let mut sink = Sink::open(SinkConfig {
+            kind: SinkKind::Udp {
+                destination: receiver.local_addr().unwrap().to_string(),
+                port: 0,
+            },
+            ..SinkConfig::stdout()
+        })
+        .unwrap();
+        let record = "x".repeat(3000);
+        sink.write_line(RecordKind::Logs, "first", Some(Level::Info)).unwrap();
+        sink.write_line(RecordKind::Measures, &record, None).unwrap();
+
+        let mut buffer = [0; 4096];
+        let size = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"DL\x00\x00\x00\x00\x00\x00first");
+        // Whole record in a single datagram
+        let size = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..8], b"DL\x01\x00\x01\x00\x00\x00");
+        assert_eq!(&buffer[8..size], record.as_bytes());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path().join("logs");
+        let names = || -> Vec<String> {
+            log_files(&directory)
+                .unwrap()
+                .iter()
+                .map(|(_, path)| path.file_name().unwrap().to_string_lossy()[..16].to_string())
+                .collect()
+        };
+
+        let mut file = RotatingFile::open(directory.clone(), 25, 2, Fsync::Always).unwrap();
+        for i in 0..5 {
+            file.write_line(&format!("record {}", i), None).unwrap();
+        }
+        file.flush().unwrap();
+        // 2 records of 9 bytes per file and the oldest part removed
+        assert_eq!(names(), ["drosix-0001-001-", "drosix-0001-002-"]);
+        let (_, last) = log_files(&directory).unwrap().pop().unwrap();
+        assert_eq!(fs::read_to_string(last).unwrap(), "record 4\n");
+
+        // The next start is a new flight
+        drop(file);
+        let file = RotatingFile::open(directory.clone(), 25, 2, Fsync::Never).unwrap();
+        assert_eq!(file.flight, 2);
+        assert_eq!(names(), ["drosix-0001-002-", "drosix-0002-000-"]);
+    }
+}
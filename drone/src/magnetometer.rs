@@ -0,0 +1,236 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use serde::Deserialize;
+
+use crate::config;
+use crate::types::Angles;
+
+/// MPU9250 address used to enable the I2C bypass toward the AK8963
+const MPU9250_ADDRESS: u8 = 0x68;
+const MPU9250_INT_PIN_CFG: u8 = 0x37;
+const MPU9250_BYPASS_EN: u8 = 0x02;
+
+const AK8963_ADDRESS: u8 = 0x0c;
+const AK8963_WIA: u8 = 0x00;
+const AK8963_ST1: u8 = 0x02;
+const AK8963_HXL: u8 = 0x03;
+const AK8963_CNTL1: u8 = 0x0a;
+const AK8963_ASAX: u8 = 0x10;
+const AK8963_DEVICE_ID: u8 = 0x48;
+const AK8963_POWER_DOWN: u8 = 0x00;
+const AK8963_FUSE_ROM: u8 = 0x0f;
+/// 16 bits output, continuous measurement mode 1 (8Hz)
+const AK8963_CONTINUOUS_8HZ: u8 = 0x12;
+/// 16 bits output, continuous measurement mode 2 (100Hz)
+const AK8963_CONTINUOUS_100HZ: u8 = 0x16;
+const AK8963_DATA_READY: u8 = 0x01;
+const AK8963_OVERFLOW: u8 = 0x08;
+/// Sensitivity in µT/LSB for 16 bits output
+const AK8963_SENSITIVITY: f32 = 0.15;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    InvalidDevice(u8),
+    NotReady,
+    Overflow,
+}
+
+impl<E: std::fmt::Debug> std::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::I2c(x) => write!(f, "Magnetometer bus error: {:?}", x),
+            Self::InvalidDevice(x) => write!(f, "Invalid magnetometer id: {:#x}", x),
+            Self::NotReady => write!(f, "Magnetometer data not ready"),
+            Self::Overflow => write!(f, "Magnetometer overflow"),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug> std::error::Error for Error<E> {}
+
+/// Magnetometer configuration stored in the parameter file under `[magnetometer]`.
+#[derive(Deserialize, Copy, Clone, Debug)]
+#[serde(default)]
+pub struct MagnetometerConfig {
+    /// Reads the magnetometer alongside the IMU
+    pub enabled: bool,
+    /// Fuses the heading into the yaw
+    pub fuse_yaw: bool,
+    /// Complementary filter gain used to fuse the heading with the DMP yaw
+    pub gain: f32,
+    /// Measurement rate in Hz: 8 or 100
+    pub rate: u16,
+}
+
+impl Default for MagnetometerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            fuse_yaw: false,
+            gain: 0.02,
+            rate: 8,
+        }
+    }
+}
+
+impl MagnetometerConfig {
+    /// Loads the magnetometer configuration from the parameter file.
+    pub fn load() -> Result<Self> {
+        let config: Self = config::load("magnetometer")?;
+        if !matches!(config.rate, 8 | 100) {
+            bail!("Invalid magnetometer rate {}Hz, expected 8 or 100", config.rate);
+        }
+        Ok(config)
+    }
+
+    /// Period at which the magnetometer provides a new measure
+    pub fn period(&self) -> Duration {
+        Duration::from_millis(1000 / u64::from(self.rate))
+    }
+}
+
+/// AK8963 magnetometer embedded in the MPU9250 and accessed through the I2C bypass.
+pub struct Ak8963<I2C> {
+    i2c: I2C,
+    adjustment: [f32; 3],
+}
+
+impl<I2C, E> Ak8963<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Enables the MPU9250 bypass then configures the AK8963 in continuous mode at 8Hz or else 100Hz.
+    pub fn new(mut i2c: I2C, rate: u16) -> Result<Self, Error<E>> {
+        let mut cfg = [0];
+        i2c.write_read(MPU9250_ADDRESS, &[MPU9250_INT_PIN_CFG], &mut cfg).map_err(Error::I2c)?;
+        i2c.write(MPU9250_ADDRESS, &[MPU9250_INT_PIN_CFG, cfg[0] | MPU9250_BYPASS_EN]).map_err(Error::I2c)?;
+
+        let mut id = [0];
+        i2c.write_read(AK8963_ADDRESS, &[AK8963_WIA], &mut id).map_err(Error::I2c)?;
+        if id[0] != AK8963_DEVICE_ID {
+            return Err(Error::InvalidDevice(id[0]));
+        }
+
+        let mut asa = [0; 3];
+        i2c.write(AK8963_ADDRESS, &[AK8963_CNTL1, AK8963_FUSE_ROM]).map_err(Error::I2c)?;
+        i2c.write_read(AK8963_ADDRESS, &[AK8963_ASAX], &mut asa).map_err(Error::I2c)?;
+        i2c.write(AK8963_ADDRESS, &[AK8963_CNTL1, AK8963_POWER_DOWN]).map_err(Error::I2c)?;
+        let mode = if rate == 8 {
+            AK8963_CONTINUOUS_8HZ
+        } else {
+            AK8963_CONTINUOUS_100HZ
+        };
+        i2c.write(AK8963_ADDRESS, &[AK8963_CNTL1, mode]).map_err(Error::I2c)?;
+
+        Ok(Self {
+            i2c,
+            adjustment: asa.map(|x| (f32::from(x) - 128.0) / 256.0 + 1.0),
+        })
+    }
+
+    /// Reads the magnetic field in µT expressed in the MPU9250 accelerometer and gyroscope frame.
+    pub fn read(&mut self) -> Result<[f32; 3], Error<E>> {
+        let mut st1 = [0];
+        self.i2c.write_read(AK8963_ADDRESS, &[AK8963_ST1], &mut st1).map_err(Error::I2c)?;
+        if st1[0] & AK8963_DATA_READY == 0 {
+            return Err(Error::NotReady);
+        }
+        // Reading ST2 (last byte) releases the data registers
+        let mut data = [0; 7];
+        self.i2c.write_read(AK8963_ADDRESS, &[AK8963_HXL], &mut data).map_err(Error::I2c)?;
+        if data[6] & AK8963_OVERFLOW != 0 {
+            return Err(Error::Overflow);
+        }
+        let raw = [0, 1, 2].map(|axis| {
+            f32::from(i16::from_le_bytes([data[2 * axis], data[2 * axis + 1]]))
+                * self.adjustment[axis]
+                * AK8963_SENSITIVITY
+        });
+        // AK8963 x and y axes are swapped and its z axis is inverted compared to the accelerometer
+        Ok([raw[1], raw[0], -raw[2]])
+    }
+}
+
+/// Tilt compensated heading in radian computed from a magnetic field expressed in the drone frame.
+/// The heading follows the yaw convention: 0 when the x axis points toward the magnetic north, positive to the
+/// left.
+pub fn heading(mag: &[f32; 3], attitude: &Angles) -> f32 {
+    let (sr, cr) = attitude.roll.sin_cos();
+    let (sp, cp) = attitude.pitch.sin_cos();
+    // Rotates the field back to a level frame keeping the heading
+    let y = mag[1] * cr - mag[2] * sr;
+    let z = mag[1] * sr + mag[2] * cr;
+    let x = mag[0] * cp + z * sp;
+    f32::atan2(-y, x)
+}
+
+/// Complementary filter slowly pulling the DMP yaw toward the magnetic heading.
+#[derive(Default)]
+pub struct HeadingFusion {
+    offset: f32,
+}
+
+impl HeadingFusion {
+    /// Returns the fused yaw.
+    pub fn fuse(&mut self, yaw: f32, heading: f32, gain: f32) -> f32 {
+        let fused = wrap_angle(yaw + self.offset);
+        self.offset = wrap_angle(self.offset + gain * wrap_angle(heading - fused));
+        wrap_angle(yaw + self.offset)
+    }
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = (angle + std::f32::consts::PI).rem_euclid(2.0 * std::f32::consts::PI);
+    wrapped - std::f32::consts::PI
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Earth magnetic field with a 60° inclination in the earth frame
+    const FIELD: [f32; 3] = [25.0, 0.0, -43.3];
+
+    /// Field measured in the drone frame for the given attitude (yaw * pitch * roll)
+    fn measure(roll: f32, pitch: f32, yaw: f32) -> [f32; 3] {
+        let (sr, cr) = roll.sin_cos();
+        let (sp, cp) = pitch.sin_cos();
+        let (sy, cy) = yaw.sin_cos();
+        // Inverse yaw
+        let (x, y, z) = (cy * FIELD[0] + sy * FIELD[1], -sy * FIELD[0] + cy * FIELD[1], FIELD[2]);
+        // Inverse pitch
+        let (x, z) = (cp * x - sp * z, sp * x + cp * z);
+        // Inverse roll
+        let (y, z) = (cr * y + sr * z, -sr * y + cr * z);
+        [x, y, z]
+    }
+
+    #[test]
+    fn test_heading() {
+        for yaw in [-2.5, -0.7, 0.0, 0.4, 1.6, 3.0] {
+            for (roll, pitch) in [(0.0, 0.0), (0.3, -0.2), (-0.4, 0.5)] {
+                let attitude = Angles {
+                    roll,
+                    pitch,
+                    yaw: 0.0,
+                };
+                let heading = heading(&measure(roll, pitch, yaw), &attitude);
+                assert!((heading - yaw).abs() < 1e-4, "heading {} yaw {}", heading, yaw);
+            }
+        }
+    }
+
+    #[test]
+    fn test_heading_fusion() {
+        let mut fusion = HeadingFusion::default();
+        let mut yaw = 0.0;
+        // DMP yaw stuck at 3.0 while the heading is -3.0 across the wrap around
+        for _ in 0..1000 {
+            yaw = fusion.fuse(3.0, -3.0, 0.02);
+        }
+        assert!((yaw + 3.0).abs() < 1e-3, "yaw {}", yaw);
+    }
+}
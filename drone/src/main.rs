@@ -4,16 +4,26 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use thread_priority::{
     RealtimeThreadSchedulePolicy, ScheduleParams, ThreadBuilder, ThreadPriority, ThreadSchedulePolicy
 };
 
+use drone::arbiter::Source;
+use drone::calibration::{calibrate_imu, calibrate_mag};
+use drone::config::CONFIG_FILE;
+use drone::exporter::{Exporter, ExporterConfig};
 use drone::flight_controller::FlightController;
+use drone::harness::run_plugin_test;
 use drone::log::Logger;
-use drone::plugin::run_plugin;
-use drone::remote::remote;
-use drone::types::Command;
+use drone::mavlink::{MavlinkConfig, MavlinkEndpoint};
+use drone::plugin::{run_plugin, PluginConfig};
+use drone::remote::{remote, RemoteConfig};
+use drone::server::{Server, ServerConfig};
+use drone::types::{command_channel, Command, TelemetryHub};
+
+/// Time given to the plugin to return from its hooks on shutdown
+const PLUGIN_STOP_TIMEOUT: Duration = Duration::from_secs(2);
 
 fn main() {
     let stop = Arc::new(AtomicBool::new(false));
@@ -23,15 +33,34 @@ fn main() {
     }
 
     let args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == "calibrate-imu") {
+        calibrate_imu().expect("IMU calibration failed");
+        return;
+    }
+    if args.get(1).is_some_and(|arg| arg == "calibrate-mag") {
+        calibrate_mag().expect("Magnetometer calibration failed");
+        return;
+    }
+    if args.get(1).is_some_and(|arg| arg == "plugin-test") {
+        let path = args.get(2).expect("Usage: drone plugin-test <plugin.py> [timeout in s]");
+        let timeout = args.get(3).map_or(60.0, |timeout| timeout.parse().expect("Invalid timeout"));
+        run_plugin_test(path, Duration::from_secs_f32(timeout)).expect("Plugin test failed");
+        return;
+    }
     let path = args.get(1).and_then(|arg| (arg == "--plugin").then(|| args.get(2).map(|x| x.clone()))).flatten();
 
-    let mut log_sink = Logger::init();
+    let mut log_sink = Logger::init().expect("Loading log parameters");
+    let server_config = ServerConfig::load().expect("Loading server parameters");
+    let mavlink_config = MavlinkConfig::load().expect("Loading MAVLink parameters");
+    let remote_config = RemoteConfig::load().expect("Loading remote parameters");
+    let plugin_config = PluginConfig::load().expect("Loading plugin parameters");
+    let exporter_config = ExporterConfig::load().expect("Loading exporter parameters");
 
-    let (answer_tx, _answer_rx) = channel();
+    let (answer_tx, answer_rx) = channel();
 
-    let (command_tx, command_rx) = channel();
+    let (command_tx, command_rx) = command_channel();
 
-    let mut controller = FlightController::new(command_rx, answer_tx);
+    let mut controller = FlightController::new(command_rx, answer_tx).expect("Loading flight controller parameters");
 
     let drone = ThreadBuilder::default()
         .name("controller")
@@ -42,27 +71,88 @@ fn main() {
         .spawn_careless(move || controller.run())
         .unwrap();
 
-    if let Some(plugin_path) = path.clone() {
-        let plugin_tx = command_tx.clone();
-        let _plugin = thread::Builder::new()
+    let telemetry = TelemetryHub::default();
+    let plugin = path.map(|plugin_path| {
+        let plugin_tx = command_tx.with_source(Source::Plugin);
+        let plugin_telemetry = telemetry.clone();
+        let plugin_stop = Arc::clone(&stop);
+        thread::Builder::new()
             .name("plugin".into())
-            .spawn(move || run_plugin(plugin_path.clone(), plugin_tx))
-            .unwrap();
-    } else {
-        let remote_tx = command_tx.clone();
-        let _remote = thread::Builder::new().name("remote".into()).spawn(move || remote(remote_tx)).unwrap();
+            .spawn(move || run_plugin(plugin_path, plugin_config, plugin_tx, plugin_telemetry, plugin_stop))
+            .unwrap()
+    });
+    let remote_tx = command_tx.with_source(Source::Pilot);
+    let _remote = thread::Builder::new().name("remote".into()).spawn(move || remote(remote_config, remote_tx)).unwrap();
+
+    if server_config.enabled {
+        match Server::bind(
+            &server_config.address,
+            command_tx.with_source(Source::Network),
+            telemetry.clone(),
+            CONFIG_FILE.into(),
+        ) {
+            Ok(server) => {
+                let server_stop = Arc::clone(&stop);
+                let _server =
+                    thread::Builder::new().name("server".into()).spawn(move || server.run(server_stop)).unwrap();
+            },
+            Err(err) => log::error!("{:#}", err),
+        }
+    }
+
+    if exporter_config.enabled {
+        match Exporter::bind(&exporter_config.address, telemetry.clone()) {
+            Ok(mut exporter) => {
+                let exporter_stop = Arc::clone(&stop);
+                let _exporter =
+                    thread::Builder::new().name("exporter".into()).spawn(move || exporter.run(exporter_stop)).unwrap();
+            },
+            Err(err) => log::error!("{:#}", err),
+        }
+    }
+
+    if mavlink_config.enabled {
+        match MavlinkEndpoint::bind(
+            &mavlink_config.address,
+            &mavlink_config.ground_station,
+            command_tx.with_source(Source::Network),
+            telemetry.clone(),
+            CONFIG_FILE.into(),
+        ) {
+            Ok(mut endpoint) => {
+                let mavlink_stop = Arc::clone(&stop);
+                let _mavlink =
+                    thread::Builder::new().name("mavlink".into()).spawn(move || endpoint.run(mavlink_stop)).unwrap();
+            },
+            Err(err) => log::error!("{:#}", err),
+        }
     }
 
     while !stop.load(Ordering::Relaxed) {
         log_sink.handle_logs();
+        for answer in answer_rx.try_iter() {
+            telemetry.publish(answer);
+        }
         thread::sleep(Duration::from_millis(10));
     }
 
     eprintln!("ctrl-c received");
-    // We want to crash anyway if we got here
+    // We want to crash anyway if we got here, the main thread sender is the failsafe one
     command_tx.send(Command::Stop).unwrap();
 
     let _ = drone.join();
+    if let Some(plugin) = plugin {
+        // The plugin hooks are cooperative, do not hang on a plugin that never returns
+        let start = Instant::now();
+        while !plugin.is_finished() && start.elapsed() < PLUGIN_STOP_TIMEOUT {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if plugin.is_finished() {
+            let _ = plugin.join();
+        } else {
+            log::error!("Plugin did not stop within {:?}", PLUGIN_STOP_TIMEOUT);
+        }
+    }
     // let _ = remote.join();
     log_sink.handle_logs();
 }
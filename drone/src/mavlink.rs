@@ -0,0 +1,1517 @@
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+use crate::battery::BatteryLevel;
+use crate::config;
+use crate::types::{Angles, Command, CommandSender, FlightCommand, PidAxis, PidConfig, Telemetry, TelemetryHub};
+
+const MAGIC_V1: u8 = 0xfe;
+const MAGIC_V2: u8 = 0xfd;
+/// MAVLink v2 incompatibility flag of the signed frames
+const SIGNED: u8 = 0x01;
+const SIGNATURE_LENGTH: usize = 13;
+const SYSTEM_ID: u8 = 1;
+/// MAV_COMP_ID_AUTOPILOT1
+const COMPONENT_ID: u8 = 1;
+/// Largest parameter id
+const PARAM_ID_LENGTH: usize = 16;
+const STATUS_TEXT_LENGTH: usize = 50;
+
+const HEARTBEAT: u32 = 0;
+const SYS_STATUS: u32 = 1;
+const PARAM_REQUEST_READ: u32 = 20;
+const PARAM_REQUEST_LIST: u32 = 21;
+const PARAM_VALUE: u32 = 22;
+const PARAM_SET: u32 = 23;
+const ATTITUDE: u32 = 30;
+const MANUAL_CONTROL: u32 = 69;
+const COMMAND_LONG: u32 = 76;
+const COMMAND_ACK: u32 = 77;
+const SET_ATTITUDE_TARGET: u32 = 82;
+const BATTERY_STATUS: u32 = 147;
+const STATUSTEXT: u32 = 253;
+
+const MAV_TYPE_QUADROTOR: u8 = 2;
+const MAV_AUTOPILOT_GENERIC: u8 = 0;
+const MAV_MODE_FLAG_SAFETY_ARMED: u8 = 0x80;
+const MAV_MODE_FLAG_MANUAL_INPUT_ENABLED: u8 = 0x40;
+const MAV_STATE_STANDBY: u8 = 3;
+const MAV_STATE_ACTIVE: u8 = 4;
+const MAV_STATE_EMERGENCY: u8 = 6;
+const MAV_CMD_COMPONENT_ARM_DISARM: u16 = 400;
+const MAV_RESULT_ACCEPTED: u8 = 0;
+const MAV_RESULT_UNSUPPORTED: u8 = 3;
+const MAV_RESULT_FAILED: u8 = 4;
+const MAV_PARAM_TYPE_REAL32: u8 = 9;
+/// 3D gyro, 3D accelerometer, 3D magnetometer, attitude stabilization and battery
+const SENSORS: u32 = 0x01 | 0x02 | 0x04 | 0x400 | 0x2000000;
+/// SET_ATTITUDE_TARGET type mask bits
+const IGNORE_THRUST: u8 = 0x40;
+const IGNORE_ATTITUDE: u8 = 0x80;
+
+const MAV_SEVERITY_CRITICAL: u8 = 2;
+const MAV_SEVERITY_WARNING: u8 = 4;
+const MAV_SEVERITY_INFO: u8 = 6;
+
+/// Period of the heartbeat and of the status messages
+const HEARTBEAT_PERIOD: Duration = Duration::from_secs(1);
+/// Period at which the endpoint checks for telemetry updates and for the stop flag
+const POLL_PERIOD: Duration = Duration::from_millis(20);
+/// Largest angle of the attitude commands matching a full stick, see the flight controller
+const MAX_ANGLE: f32 = 15.0;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The buffer does not hold a complete frame
+    Truncated,
+    InvalidMagic(u8),
+    /// Message not handled, its CRC cannot be checked
+    UnknownMessage(u32),
+    Crc,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "Truncated MAVLink frame"),
+            Self::InvalidMagic(x) => write!(f, "Invalid MAVLink magic: {:#x}", x),
+            Self::UnknownMessage(x) => write!(f, "Unknown MAVLink message: {}", x),
+            Self::Crc => write!(f, "Invalid MAVLink CRC"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// MAVLink endpoint configuration stored in the parameter file under `[mavlink]`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MavlinkConfig {
+    pub enabled: bool,
+    /// Local UDP address
+    pub address: String,
+    /// Ground station address used until a ground station talks to the drone
+    pub ground_station: String,
+}
+
+impl Default for MavlinkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "0.0.0.0:14555".into(),
+            ground_station: "255.255.255.255:14550".into(),
+        }
+    }
+}
+
+impl MavlinkConfig {
+    /// Loads the MAVLink configuration from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("mavlink")
+    }
+}
+
+/// Messages of the common dialect handled by the drone
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Heartbeat {
+        kind: u8,
+        autopilot: u8,
+        base_mode: u8,
+        custom_mode: u32,
+        system_status: u8,
+    },
+    SysStatus {
+        /// Sensor bitmask used for the present, enabled and healthy sensors
+        sensors: u32,
+        /// Main loop load in ‰
+        load: u16,
+        /// Battery voltage in mV, `u16::MAX` if unknown
+        voltage: u16,
+        /// Battery current in cA, -1 if unknown
+        current: i16,
+        /// Remaining battery in %, -1 if unknown
+        remaining: i8,
+        errors: u16,
+    },
+    ParamRequestRead {
+        target_system: u8,
+        target_component: u8,
+        id: String,
+        /// Index of the parameter, -1 to use the id
+        index: i16,
+    },
+    ParamRequestList {
+        target_system: u8,
+        target_component: u8,
+    },
+    ParamValue {
+        id: String,
+        value: f32,
+        kind: u8,
+        count: u16,
+        index: u16,
+    },
+    ParamSet {
+        target_system: u8,
+        target_component: u8,
+        id: String,
+        value: f32,
+        kind: u8,
+    },
+    /// Attitude in rad and rates in rad/s in the NED frame
+    Attitude {
+        time_boot_ms: u32,
+        roll: f32,
+        pitch: f32,
+        yaw: f32,
+        rollspeed: f32,
+        pitchspeed: f32,
+        yawspeed: f32,
+    },
+    /// Sticks in [-1000, 1000]
+    ManualControl {
+        target: u8,
+        x: i16,
+        y: i16,
+        z: i16,
+        r: i16,
+        buttons: u16,
+    },
+    CommandLong {
+        target_system: u8,
+        target_component: u8,
+        command: u16,
+        confirmation: u8,
+        params: [f32; 7],
+    },
+    CommandAck {
+        command: u16,
+        result: u8,
+    },
+    /// Attitude quaternion in the NED frame and thrust in [0, 1]
+    SetAttitudeTarget {
+        time_boot_ms: u32,
+        target_system: u8,
+        target_component: u8,
+        type_mask: u8,
+        q: [f32; 4],
+        rates: [f32; 3],
+        thrust: f32,
+    },
+    BatteryStatus {
+        /// Consumed charge in mAh, -1 if unknown
+        consumed: i32,
+        /// Pack voltage in mV, `u16::MAX` if unknown
+        voltage: u16,
+        /// Current in cA, -1 if unknown
+        current: i16,
+        /// Remaining battery in %, -1 if unknown
+        remaining: i8,
+    },
+    StatusText {
+        severity: u8,
+        text: String,
+    },
+}
+
+/// Extra CRC seed and payload length of the known messages
+fn message_info(id: u32) -> Option<(u8, usize)> {
+    match id {
+        HEARTBEAT => Some((50, 9)),
+        SYS_STATUS => Some((124, 31)),
+        PARAM_REQUEST_READ => Some((214, 20)),
+        PARAM_REQUEST_LIST => Some((159, 2)),
+        PARAM_VALUE => Some((220, 25)),
+        PARAM_SET => Some((168, 23)),
+        ATTITUDE => Some((39, 28)),
+        MANUAL_CONTROL => Some((243, 11)),
+        COMMAND_LONG => Some((152, 33)),
+        COMMAND_ACK => Some((143, 3)),
+        SET_ATTITUDE_TARGET => Some((49, 39)),
+        BATTERY_STATUS => Some((154, 36)),
+        STATUSTEXT => Some((83, 51)),
+        _ => None,
+    }
+}
+
+/// CRC-16/MCRF4XX (X.25) used by MAVLink
+pub fn crc(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |crc, byte| {
+        let tmp = byte ^ (crc as u8);
+        let tmp = tmp ^ (tmp << 4);
+        (crc >> 8) ^ (u16::from(tmp) << 8) ^ (u16::from(tmp) << 3) ^ (u16::from(tmp) >> 4)
+    })
+}
+
+/// Little endian payload writer, the fields are serialized from the largest to the smallest type
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(mut self, bytes: &[u8]) -> Self {
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    fn text(self, text: &str, length: usize) -> Self {
+        let mut buffer = vec![0; length];
+        let text = &text.as_bytes()[..text.len().min(length)];
+        buffer[..text.len()].copy_from_slice(text);
+        self.bytes(&buffer)
+    }
+}
+
+/// Little endian payload reader completing the payloads truncated by MAVLink v2 with zeros
+struct Reader<'a> {
+    payload: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> [u8; N] {
+        let mut bytes = [0; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.payload.get(self.position + i).copied().unwrap_or(0);
+        }
+        self.position += N;
+        bytes
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.bytes::<1>()[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.bytes())
+    }
+
+    fn i16(&mut self) -> i16 {
+        i16::from_le_bytes(self.bytes())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.bytes())
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.bytes())
+    }
+
+    fn f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.bytes())
+    }
+
+    fn text<const N: usize>(&mut self) -> String {
+        let bytes = self.bytes::<N>();
+        let end = bytes.iter().position(|x| *x == 0).unwrap_or(N);
+        String::from_utf8_lossy(&bytes[..end]).into()
+    }
+}
+
+impl Message {
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::Heartbeat {
+                ..
+            } => HEARTBEAT,
+            Self::SysStatus {
+                ..
+            } => SYS_STATUS,
+            Self::ParamRequestRead {
+                ..
+            } => PARAM_REQUEST_READ,
+            Self::ParamRequestList {
+                ..
+            } => PARAM_REQUEST_LIST,
+            Self::ParamValue {
+                ..
+            } => PARAM_VALUE,
+            Self::ParamSet {
+                ..
+            } => PARAM_SET,
+            Self::Attitude {
+                ..
+            } => ATTITUDE,
+            Self::ManualControl {
+                ..
+            } => MANUAL_CONTROL,
+            Self::CommandLong {
+                ..
+            } => COMMAND_LONG,
+            Self::CommandAck {
+                ..
+            } => COMMAND_ACK,
+            Self::SetAttitudeTarget {
+                ..
+            } => SET_ATTITUDE_TARGET,
+            Self::BatteryStatus {
+                ..
+            } => BATTERY_STATUS,
+            Self::StatusText {
+                ..
+            } => STATUSTEXT,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        let w = Writer::default();
+        let w = match self {
+            Self::Heartbeat {
+                kind,
+                autopilot,
+                base_mode,
+                custom_mode,
+                system_status,
+            } => w.bytes(&custom_mode.to_le_bytes()).bytes(&[*kind, *autopilot, *base_mode, *system_status, 3]),
+            Self::SysStatus {
+                sensors,
+                load,
+                voltage,
+                current,
+                remaining,
+                errors,
+            } => w
+                .bytes(&sensors.to_le_bytes())
+                .bytes(&sensors.to_le_bytes())
+                .bytes(&sensors.to_le_bytes())
+                .bytes(&load.to_le_bytes())
+                .bytes(&voltage.to_le_bytes())
+                .bytes(&current.to_le_bytes())
+                // Communication drop rate and errors
+                .bytes(&[0; 4])
+                .bytes(&errors.to_le_bytes())
+                .bytes(&[0; 6])
+                .bytes(&remaining.to_le_bytes()),
+            Self::ParamRequestRead {
+                target_system,
+                target_component,
+                id,
+                index,
+            } => w.bytes(&index.to_le_bytes()).bytes(&[*target_system, *target_component]).text(id, PARAM_ID_LENGTH),
+            Self::ParamRequestList {
+                target_system,
+                target_component,
+            } => w.bytes(&[*target_system, *target_component]),
+            Self::ParamValue {
+                id,
+                value,
+                kind,
+                count,
+                index,
+            } => w
+                .bytes(&value.to_le_bytes())
+                .bytes(&count.to_le_bytes())
+                .bytes(&index.to_le_bytes())
+                .text(id, PARAM_ID_LENGTH)
+                .bytes(&[*kind]),
+            Self::ParamSet {
+                target_system,
+                target_component,
+                id,
+                value,
+                kind,
+            } => w
+                .bytes(&value.to_le_bytes())
+                .bytes(&[*target_system, *target_component])
+                .text(id, PARAM_ID_LENGTH)
+                .bytes(&[*kind]),
+            Self::Attitude {
+                time_boot_ms,
+                roll,
+                pitch,
+                yaw,
+                rollspeed,
+                pitchspeed,
+                yawspeed,
+            } => [roll, pitch, yaw, rollspeed, pitchspeed, yawspeed]
+                .iter()
+                .fold(w.bytes(&time_boot_ms.to_le_bytes()), |w, x| w.bytes(&x.to_le_bytes())),
+            Self::ManualControl {
+                target,
+                x,
+                y,
+                z,
+                r,
+                buttons,
+            } => [x, y, z, r]
+                .iter()
+                .fold(w, |w, x| w.bytes(&x.to_le_bytes()))
+                .bytes(&buttons.to_le_bytes())
+                .bytes(&[*target]),
+            Self::CommandLong {
+                target_system,
+                target_component,
+                command,
+                confirmation,
+                params,
+            } => params.iter().fold(w, |w, x| w.bytes(&x.to_le_bytes())).bytes(&command.to_le_bytes()).bytes(&[
+                *target_system,
+                *target_component,
+                *confirmation,
+            ]),
+            Self::CommandAck {
+                command,
+                result,
+            } => w.bytes(&command.to_le_bytes()).bytes(&[*result]),
+            Self::SetAttitudeTarget {
+                time_boot_ms,
+                target_system,
+                target_component,
+                type_mask,
+                q,
+                rates,
+                thrust,
+            } => q
+                .iter()
+                .chain(rates)
+                .chain([thrust])
+                .fold(w.bytes(&time_boot_ms.to_le_bytes()), |w, x| w.bytes(&x.to_le_bytes()))
+                .bytes(&[*target_system, *target_component, *type_mask]),
+            Self::BatteryStatus {
+                consumed,
+                voltage,
+                current,
+                remaining,
+            } => {
+                let w = w
+                    .bytes(&consumed.to_le_bytes())
+                    // Energy unknown
+                    .bytes(&(-1i32).to_le_bytes())
+                    // Temperature unknown
+                    .bytes(&i16::MAX.to_le_bytes())
+                    .bytes(&voltage.to_le_bytes());
+                // Unused cells
+                (1..10)
+                    .fold(w, |w, _| w.bytes(&u16::MAX.to_le_bytes()))
+                    .bytes(&current.to_le_bytes())
+                    // Id, function and LiPo type
+                    .bytes(&[0, 0, 1])
+                    .bytes(&remaining.to_le_bytes())
+            },
+            Self::StatusText {
+                severity,
+                text,
+            } => w.bytes(&[*severity]).text(text, STATUS_TEXT_LENGTH),
+        };
+        w.0
+    }
+
+    fn parse(id: u32, payload: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader {
+            payload,
+            position: 0,
+        };
+        let message = match id {
+            HEARTBEAT => {
+                let custom_mode = r.u32();
+                Self::Heartbeat {
+                    custom_mode,
+                    kind: r.u8(),
+                    autopilot: r.u8(),
+                    base_mode: r.u8(),
+                    system_status: r.u8(),
+                }
+            },
+            SYS_STATUS => {
+                let sensors = r.u32();
+                r.bytes::<8>();
+                let load = r.u16();
+                let voltage = r.u16();
+                let current = r.i16();
+                r.bytes::<4>();
+                let errors = r.u16();
+                r.bytes::<6>();
+                Self::SysStatus {
+                    sensors,
+                    load,
+                    voltage,
+                    current,
+                    errors,
+                    remaining: r.u8() as i8,
+                }
+            },
+            PARAM_REQUEST_READ => {
+                let index = r.i16();
+                Self::ParamRequestRead {
+                    index,
+                    target_system: r.u8(),
+                    target_component: r.u8(),
+                    id: r.text::<PARAM_ID_LENGTH>(),
+                }
+            },
+            PARAM_REQUEST_LIST => Self::ParamRequestList {
+                target_system: r.u8(),
+                target_component: r.u8(),
+            },
+            PARAM_VALUE => Self::ParamValue {
+                value: r.f32(),
+                count: r.u16(),
+                index: r.u16(),
+                id: r.text::<PARAM_ID_LENGTH>(),
+                kind: r.u8(),
+            },
+            PARAM_SET => Self::ParamSet {
+                value: r.f32(),
+                target_system: r.u8(),
+                target_component: r.u8(),
+                id: r.text::<PARAM_ID_LENGTH>(),
+                kind: r.u8(),
+            },
+            ATTITUDE => Self::Attitude {
+                time_boot_ms: r.u32(),
+                roll: r.f32(),
+                pitch: r.f32(),
+                yaw: r.f32(),
+                rollspeed: r.f32(),
+                pitchspeed: r.f32(),
+                yawspeed: r.f32(),
+            },
+            MANUAL_CONTROL => Self::ManualControl {
+                x: r.i16(),
+                y: r.i16(),
+                z: r.i16(),
+                r: r.i16(),
+                buttons: r.u16(),
+                target: r.u8(),
+            },
+            COMMAND_LONG => {
+                let params = [(); 7].map(|_| r.f32());
+                Self::CommandLong {
+                    params,
+                    command: r.u16(),
+                    target_system: r.u8(),
+                    target_component: r.u8(),
+                    confirmation: r.u8(),
+                }
+            },
+            COMMAND_ACK => Self::CommandAck {
+                command: r.u16(),
+                result: r.u8(),
+            },
+            SET_ATTITUDE_TARGET => Self::SetAttitudeTarget {
+                time_boot_ms: r.u32(),
+                q: [(); 4].map(|_| r.f32()),
+                rates: [(); 3].map(|_| r.f32()),
+                thrust: r.f32(),
+                target_system: r.u8(),
+                target_component: r.u8(),
+                type_mask: r.u8(),
+            },
+            BATTERY_STATUS => {
+                let consumed = r.i32();
+                r.bytes::<6>();
+                let voltage = r.u16();
+                r.bytes::<18>();
+                let current = r.i16();
+                r.bytes::<3>();
+                Self::BatteryStatus {
+                    consumed,
+                    voltage,
+                    current,
+                    remaining: r.u8() as i8,
+                }
+            },
+            STATUSTEXT => Self::StatusText {
+                severity: r.u8(),
+                text: r.text::<STATUS_TEXT_LENGTH>(),
+            },
+            x => return Err(Error::UnknownMessage(x)),
+        };
+        Ok(message)
+    }
+}
+
+/// MAVLink frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub sequence: u8,
+    pub system: u8,
+    pub component: u8,
+    pub message: Message,
+}
+
+impl Frame {
+    /// Serializes the frame as MAVLink v1, all the messages sent by the drone have an 8 bits id
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = self.message.payload();
+        let mut frame =
+            vec![MAGIC_V1, payload.len() as u8, self.sequence, self.system, self.component, self.message.id() as u8];
+        frame.extend(payload);
+        let (extra, _) = message_info(self.message.id()).expect("Known message");
+        let crc = crc(&[&frame[1..], &[extra]].concat());
+        frame.extend(crc.to_le_bytes());
+        frame
+    }
+
+    /// Parses a MAVLink v1 or v2 frame at the start of the buffer and returns it with its length
+    pub fn decode(buffer: &[u8]) -> Result<(Self, usize), Error> {
+        let magic = *buffer.first().ok_or(Error::Truncated)?;
+        let length = usize::from(*buffer.get(1).ok_or(Error::Truncated)?);
+        let (header, id, signature) = match magic {
+            MAGIC_V1 => (6, buffer.get(5).map(|x| u32::from(*x)), 0),
+            MAGIC_V2 => {
+                let signature = buffer.get(2).map_or(0, |flags| {
+                    if flags & SIGNED != 0 {
+                        SIGNATURE_LENGTH
+                    } else {
+                        0
+                    }
+                });
+                let id = buffer.get(7..10).map(|x| u32::from_le_bytes([x[0], x[1], x[2], 0]));
+                (10, id, signature)
+            },
+            x => return Err(Error::InvalidMagic(x)),
+        };
+        let total = header + length + 2 + signature;
+        if buffer.len() < total {
+            return Err(Error::Truncated);
+        }
+        let id = id.ok_or(Error::Truncated)?;
+        let (extra, _) = message_info(id).ok_or(Error::UnknownMessage(id))?;
+        let end = header + length;
+        let expected = u16::from_le_bytes([buffer[end], buffer[end + 1]]);
+        if crc(&[&buffer[1..end], &[extra]].concat()) != expected {
+            return Err(Error::Crc);
+        }
+        let (sequence, system, component) = match magic {
+            MAGIC_V1 => (buffer[2], buffer[3], buffer[4]),
+            _ => (buffer[4], buffer[5], buffer[6]),
+        };
+        let frame = Self {
+            sequence,
+            system,
+            component,
+            message: Message::parse(id, &buffer[header..end])?,
+        };
+        Ok((frame, total))
+    }
+}
+
+/// Parses all the frames of a datagram skipping the invalid and unknown ones
+pub fn decode_all(mut buffer: &[u8]) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    while !buffer.is_empty() {
+        match Frame::decode(buffer) {
+            Ok((frame, length)) => {
+                frames.push(frame);
+                buffer = &buffer[length..];
+            },
+            Err(Error::Truncated) => break,
+            Err(err) => {
+                if !matches!(err, Error::UnknownMessage(_)) {
+                    log::debug!("{}", err);
+                }
+                // Resynchronizes on the next magic
+                let next = buffer[1..].iter().position(|x| *x == MAGIC_V1 || *x == MAGIC_V2);
+                match next {
+                    Some(next) => buffer = &buffer[next + 1..],
+                    None => break,
+                }
+            },
+        }
+    }
+    frames
+}
+
+/// Converts a NED quaternion into roll, pitch and yaw in rad
+fn quat_to_euler([w, x, y, z]: [f32; 4]) -> (f32, f32, f32) {
+    let roll = (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+    let pitch = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin();
+    let yaw = (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z));
+    (roll, pitch, yaw)
+}
+
+/// Converts an incoming message into a flight controller command.
+/// The drone frame is x forward, y left and z up while MAVLink uses the NED frame.
+fn to_command(message: &Message) -> Option<Command> {
+    match *message {
+        Message::CommandLong {
+            command: MAV_CMD_COMPONENT_ARM_DISARM,
+            params,
+            ..
+        } => Some(Command::Armed(params[0] > 0.5)),
+        Message::ManualControl {
+            x,
+            y,
+            z,
+            r,
+            ..
+        } => {
+            let stick = |x: i16| (f32::from(x) / 1000.0).clamp(-1.0, 1.0);
+            Some(Command::Flight(FlightCommand {
+                thrust: stick(z).max(0.0),
+                angles: Angles {
+                    roll: stick(y),
+                    pitch: stick(x),
+                    yaw: -stick(r),
+                },
+            }))
+        },
+        Message::SetAttitudeTarget {
+            type_mask,
+            q,
+            thrust,
+            ..
+        } => {
+            let (roll, pitch, yaw) = if type_mask & IGNORE_ATTITUDE == 0 {
+                quat_to_euler(q)
+            } else {
+                (0.0, 0.0, 0.0)
+            };
+            if !(thrust.is_finite() && roll.is_finite() && pitch.is_finite() && yaw.is_finite()) {
+                return None;
+            }
+            let stick = |x: f32| (x / MAX_ANGLE.to_radians()).clamp(-1.0, 1.0);
+            let angles = Angles {
+                roll: stick(roll),
+                pitch: stick(-pitch),
+                yaw: stick(-yaw),
+            };
+            if type_mask & IGNORE_THRUST == 0 {
+                Some(Command::Flight(FlightCommand {
+                    thrust: thrust.clamp(0.0, 1.0),
+                    angles,
+                }))
+            } else {
+                // A ground station streaming only the attitude keeps the current thrust
+                Some(Command::Attitude(angles))
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Flattens the numeric parameters of the parameter file into `section.key` ids sorted by id.
+/// The ids longer than the 16 characters allowed by MAVLink are not exposed.
+fn flatten(table: &toml::Table, prefix: &str, params: &mut Vec<(String, f32)>) {
+    for (key, value) in table {
+        let id = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        let value = match value {
+            toml::Value::Table(table) => {
+                flatten(table, &id, params);
+                continue;
+            },
+            toml::Value::Float(x) => *x as f32,
+            toml::Value::Integer(x) => *x as f32,
+            toml::Value::Boolean(x) => f32::from(u8::from(*x)),
+            _ => continue,
+        };
+        if id.len() <= PARAM_ID_LENGTH {
+            params.push((id, value));
+        }
+    }
+}
+
+fn read_params(path: &PathBuf) -> Result<toml::Table> {
+    fs::read_to_string(path).context("Reading parameter file")?.parse::<toml::Table>().context("Parsing parameter file")
+}
+
+/// MAVLink endpoint streaming the telemetry to a ground station and accepting its commands over UDP.
+pub struct MavlinkEndpoint {
+    socket: UdpSocket,
+    /// Last ground station heard or the configured one
+    peer: SocketAddr,
+    sequence: u8,
+    command_tx: CommandSender,
+    telemetry: TelemetryHub,
+    /// Parameter file exposed through the PARAM protocol
+    params: PathBuf,
+    start: Instant,
+}
+
+impl MavlinkEndpoint {
+    pub fn bind(
+        address: &str,
+        ground_station: &str,
+        command_tx: CommandSender,
+        telemetry: TelemetryHub,
+        params: PathBuf,
+    ) -> Result<Self> {
+        let socket = UdpSocket::bind(address).with_context(|| format!("Binding MAVLink endpoint to {}", address))?;
+        socket.set_broadcast(true)?;
+        socket.set_read_timeout(Some(POLL_PERIOD))?;
+        let peer = ground_station
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Invalid ground station address {}", ground_station))?;
+        Ok(Self {
+            socket,
+            peer,
+            sequence: 0,
+            command_tx,
+            telemetry,
+            params,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Streams the telemetry and handles the ground station messages until the stop flag is raised
+    pub fn run(&mut self, stop: Arc<AtomicBool>) {
+        log::info!("MAVLink endpoint listening on {:?}", self.socket.local_addr());
+        let mut buffer = [0; 2048];
+        let mut sequence = 0;
+        let mut previous = Telemetry::default();
+        let mut last_heartbeat: Option<Instant> = None;
+        while !stop.load(Ordering::Relaxed) {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((length, peer)) => {
+                    self.peer = peer;
+                    for frame in decode_all(&buffer[..length]) {
+                        if let Err(err) = self.handle_message(&frame.message) {
+                            log::warn!("MAVLink {:?}: {:#}", frame.message, err);
+                        }
+                    }
+                },
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => (),
+                Err(err) => log::warn!("MAVLink reception: {}", err),
+            }
+
+            let (latest, telemetry) = self.telemetry.latest();
+            if last_heartbeat.is_none_or(|x| x.elapsed() >= HEARTBEAT_PERIOD) {
+                last_heartbeat = Some(Instant::now());
+                self.send_status(&telemetry);
+            }
+            if latest != sequence {
+                sequence = latest;
+                self.send_telemetry(&previous, &telemetry);
+                previous = telemetry;
+            }
+        }
+    }
+
+    fn send(&mut self, message: Message) {
+        let frame = Frame {
+            sequence: self.sequence,
+            system: SYSTEM_ID,
+            component: COMPONENT_ID,
+            message,
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+        if let Err(err) = self.socket.send_to(&frame.encode(), self.peer) {
+            log::debug!("MAVLink emission to {}: {}", self.peer, err);
+        }
+    }
+
+    /// Sends a text to display on the ground station
+    fn status_text(&mut self, severity: u8, text: &str) {
+        self.send(Message::StatusText {
+            severity,
+            text: text.into(),
+        });
+    }
+
+    /// Sends the heartbeat, the system status and the battery status, the battery values are reported as unknown
+    /// without battery monitoring
+    fn send_status(&mut self, telemetry: &Telemetry) {
+        let mut base_mode = MAV_MODE_FLAG_MANUAL_INPUT_ENABLED;
+        if telemetry.armed {
+            base_mode |= MAV_MODE_FLAG_SAFETY_ARMED;
+        }
+        let system_status = match (telemetry.armed, telemetry.landing) {
+            (_, true) => MAV_STATE_EMERGENCY,
+            (true, false) => MAV_STATE_ACTIVE,
+            (false, false) => MAV_STATE_STANDBY,
+        };
+        self.send(Message::Heartbeat {
+            kind: MAV_TYPE_QUADROTOR,
+            autopilot: MAV_AUTOPILOT_GENERIC,
+            base_mode,
+            custom_mode: 0,
+            system_status,
+        });
+
+        let (voltage, current, remaining, consumed) = match telemetry.battery {
+            Some(battery) => (
+                (battery.voltage * 1000.0) as u16,
+                (battery.current * 100.0) as i16,
+                battery.remaining as i8,
+                battery.consumed as i32,
+            ),
+            None => (u16::MAX, -1, -1, -1),
+        };
+        // Average PID loop duration against the 10ms IMU period
+        let load = (telemetry.stats.pru_cycle.mean() / 10.0) as u16;
+        self.send(Message::SysStatus {
+            sensors: SENSORS,
+            load,
+            voltage,
+            current,
+            remaining,
+            errors: telemetry.imu.errors.min(u64::from(u16::MAX)) as u16,
+        });
+        self.send(Message::BatteryStatus {
+            consumed,
+            voltage,
+            current,
+            remaining,
+        });
+    }
+
+    /// Sends the attitude and the state changes
+    fn send_telemetry(&mut self, previous: &Telemetry, telemetry: &Telemetry) {
+        let odometry = telemetry.odometry;
+        self.send(Message::Attitude {
+            time_boot_ms: self.start.elapsed().as_millis() as u32,
+            roll: odometry.attitude.roll,
+            pitch: -odometry.attitude.pitch,
+            yaw: -odometry.attitude.yaw,
+            rollspeed: odometry.rate.roll,
+            pitchspeed: -odometry.rate.pitch,
+            yawspeed: -odometry.rate.yaw,
+        });
+
+        if telemetry.armed != previous.armed {
+            let text = if telemetry.armed {
+                "Armed"
+            } else {
+                "Disarmed"
+            };
+            self.status_text(MAV_SEVERITY_INFO, text);
+        }
+        if telemetry.landing && !previous.landing {
+            self.status_text(MAV_SEVERITY_CRITICAL, "Failsafe landing");
+        }
+        let level = |telemetry: &Telemetry| telemetry.battery.map(|battery| battery.level).unwrap_or_default();
+        if level(telemetry) > level(previous) {
+            match level(telemetry) {
+                BatteryLevel::Low => self.status_text(MAV_SEVERITY_WARNING, "Battery low"),
+                BatteryLevel::Critical => self.status_text(MAV_SEVERITY_CRITICAL, "Battery critical"),
+                BatteryLevel::Normal => (),
+            }
+        }
+    }
+
+    fn handle_message(&mut self, message: &Message) -> Result<()> {
+        let target = match *message {
+            Message::ParamRequestRead {
+                target_system,
+                ..
+            }
+            | Message::ParamRequestList {
+                target_system,
+                ..
+            }
+            | Message::ParamSet {
+                target_system,
+                ..
+            }
+            | Message::CommandLong {
+                target_system,
+                ..
+            }
+            | Message::SetAttitudeTarget {
+                target_system,
+                ..
+            }
+            | Message::ManualControl {
+                target: target_system,
+                ..
+            } => target_system,
+            _ => return Ok(()),
+        };
+        // 0 is the broadcast system
+        if target != SYSTEM_ID && target != 0 {
+            return Ok(());
+        }
+
+        match message {
+            Message::ParamRequestList {
+                ..
+            } => {
+                let mut params = Vec::new();
+                flatten(&read_params(&self.params)?, "", &mut params);
+                for (index, (id, value)) in params.iter().enumerate() {
+                    self.send_param(id, *value, index, params.len());
+                }
+            },
+            Message::ParamRequestRead {
+                id,
+                index,
+                ..
+            } => {
+                let mut params = Vec::new();
+                flatten(&read_params(&self.params)?, "", &mut params);
+                let found = match usize::try_from(*index) {
+                    Ok(index) => params.get(index).map(|_| index),
+                    Err(_) => params.iter().position(|(x, _)| x == id),
+                };
+                let index = found.ok_or_else(|| anyhow!("Unknown parameter {} {}", id, index))?;
+                self.send_param(&params[index].0, params[index].1, index, params.len());
+            },
+            Message::ParamSet {
+                id,
+                value,
+                ..
+            } => self.set_param(id, *value)?,
+            Message::CommandLong {
+                command,
+                ..
+            } => {
+                let result = match to_command(message) {
+                    Some(x) => match self.command_tx.send(x) {
+                        Ok(()) => MAV_RESULT_ACCEPTED,
+                        Err(_) => MAV_RESULT_FAILED,
+                    },
+                    None => MAV_RESULT_UNSUPPORTED,
+                };
+                self.send(Message::CommandAck {
+                    command: *command,
+                    result,
+                });
+            },
+            _ => {
+                if let Some(command) = to_command(message) {
+                    self.command_tx.send(command).map_err(|_| anyhow!("Flight controller stopped"))?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn send_param(&mut self, id: &str, value: f32, index: usize, count: usize) {
+        self.send(Message::ParamValue {
+            id: id.into(),
+            value,
+            kind: MAV_PARAM_TYPE_REAL32,
+            count: count as u16,
+            index: index as u16,
+        });
+    }
+
+    /// Stores a PID parameter keeping its type in the parameter file then sends the PID to the flight controller.
+    /// The other parameters and the non finite values are rejected with a text and their unchanged value.
+    fn set_param(&mut self, id: &str, value: f32) -> Result<()> {
+        let mut params = read_params(&self.params)?;
+        let (section, key) = id.split_once('.').unwrap_or(("", id));
+        let axis = match section {
+            "roll_pid" => Some(PidAxis::Roll),
+            "pitch_pid" => Some(PidAxis::Pitch),
+            "yaw_pid" => Some(PidAxis::Yaw),
+            _ => None,
+        };
+        match (axis, params.get_mut(section)) {
+            (Some(axis), Some(toml::Value::Table(table))) if value.is_finite() => {
+                let entry = table.get_mut(key).ok_or_else(|| anyhow!("Unknown parameter {}", id))?;
+                *entry = match entry {
+                    toml::Value::Float(_) => toml::Value::Float(f64::from(value)),
+                    toml::Value::Integer(_) => toml::Value::Integer(value.round() as i64),
+                    _ => bail!("Parameter {} is not a number", id),
+                };
+                let pid: PidConfig = toml::Value::Table(table.clone()).try_into()?;
+                config::store(&self.params, id, &table[key])?;
+                self.command_tx.send(Command::SetPid {
+                    axis,
+                    pid,
+                })?;
+            },
+            (Some(_), _) if !value.is_finite() => {
+                self.status_text(MAV_SEVERITY_WARNING, &format!("Invalid value for {}", id))
+            },
+            _ => self.status_text(MAV_SEVERITY_WARNING, &format!("Parameter {} is read only", id)),
+        }
+
+        let mut flat = Vec::new();
+        flatten(&params, "", &mut flat);
+        let index = flat.iter().position(|(x, _)| x == id).ok_or_else(|| anyhow!("Unknown parameter {}", id))?;
+        self.send_param(id, flat[index].1, index, flat.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CONFIG_FILE;
+    use crate::types::{command_channel, CommandReceiver};
+    use std::thread;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc(b"123456789"), 0x6f91);
+    }
+
+    #[test]
+    fn test_codec() {
+        let messages = vec![
+            Message::Heartbeat {
+                kind: MAV_TYPE_QUADROTOR,
+                autopilot: MAV_AUTOPILOT_GENERIC,
+                base_mode: MAV_MODE_FLAG_SAFETY_ARMED,
+                custom_mode: 0,
+                system_status: MAV_STATE_ACTIVE,
+            },
+            Message::SysStatus {
+                sensors: SENSORS,
+                load: 120,
+                voltage: 12100,
+                current: -1,
+                remaining: 80,
+                errors: 2,
+            },
+            Message::ParamValue {
+                id: "roll_pid.kpa".into(),
+                value: 1.5,
+                kind: MAV_PARAM_TYPE_REAL32,
+                count: 10,
+                index: 3,
+            },
+            Message::SetAttitudeTarget {
+                time_boot_ms: 1000,
+                target_system: 1,
+                target_component: 1,
+                type_mask: 0x07,
+                q: [1.0, 0.0, 0.0, 0.0],
+                rates: [0.0; 3],
+                thrust: 0.5,
+            },
+            Message::BatteryStatus {
+                consumed: 400,
+                voltage: 11100,
+                current: 2600,
+                remaining: 83,
+            },
+            Message::StatusText {
+                severity: MAV_SEVERITY_WARNING,
+                text: "Battery low".into(),
+            },
+        ];
+        for message in messages {
+            let frame = Frame {
+                sequence: 7,
+                system: SYSTEM_ID,
+                component: COMPONENT_ID,
+                message,
+            };
+            let encoded = frame.encode();
+            assert_eq!(encoded.len(), 8 + message_info(frame.message.id()).unwrap().1);
+            assert_eq!(Frame::decode(&encoded), Ok((frame, encoded.len())));
+        }
+    }
+
+    #[test]
+    fn test_decode_v2() {
+        // COMMAND_LONG arming with the trailing zeros truncated
+        let payload = [
+            0x00, 0x00, 0x80, 0x3f, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x90, 0x01,
+            1, 1,
+        ];
+        let mut frame = vec![MAGIC_V2, payload.len() as u8, 0, 0, 42, 255, 190, COMMAND_LONG as u8, 0, 0];
+        frame.extend(payload);
+        let crc = crc(&[&frame[1..], &[152]].concat());
+        frame.extend(crc.to_le_bytes());
+
+        // Preceded by garbage and followed by a corrupted frame
+        let mut datagram = vec![0x12, 0x34];
+        datagram.extend(&frame);
+        let mut corrupted = frame.clone();
+        corrupted[12] ^= 0xff;
+        datagram.extend(corrupted);
+
+        let frames = decode_all(&datagram);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].sequence, 42);
+        assert_eq!(frames[0].system, 255);
+        assert!(matches!(to_command(&frames[0].message), Some(Command::Armed(true))));
+        assert_eq!(Frame::decode(&frame[..10]), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn test_commands() {
+        let command = to_command(&Message::ManualControl {
+            target: 1,
+            x: 500,
+            y: -250,
+            z: 1000,
+            r: 2000,
+            buttons: 0,
+        });
+        match command {
+            Some(Command::Flight(command)) => {
+                assert_eq!(command.thrust, 1.0);
+                assert_eq!(command.angles.pitch, 0.5);
+                assert_eq!(command.angles.roll, -0.25);
+                assert_eq!(command.angles.yaw, -1.0);
+            },
+            command => panic!("Unexpected command {:?}", command),
+        }
+
+        // 7.5° nose up around the NED y axis
+        let half = 7.5f32.to_radians() / 2.0;
+        let command = to_command(&Message::SetAttitudeTarget {
+            time_boot_ms: 0,
+            target_system: 1,
+            target_component: 1,
+            type_mask: 0,
+            q: [half.cos(), 0.0, half.sin(), 0.0],
+            rates: [0.0; 3],
+            thrust: 0.4,
+        });
+        match command {
+            Some(Command::Flight(command)) => {
+                assert_eq!(command.thrust, 0.4);
+                assert!((command.angles.pitch + 0.5).abs() < 1e-5, "pitch {}", command.angles.pitch);
+                assert!(command.angles.roll.abs() < 1e-5);
+            },
+            command => panic!("Unexpected command {:?}", command),
+        }
+
+        // 7.5° heading without thrust
+        let command = to_command(&Message::SetAttitudeTarget {
+            time_boot_ms: 0,
+            target_system: 1,
+            target_component: 1,
+            type_mask: IGNORE_THRUST,
+            q: [half.cos(), 0.0, 0.0, half.sin()],
+            rates: [0.0; 3],
+            thrust: 0.0,
+        });
+        match command {
+            Some(Command::Attitude(angles)) => assert!((angles.yaw + 0.5).abs() < 1e-5, "yaw {}", angles.yaw),
+            command => panic!("Unexpected command {:?}", command),
+        }
+        let command = to_command(&Message::SetAttitudeTarget {
+            time_boot_ms: 0,
+            target_system: 1,
+            target_component: 1,
+            type_mask: 0,
+            q: [1.0, 0.0, 0.0, 0.0],
+            rates: [0.0; 3],
+            thrust: f32::NAN,
+        });
+        assert!(command.is_none());
+    }
+
+    fn start() -> (UdpSocket, CommandReceiver, TelemetryHub, Arc<AtomicBool>, TempDir) {
+        let directory = tempfile::tempdir().unwrap();
+        let params = directory.path().join(CONFIG_FILE);
+        fs::write(
+            &params,
+            "debug_config = \"None\"\n[roll_pid]\nkpa = 1.0\nkpr = 2.0\nti = 0.0\ntd = 0.0\nfilter = 0.0\nkaw = 0.0\nmax \
+             = 1.0\nmin = -1.0\n[battery]\ncell = 3\nvoltage_divider = 11.0\n",
+        )
+        .unwrap();
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let (command_tx, command_rx) = command_channel();
+        let telemetry = TelemetryHub::default();
+        let mut endpoint = MavlinkEndpoint::bind(
+            "127.0.0.1:0",
+            &ground.local_addr().unwrap().to_string(),
+            command_tx,
+            telemetry.clone(),
+            params,
+        )
+        .unwrap();
+        ground.connect(endpoint.local_addr().unwrap()).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let endpoint_stop = Arc::clone(&stop);
+        thread::spawn(move || endpoint.run(endpoint_stop));
+        (ground, command_rx, telemetry, stop, directory)
+    }
+
+    fn send(ground: &UdpSocket, message: Message) {
+        let frame = Frame {
+            sequence: 0,
+            system: 255,
+            component: 190,
+            message,
+        };
+        ground.send(&frame.encode()).unwrap();
+    }
+
+    /// Receives messages until the predicate matches
+    fn receive<T>(ground: &UdpSocket, mut predicate: impl FnMut(Message) -> Option<T>) -> T {
+        let mut buffer = [0; 2048];
+        loop {
+            let length = ground.recv(&mut buffer).unwrap();
+            for frame in decode_all(&buffer[..length]) {
+                if let Some(x) = predicate(frame.message) {
+                    return x;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let (ground, command_rx, telemetry, stop, _directory) = start();
+
+        receive(&ground, |message| matches!(message, Message::Heartbeat { .. }).then_some(()));
+
+        send(
+            &ground,
+            Message::CommandLong {
+                target_system: 1,
+                target_component: 1,
+                command: MAV_CMD_COMPONENT_ARM_DISARM,
+                confirmation: 0,
+                params: [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            },
+        );
+        assert!(matches!(command_rx.recv_timeout(Duration::from_secs(1)).unwrap().1, Command::Armed(true)));
+        let result = receive(&ground, |message| match message {
+            Message::CommandAck {
+                command: MAV_CMD_COMPONENT_ARM_DISARM,
+                result,
+            } => Some(result),
+            _ => None,
+        });
+        assert_eq!(result, MAV_RESULT_ACCEPTED);
+
+        // Messages for another system are ignored
+        send(
+            &ground,
+            Message::ManualControl {
+                target: 2,
+                x: 0,
+                y: 0,
+                z: 500,
+                r: 0,
+                buttons: 0,
+            },
+        );
+        send(
+            &ground,
+            Message::ManualControl {
+                target: 1,
+                x: 0,
+                y: 0,
+                z: 800,
+                r: 0,
+                buttons: 0,
+            },
+        );
+        match command_rx.recv_timeout(Duration::from_secs(1)).unwrap().1 {
+            Command::Flight(command) => assert_eq!(command.thrust, 0.8),
+            command => panic!("Unexpected command {:?}", command),
+        }
+
+        telemetry.publish(Telemetry {
+            armed: true,
+            ..Default::default()
+        });
+        let text = receive(&ground, |message| match message {
+            Message::StatusText {
+                text,
+                ..
+            } => Some(text),
+            _ => None,
+        });
+        assert_eq!(text, "Armed");
+
+        stop.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_params() {
+        let (ground, command_rx, _, stop, directory) = start();
+        let params = directory.path().join(CONFIG_FILE);
+
+        send(
+            &ground,
+            Message::ParamRequestList {
+                target_system: 1,
+                target_component: 1,
+            },
+        );
+        let mut ids = Vec::new();
+        let count = receive(&ground, |message| match message {
+            Message::ParamValue {
+                id,
+                count,
+                ..
+            } => {
+                ids.push(id);
+                (ids.len() == usize::from(count)).then_some(count)
+            },
+            _ => None,
+        });
+        // Only the numeric parameters with a short enough id
+        assert_eq!(count, 9);
+        assert_eq!(ids[0], "battery.cell");
+        assert!(!ids.contains(&"battery.voltage_divider".to_string()));
+
+        send(
+            &ground,
+            Message::ParamSet {
+                target_system: 1,
+                target_component: 1,
+                id: "roll_pid.kpa".into(),
+                value: 2.5,
+                kind: MAV_PARAM_TYPE_REAL32,
+            },
+        );
+        let value = receive(&ground, |message| match message {
+            Message::ParamValue {
+                id,
+                value,
+                ..
+            } if id == "roll_pid.kpa" => Some(value),
+            _ => None,
+        });
+        assert_eq!(value, 2.5);
+        match command_rx.recv_timeout(Duration::from_secs(1)).unwrap().1 {
+            Command::SetPid {
+                axis: PidAxis::Roll,
+                pid,
+            } => assert_eq!(pid.kpa, 2.5),
+            command => panic!("Unexpected command {:?}", command),
+        }
+        let stored = read_params(&params).unwrap();
+        assert_eq!(stored["roll_pid"]["kpa"].as_float(), Some(2.5));
+
+        // Only the PIDs are writable and with a finite value, the unchanged value is sent back
+        for (id, value, current) in [("battery.cell", 4.0, 3.0), ("roll_pid.kpa", f32::NAN, 2.5)] {
+            send(
+                &ground,
+                Message::ParamSet {
+                    target_system: 1,
+                    target_component: 1,
+                    id: id.into(),
+                    value,
+                    kind: MAV_PARAM_TYPE_REAL32,
+                },
+            );
+            let text = receive(&ground, |message| match message {
+                Message::StatusText {
+                    text,
+                    ..
+                } => Some(text),
+                _ => None,
+            });
+            assert!(text.contains(id));
+            let value = receive(&ground, |message| match message {
+                Message::ParamValue {
+                    id: x,
+                    value,
+                    ..
+                } if x == id => Some(value),
+                _ => None,
+            });
+            assert_eq!(value, current);
+        }
+        assert_eq!(read_params(&params).unwrap()["battery"]["cell"].as_integer(), Some(3));
+        assert_eq!(read_params(&params).unwrap()["roll_pid"]["kpa"].as_float(), Some(2.5));
+        assert!(command_rx.try_recv().is_err());
+
+        stop.store(true, Ordering::Relaxed);
+    }
+}
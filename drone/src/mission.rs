@@ -0,0 +1,304 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::arbiter::Source;
+use crate::plugin::{Envelope, Violation};
+use crate::types::{Command, CommandSender, FlightCommand, Telemetry, TelemetryHub};
+
+/// Flight command period of the mission steps (20Hz like the remotes)
+const COMMAND_PERIOD: Duration = Duration::from_millis(50);
+/// Telemetry polling period while waiting for a condition
+const POLL_PERIOD: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MissionError {
+    /// The plugin or the drone is stopping
+    Stopped,
+    /// The drone disarmed during a flight step
+    Disarmed,
+    /// The flight controller started a failsafe landing
+    FailsafeLanding,
+    /// Another source took the control of the drone
+    Overridden(Source),
+    /// Command outside of the plugin safety envelope
+    Rejected(Violation),
+    Timeout,
+}
+
+impl fmt::Display for MissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stopped => write!(f, "Mission stopped"),
+            Self::Disarmed => write!(f, "Drone disarmed during the mission"),
+            Self::FailsafeLanding => write!(f, "Failsafe landing during the mission"),
+            Self::Overridden(source) => write!(f, "Control taken over by {:?}", source),
+            Self::Rejected(violation) => write!(f, "Command rejected: {}", violation),
+            Self::Timeout => write!(f, "Mission step timed out"),
+        }
+    }
+}
+
+impl std::error::Error for MissionError {}
+
+/// Mission primitives run by a plugin over the command channel.
+///
+/// Each step checks the telemetry before sending a command and aborts when the plugin stops, when the drone disarms
+/// or starts a failsafe landing or when another source takes the control.
+pub struct Mission {
+    tx: CommandSender,
+    envelope: Envelope,
+    telemetry: TelemetryHub,
+    /// Drone shutdown
+    stop: Arc<AtomicBool>,
+    /// Plugin asking to be stopped
+    finished: AtomicBool,
+}
+
+impl Mission {
+    pub fn new(tx: CommandSender, envelope: Envelope, telemetry: TelemetryHub, stop: Arc<AtomicBool>) -> Self {
+        Self {
+            tx,
+            envelope,
+            telemetry,
+            stop,
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    pub fn stopping(&self) -> bool {
+        self.stop.load(Ordering::Relaxed) || self.finished.load(Ordering::Relaxed)
+    }
+
+    pub fn telemetry(&self) -> Telemetry {
+        self.telemetry.latest().1
+    }
+
+    /// Sends a command inside the safety envelope, on a violation the plugin stops if the envelope asks for a failsafe
+    pub fn send(&self, command: Command) -> Result<(), MissionError> {
+        if let Err(violation) = self.envelope.check(&command, self.telemetry().armed) {
+            return Err(self.reject(&command, violation));
+        }
+        self.tx.send(command).map_err(|_| MissionError::Stopped)
+    }
+
+    /// Rejects a plugin command, the plugin stops if the envelope asks for a failsafe
+    pub fn reject(&self, command: &dyn fmt::Debug, violation: Violation) -> MissionError {
+        log::error!("Plugin command {:?} rejected: {}", command, violation);
+        if self.envelope.failsafe() {
+            self.finish();
+            let _ = self.tx.send(Command::Failsafe(format!("plugin violation, {}", violation)));
+        }
+        MissionError::Rejected(violation)
+    }
+
+    /// Returns the telemetry if the mission can go on, the flight checks only apply when `flying` is set
+    fn check(&self, flying: bool) -> Result<Telemetry, MissionError> {
+        let telemetry = self.telemetry();
+        if self.stopping() {
+            return Err(MissionError::Stopped);
+        }
+        if flying {
+            if !telemetry.armed {
+                return Err(MissionError::Disarmed);
+            }
+            if telemetry.landing {
+                return Err(MissionError::FailsafeLanding);
+            }
+            match telemetry.control {
+                Some(source) if source != Source::Plugin => return Err(MissionError::Overridden(source)),
+                _ => (),
+            }
+        }
+        Ok(telemetry)
+    }
+
+    /// Polls the telemetry until the predicate holds, the flight checks apply if the drone was armed at the start
+    pub fn wait_until<E: From<MissionError>>(
+        &self,
+        mut predicate: impl FnMut(&Telemetry) -> Result<bool, E>,
+        timeout: Duration,
+    ) -> Result<Telemetry, E> {
+        let flying = self.telemetry().armed;
+        let start = Instant::now();
+        loop {
+            let telemetry = self.check(flying)?;
+            if predicate(&telemetry)? {
+                return Ok(telemetry);
+            }
+            if start.elapsed() > timeout {
+                return Err(MissionError::Timeout.into());
+            }
+            thread::sleep(POLL_PERIOD);
+        }
+    }
+
+    pub fn arm(&self, timeout: Duration) -> Result<(), MissionError> {
+        self.send(Command::Armed(true))?;
+        self.wait_until(|telemetry| Ok(telemetry.armed), timeout).map(|_| ())
+    }
+
+    /// Disarms the drone, this is never cancelled
+    pub fn disarm(&self, timeout: Duration) -> Result<(), MissionError> {
+        self.send(Command::Armed(false))?;
+        let start = Instant::now();
+        while self.telemetry().armed {
+            if start.elapsed() > timeout {
+                return Err(MissionError::Timeout);
+            }
+            thread::sleep(POLL_PERIOD);
+        }
+        Ok(())
+    }
+
+    /// Sends flight commands linearly interpolated from `from` to `to` during `duration`
+    pub fn ramp(&self, from: FlightCommand, to: FlightCommand, duration: Duration) -> Result<(), MissionError> {
+        let lerp = |a: f32, b: f32, x: f32| a + (b - a) * x;
+        let start = Instant::now();
+        loop {
+            self.check(true)?;
+            let progress = (start.elapsed().as_secs_f32() / duration.as_secs_f32()).min(1.0);
+            let progress = if progress.is_nan() {
+                1.0
+            } else {
+                progress
+            };
+            let mut command = to;
+            command.thrust = lerp(from.thrust, to.thrust, progress);
+            command.angles.roll = lerp(from.angles.roll, to.angles.roll, progress);
+            command.angles.pitch = lerp(from.angles.pitch, to.angles.pitch, progress);
+            command.angles.yaw = lerp(from.angles.yaw, to.angles.yaw, progress);
+            self.send(Command::Flight(command))?;
+            if progress >= 1.0 {
+                return Ok(());
+            }
+            thread::sleep(COMMAND_PERIOD.min(duration.saturating_sub(start.elapsed())));
+        }
+    }
+
+    pub fn hold(&self, command: FlightCommand, duration: Duration) -> Result<(), MissionError> {
+        self.ramp(command, command, duration)
+    }
+
+    /// Raises the thrust from zero with a level attitude
+    pub fn takeoff(&self, thrust: f32, duration: Duration) -> Result<(), MissionError> {
+        let to = FlightCommand {
+            thrust,
+            ..Default::default()
+        };
+        self.ramp(FlightCommand::default(), to, duration)
+    }
+
+    /// Brings the thrust down to zero with a level attitude then disarms
+    pub fn land(&self, duration: Duration, timeout: Duration) -> Result<(), MissionError> {
+        let from = self.check(true)?.command;
+        self.ramp(from, FlightCommand::default(), duration)?;
+        self.disarm(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::EnvelopeConfig;
+    use crate::types::command_channel;
+
+    /// Flight controller applying the plugin commands until the pilot takes over after `takeover` commands
+    fn fake_controller(takeover: usize) -> (Mission, thread::JoinHandle<Vec<f32>>) {
+        let (command_tx, command_rx) = command_channel();
+        let telemetry = TelemetryHub::default();
+        let hub = telemetry.clone();
+        let controller = thread::spawn(move || {
+            let mut state = Telemetry::default();
+            let mut thrust = Vec::new();
+            for (_, command, _) in command_rx.iter() {
+                match command {
+                    Command::Armed(armed) => {
+                        state.armed = armed;
+                        state.control = Some(Source::Plugin);
+                    },
+                    Command::Flight(command) => {
+                        thrust.push(command.thrust);
+                        state.command = command;
+                    },
+                    _ => (),
+                }
+                if thrust.len() == takeover {
+                    state.control = Some(Source::Pilot);
+                }
+                hub.publish(state);
+            }
+            thrust
+        });
+        let mission = Mission::new(
+            command_tx.with_source(Source::Plugin),
+            Envelope::new(EnvelopeConfig::default()),
+            telemetry,
+            Arc::new(AtomicBool::new(false)),
+        );
+        (mission, controller)
+    }
+
+    #[test]
+    fn test_mission() {
+        let (mission, controller) = fake_controller(usize::MAX);
+        // Flight steps require an armed drone
+        assert_eq!(mission.hold(FlightCommand::default(), Duration::ZERO), Err(MissionError::Disarmed));
+        mission.arm(Duration::from_secs(1)).unwrap();
+        mission.takeoff(0.5, Duration::from_millis(200)).unwrap();
+        mission.land(Duration::from_millis(200), Duration::from_secs(1)).unwrap();
+        assert!(!mission.telemetry().armed);
+        assert_eq!(
+            mission
+                .wait_until(|telemetry| Ok::<_, MissionError>(telemetry.armed), Duration::from_millis(50))
+                .map(|_| ()),
+            Err(MissionError::Timeout)
+        );
+        drop(mission);
+
+        let thrust = controller.join().unwrap();
+        let peak = thrust.iter().position(|x| *x == 0.5).unwrap();
+        assert!(thrust[..peak].windows(2).all(|x| x[0] <= x[1]), "{:?}", thrust);
+        assert!(thrust[peak..].windows(2).all(|x| x[0] >= x[1]), "{:?}", thrust);
+        assert_eq!(thrust.last(), Some(&0.0));
+    }
+
+    #[test]
+    fn test_override() {
+        let (mission, _controller) = fake_controller(3);
+        mission.arm(Duration::from_secs(1)).unwrap();
+        let command = FlightCommand {
+            thrust: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(mission.hold(command, Duration::from_secs(1)), Err(MissionError::Overridden(Source::Pilot)));
+
+        mission.finish();
+        assert_eq!(mission.disarm(Duration::from_secs(1)), Ok(()));
+        assert_eq!(mission.arm(Duration::from_secs(1)), Err(MissionError::Stopped));
+    }
+
+    #[test]
+    fn test_violation_failsafe() {
+        let (command_tx, command_rx) = command_channel();
+        let envelope = Envelope::new(EnvelopeConfig {
+            max_thrust: 0.5,
+            failsafe: true,
+            ..Default::default()
+        });
+        let mission = Mission::new(command_tx, envelope, TelemetryHub::default(), Arc::new(AtomicBool::new(false)));
+        let command = FlightCommand {
+            thrust: 0.9,
+            ..Default::default()
+        };
+        assert_eq!(mission.send(Command::Flight(command)), Err(MissionError::Rejected(Violation::Thrust(0.9))));
+        assert!(mission.stopping());
+        assert!(matches!(command_rx.try_recv().unwrap().1, Command::Failsafe(_)));
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::config;
+use crate::sensor::ImuSample;
+
+/// Named mounting orientations of the IMU with respect to the frame.
+/// Each preset is the rotation bringing the frame axes onto the IMU axes.
+/// The default `roll90_yaw90` is the mounting of the Drosix frame: roll around the IMU z axis, pitch around x and yaw
+/// around y.
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrientationPreset {
+    None,
+    Yaw90,
+    Yaw180,
+    Yaw270,
+    Roll90,
+    Roll180,
+    Roll270,
+    Pitch90,
+    Pitch180,
+    Pitch270,
+    Roll180Yaw90,
+    Roll180Yaw270,
+    #[default]
+    Roll90Yaw90,
+}
+
+impl OrientationPreset {
+    /// Roll, pitch and yaw angles of the preset in degrees
+    fn angles(self) -> [f64; 3] {
+        match self {
+            Self::None => [0.0, 0.0, 0.0],
+            Self::Yaw90 => [0.0, 0.0, 90.0],
+            Self::Yaw180 => [0.0, 0.0, 180.0],
+            Self::Yaw270 => [0.0, 0.0, 270.0],
+            Self::Roll90 => [90.0, 0.0, 0.0],
+            Self::Roll180 => [180.0, 0.0, 0.0],
+            Self::Roll270 => [270.0, 0.0, 0.0],
+            Self::Pitch90 => [0.0, 90.0, 0.0],
+            Self::Pitch180 => [0.0, 180.0, 0.0],
+            Self::Pitch270 => [0.0, 270.0, 0.0],
+            Self::Roll180Yaw90 => [180.0, 0.0, 90.0],
+            Self::Roll180Yaw270 => [180.0, 0.0, 270.0],
+            Self::Roll90Yaw90 => [90.0, 0.0, 90.0],
+        }
+    }
+}
+
+/// IMU mounting configuration stored in the parameter file under `[imu_orientation]`.
+/// The offsets (in degrees) are applied on top of the preset to account for an imperfect mounting.
+#[derive(Deserialize, Copy, Clone, Debug, Default)]
+#[serde(default)]
+pub struct OrientationConfig {
+    pub preset: OrientationPreset,
+    pub roll: f64,
+    pub pitch: f64,
+    pub yaw: f64,
+}
+
+/// Rotation from the IMU frame to the frame of the drone.
+///
+/// The frame of the drone has its x axis pointing forward, y axis pointing left and z axis pointing up. Angles and
+/// rates follow the right hand rule around these axes.
+#[derive(Copy, Clone, Debug)]
+pub struct Orientation {
+    /// Mounting quaternion `[w, x, y, z]`
+    quaternion: [f64; 4],
+    /// Mounting rotation matrix
+    matrix: [[f32; 3]; 3],
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self::from(OrientationConfig::default())
+    }
+}
+
+impl From<OrientationConfig> for Orientation {
+    fn from(config: OrientationConfig) -> Self {
+        let [roll, pitch, yaw] = config.preset.angles();
+        let preset = quat_from_euler(roll.to_radians(), pitch.to_radians(), yaw.to_radians());
+        let offset = quat_from_euler(config.roll.to_radians(), config.pitch.to_radians(), config.yaw.to_radians());
+        let quaternion = quat_mul(&offset, &preset);
+        Self {
+            quaternion,
+            matrix: quat_to_matrix(&quaternion),
+        }
+    }
+}
+
+impl Orientation {
+    /// Loads the IMU orientation from the parameter file, the default mounting is used without `[imu_orientation]`.
+    pub fn load() -> Result<Self> {
+        Ok(config::load::<OrientationConfig>("imu_orientation")?.into())
+    }
+
+    /// Rotates a vector from the IMU frame to the drone frame.
+    pub fn rotate(&self, v: &[f32; 3]) -> [f32; 3] {
+        self.matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+    }
+
+    /// IMU axis closest to an axis of the drone frame and whether they point in the same direction
+    pub fn imu_axis(&self, axis: usize) -> (usize, bool) {
+        let row = self.matrix[axis];
+        let imu_axis = (0..3).max_by(|a, b| row[*a].abs().total_cmp(&row[*b].abs())).unwrap_or(axis);
+        (imu_axis, row[imu_axis] > 0.0)
+    }
+
+    /// Converts an IMU attitude quaternion into the attitude quaternion of the drone.
+    pub fn attitude(&self, q: &[f64; 4]) -> [f64; 4] {
+        quat_mul(q, &quat_conj(&self.quaternion))
+    }
+
+    /// Expresses an IMU sample in the drone frame.
+    pub fn apply(&self, sample: &ImuSample) -> ImuSample {
+        ImuSample {
+            gyro: self.rotate(&sample.gyro),
+            accel: self.rotate(&sample.accel),
+            quaternion: self.attitude(&sample.quaternion),
+        }
+    }
+}
+
+/// Quaternion `[w, x, y, z]` of the rotation `yaw * pitch * roll` (angles in radian)
+pub fn quat_from_euler(roll: f64, pitch: f64, yaw: f64) -> [f64; 4] {
+    let (sr, cr) = (roll / 2.0).sin_cos();
+    let (sp, cp) = (pitch / 2.0).sin_cos();
+    let (sy, cy) = (yaw / 2.0).sin_cos();
+    [cr * cp * cy + sr * sp * sy, sr * cp * cy - cr * sp * sy, cr * sp * cy + sr * cp * sy, cr * cp * sy - sr * sp * cy]
+}
+
+/// Hamilton product of two quaternions
+pub fn quat_mul(a: &[f64; 4], b: &[f64; 4]) -> [f64; 4] {
+    [
+        a[0] * b[0] - a[1] * b[1] - a[2] * b[2] - a[3] * b[3],
+        a[0] * b[1] + a[1] * b[0] + a[2] * b[3] - a[3] * b[2],
+        a[0] * b[2] - a[1] * b[3] + a[2] * b[0] + a[3] * b[1],
+        a[0] * b[3] + a[1] * b[2] - a[2] * b[1] + a[3] * b[0],
+    ]
+}
+
+/// Conjugate of a quaternion, i.e. the inverse rotation for unit quaternions
+pub fn quat_conj(q: &[f64; 4]) -> [f64; 4] {
+    [q[0], -q[1], -q[2], -q[3]]
+}
+
+fn quat_to_matrix(q: &[f64; 4]) -> [[f32; 3]; 3] {
+    let [w, x, y, z] = *q;
+    [
+        [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y)],
+        [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x)],
+        [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y)],
+    ]
+    .map(|row| row.map(|x| x as f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::quat_to_angles;
+
+    fn assert_close(a: &[f32; 3], b: &[f32; 3]) {
+        assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_presets() {
+        let orientation = Orientation::from(OrientationConfig {
+            preset: OrientationPreset::Yaw90,
+            ..Default::default()
+        });
+        // IMU x axis points to the left of the drone
+        assert_close(&orientation.rotate(&[1.0, 0.0, 0.0]), &[0.0, 1.0, 0.0]);
+        assert_close(&orientation.rotate(&[0.0, 1.0, 0.0]), &[-1.0, 0.0, 0.0]);
+
+        let orientation = Orientation::from(OrientationConfig {
+            preset: OrientationPreset::Roll180,
+            ..Default::default()
+        });
+        // IMU mounted upside down
+        assert_close(&orientation.rotate(&[0.0, 1.0, 0.0]), &[0.0, -1.0, 0.0]);
+        assert_close(&orientation.rotate(&[0.0, 0.0, 1.0]), &[0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn test_default_mounting() {
+        // Roll rate on the gyroscope z axis, pitch on x and yaw on y
+        let orientation = Orientation::default();
+        assert_close(&orientation.rotate(&[1.0, 2.0, 3.0]), &[3.0, 1.0, 2.0]);
+        assert_eq!([0, 1, 2].map(|axis| orientation.imu_axis(axis)), [(2, true), (0, true), (1, true)]);
+
+        let orientation = Orientation::from(OrientationConfig {
+            preset: OrientationPreset::Roll180,
+            ..Default::default()
+        });
+        assert_eq!(orientation.imu_axis(2), (2, false));
+    }
+
+    #[test]
+    fn test_offsets() {
+        let orientation = Orientation::from(OrientationConfig {
+            preset: OrientationPreset::Yaw90,
+            yaw: -90.0,
+            ..Default::default()
+        });
+        assert_close(&orientation.rotate(&[1.0, 2.0, 3.0]), &[1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_mounted_attitude_is_level() {
+        // An IMU mounted upside down and rotated sees its own mounting as its attitude when the drone is level
+        for preset in [OrientationPreset::Roll180Yaw90, OrientationPreset::Pitch90, OrientationPreset::Yaw270] {
+            let orientation = Orientation::from(OrientationConfig {
+                preset,
+                ..Default::default()
+            });
+            let angles = quat_to_angles(&orientation.attitude(&orientation.quaternion));
+            assert_close(&[angles.roll, angles.pitch, angles.yaw], &[0.0, 0.0, 0.0]);
+        }
+    }
+}
@@ -1,10 +1,183 @@
-use crate::types::{Angles, Command, FlightCommand};
-use anyhow::Result;
+use crate::config;
+use crate::flight_controller::MAX_TILT;
+use crate::mission::{Mission, MissionError};
+use crate::types::{Angles, Command, CommandSender, FlightCommand, Telemetry, TelemetryHub};
+use anyhow::{Context, Result};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTimeoutError, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::PyType;
+use pyo3::types::{PyTuple, PyType};
+use serde::Deserialize;
 use std::ffi::CString;
+use std::fmt;
 use std::path::Path;
-use std::sync::mpsc::Sender;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Plugin configuration stored in the parameter file under `[plugin]`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PluginConfig {
+    /// Rate of the `on_tick` hook in Hz
+    pub rate: f32,
+    pub envelope: EnvelopeConfig,
+}
+
+impl Default for PluginConfig {
+    fn default() -> Self {
+        Self {
+            rate: 50.0,
+            envelope: EnvelopeConfig::default(),
+        }
+    }
+}
+
+impl PluginConfig {
+    /// Loads the plugin configuration from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("plugin")
+    }
+}
+
+/// Kind of command a plugin may send
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginCommand {
+    /// Flight commands
+    Position,
+    /// Motor values bypassing the PID, only when disarmed
+    Raw,
+    /// Arming, disarming is always allowed
+    Arm,
+}
+
+/// Limits of the plugin commands stored in the parameter file under `[plugin.envelope]`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct EnvelopeConfig {
+    /// Largest roll or pitch command in degrees
+    pub max_tilt: f32,
+    pub max_thrust: f32,
+    pub allowed: Vec<PluginCommand>,
+    /// Largest number of commands per second, a raw command counts once per motor
+    pub max_rate: u32,
+    /// Starts a failsafe landing and stops the plugin on a violation
+    pub failsafe: bool,
+}
+
+impl Default for EnvelopeConfig {
+    fn default() -> Self {
+        Self {
+            max_tilt: MAX_TILT,
+            max_thrust: 1.0,
+            allowed: vec![PluginCommand::Position, PluginCommand::Arm],
+            max_rate: 100,
+            failsafe: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Violation {
+    NotAllowed(PluginCommand),
+    /// Command not available to the plugins
+    Unsupported,
+    /// Non finite value in a flight command
+    Invalid,
+    /// Tilt command in degrees
+    Tilt(f32),
+    Thrust(f32),
+    /// Stick command outside of [-1, 1]
+    Stick(f32),
+    Rate,
+    RawArmed,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAllowed(kind) => write!(f, "{:?} commands are not allowed", kind),
+            Self::Unsupported => write!(f, "Unsupported command"),
+            Self::Invalid => write!(f, "Non finite flight command"),
+            Self::Tilt(tilt) => write!(f, "Tilt of {:.1}° above the limit", tilt),
+            Self::Thrust(thrust) => write!(f, "Thrust of {:.2} outside of the limits", thrust),
+            Self::Stick(stick) => write!(f, "Stick command of {:.2} outside of [-1, 1]", stick),
+            Self::Rate => write!(f, "Command rate above the limit"),
+            Self::RawArmed => write!(f, "Raw commands are only allowed when disarmed"),
+        }
+    }
+}
+
+/// Safety envelope checking the plugin commands before they reach the flight controller
+pub struct Envelope {
+    config: EnvelopeConfig,
+    /// Start of the rate limiting window and the number of commands in it
+    window: Mutex<(Instant, u32)>,
+}
+
+impl Envelope {
+    pub fn new(config: EnvelopeConfig) -> Self {
+        Self {
+            config,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    pub fn failsafe(&self) -> bool {
+        self.config.failsafe
+    }
+
+    pub fn check(&self, command: &Command, armed: bool) -> Result<(), Violation> {
+        let kind = match command {
+            // Disarming is never limited
+            Command::Armed(false) => return Ok(()),
+            Command::Armed(true) => PluginCommand::Arm,
+            Command::Flight(_) => PluginCommand::Position,
+            Command::SetMotor {
+                ..
+            } => PluginCommand::Raw,
+            _ => return Err(Violation::Unsupported),
+        };
+        if !self.config.allowed.contains(&kind) {
+            return Err(Violation::NotAllowed(kind));
+        }
+        match command {
+            Command::Flight(command) => {
+                let angles = command.angles;
+                let sticks = [angles.roll, angles.pitch, angles.yaw];
+                if !command.thrust.is_finite() || sticks.iter().any(|x| !x.is_finite()) {
+                    return Err(Violation::Invalid);
+                }
+                if let Some(stick) = sticks.into_iter().find(|x| x.abs() > 1.0) {
+                    return Err(Violation::Stick(stick));
+                }
+                let tilt = angles.roll.abs().max(angles.pitch.abs()) * MAX_TILT;
+                if tilt > self.config.max_tilt {
+                    return Err(Violation::Tilt(tilt));
+                }
+                if !(0.0..=self.config.max_thrust).contains(&command.thrust) {
+                    return Err(Violation::Thrust(command.thrust));
+                }
+            },
+            Command::SetMotor {
+                ..
+            } if armed => return Err(Violation::RawArmed),
+            _ => (),
+        }
+
+        let mut window = self.window.lock().unwrap();
+        if window.0.elapsed() >= Duration::from_secs(1) {
+            *window = (Instant::now(), 0);
+        }
+        if window.1 >= self.config.max_rate {
+            return Err(Violation::Rate);
+        }
+        window.1 += 1;
+        Ok(())
+    }
+}
 
 #[pyclass(name = "Command")]
 #[derive(Debug, Clone)]
@@ -16,7 +189,150 @@ enum CommandKind {
 
 #[pyclass(frozen)]
 struct Comm {
-    pub tx: Sender<Command>,
+    pub mission: Arc<Mission>,
+}
+
+/// Mission of the running plugin used by the module level primitives
+static MISSION: Mutex<Option<Arc<Mission>>> = Mutex::new(None);
+
+create_exception!(drosix, MissionAborted, PyException, "Mission step cancelled by the drone");
+create_exception!(drosix, CommandRejected, MissionAborted, "Command outside of the plugin safety envelope");
+
+impl From<MissionError> for PyErr {
+    fn from(err: MissionError) -> Self {
+        match err {
+            MissionError::Timeout => PyTimeoutError::new_err(err.to_string()),
+            MissionError::Rejected(_) => CommandRejected::new_err(err.to_string()),
+            err => MissionAborted::new_err(err.to_string()),
+        }
+    }
+}
+
+/// Telemetry snapshot given to the mission scripts, angles in rad
+#[pyclass(name = "Telemetry", frozen, get_all)]
+struct TelemetrySnapshot {
+    armed: bool,
+    landing: bool,
+    /// Source in control: pilot, network, plugin or None
+    control: Option<String>,
+    thrust: f32,
+    roll: f32,
+    pitch: f32,
+    yaw: f32,
+    altitude: f32,
+    /// Battery remaining charge in %, `None` without battery monitoring
+    battery: Option<f32>,
+}
+
+impl From<&Telemetry> for TelemetrySnapshot {
+    fn from(telemetry: &Telemetry) -> Self {
+        let odometry = telemetry.odometry;
+        Self {
+            armed: telemetry.armed,
+            landing: telemetry.landing,
+            control: telemetry.control.map(|source| format!("{:?}", source).to_lowercase()),
+            thrust: odometry.thrust,
+            roll: odometry.attitude.roll,
+            pitch: odometry.attitude.pitch,
+            yaw: odometry.attitude.yaw,
+            altitude: telemetry.altitude,
+            battery: telemetry.battery.map(|battery| battery.remaining),
+        }
+    }
+}
+
+/// Thrust, roll, pitch and yaw like `Command.Position`
+type Attitude = (f32, f32, f32, f32);
+
+fn flight_command((thrust, roll, pitch, yaw): Attitude) -> FlightCommand {
+    FlightCommand {
+        thrust,
+        angles: Angles {
+            roll,
+            pitch,
+            yaw,
+        },
+    }
+}
+
+fn mission() -> PyResult<Arc<Mission>> {
+    MISSION.lock().unwrap().clone().ok_or_else(|| MissionAborted::new_err("No plugin running"))
+}
+
+/// Converts a duration in s, an infinite duration never expires
+fn seconds(seconds: f32) -> PyResult<Duration> {
+    if seconds == f32::INFINITY {
+        return Ok(Duration::MAX);
+    }
+    Duration::try_from_secs_f32(seconds).map_err(|_| PyValueError::new_err(format!("Invalid duration of {}s", seconds)))
+}
+
+/// The blocking primitives release the GIL while waiting
+#[pyfunction]
+#[pyo3(signature = (timeout = 1.0))]
+fn arm(py: Python<'_>, timeout: f32) -> PyResult<()> {
+    let mission = mission()?;
+    let timeout = seconds(timeout)?;
+    Ok(py.allow_threads(|| mission.arm(timeout))?)
+}
+
+#[pyfunction]
+#[pyo3(signature = (timeout = 1.0))]
+fn disarm(py: Python<'_>, timeout: f32) -> PyResult<()> {
+    let mission = mission()?;
+    let timeout = seconds(timeout)?;
+    Ok(py.allow_threads(|| mission.disarm(timeout))?)
+}
+
+#[pyfunction]
+fn takeoff(py: Python<'_>, thrust: f32, duration: f32) -> PyResult<()> {
+    let mission = mission()?;
+    let duration = seconds(duration)?;
+    Ok(py.allow_threads(|| mission.takeoff(thrust, duration))?)
+}
+
+#[pyfunction]
+fn hold(py: Python<'_>, attitude: Attitude, seconds: f32) -> PyResult<()> {
+    let mission = mission()?;
+    let duration = self::seconds(seconds)?;
+    Ok(py.allow_threads(|| mission.hold(flight_command(attitude), duration))?)
+}
+
+#[pyfunction]
+#[pyo3(name = "ramp")]
+fn ramp_attitude(py: Python<'_>, from: Attitude, to: Attitude, seconds: f32) -> PyResult<()> {
+    let mission = mission()?;
+    let duration = self::seconds(seconds)?;
+    Ok(py.allow_threads(|| mission.ramp(flight_command(from), flight_command(to), duration))?)
+}
+
+#[pyfunction]
+#[pyo3(signature = (duration = 3.0, timeout = 1.0))]
+fn land(py: Python<'_>, duration: f32, timeout: f32) -> PyResult<()> {
+    let mission = mission()?;
+    let (duration, timeout) = (seconds(duration)?, seconds(timeout)?);
+    Ok(py.allow_threads(|| mission.land(duration, timeout))?)
+}
+
+/// Waits until `predicate(telemetry)` is true
+#[pyfunction]
+fn wait_until(py: Python<'_>, predicate: Py<PyAny>, timeout: f32) -> PyResult<()> {
+    let mission = mission()?;
+    let timeout = seconds(timeout)?;
+    py.allow_threads(|| {
+        mission.wait_until(
+            |telemetry| {
+                Python::with_gil(|py| predicate.call1(py, (TelemetrySnapshot::from(telemetry),))?.is_truthy(py))
+            },
+            timeout,
+        )
+    })
+    .map(|_| ())
+}
+
+#[pyfunction]
+fn telemetry() -> PyResult<TelemetrySnapshot> {
+    Ok(TelemetrySnapshot::from(&mission()?.telemetry()))
 }
 
 #[pyclass(subclass)]
@@ -33,8 +349,34 @@ impl Plugin {
         }
     }
 
-    fn start(&self) {
-        log::warn!("You shall implement start as your plugin entry point!");
+    /// Called once before the first tick, runs the deprecated `start` entry point of the older plugins
+    fn on_start(slf: &Bound<'_, Self>) -> PyResult<()> {
+        if slf.hasattr("start")? {
+            log::warn!("Plugin.start is deprecated, implement on_start instead");
+            slf.call_method0("start")?;
+        }
+        Ok(())
+    }
+
+    /// Called periodically with the time elapsed since the previous tick in s
+    fn on_tick(&self, _dt: f32) {}
+
+    fn on_armed(&self) {}
+
+    fn on_disarmed(&self) {}
+
+    /// Called once when the plugin or the drone stops
+    fn on_stop(&self) {}
+
+    /// Asks the scheduler to stop the plugin after the current hook
+    fn stop(&self) {
+        self.comm.get().mission.finish();
+    }
+
+    /// Whether the plugin is stopping, long running hooks shall return when it is set
+    #[getter]
+    fn stopping(&self) -> bool {
+        self.comm.get().mission.stopping()
     }
 
     fn log(&self, msg: String) {
@@ -45,33 +387,33 @@ impl Plugin {
         let comm = self.comm.get();
         match cmd {
             CommandKind::Raw(m0, m1, m2, m3) => {
-                comm.tx
+                comm.mission
                     .send(Command::SetMotor {
                         motor: 0,
                         value: m0,
                     })
-                    .unwrap();
-                comm.tx
+                    .map_err(PyErr::from)?;
+                comm.mission
                     .send(Command::SetMotor {
                         motor: 1,
                         value: m1,
                     })
-                    .unwrap();
-                comm.tx
+                    .map_err(PyErr::from)?;
+                comm.mission
                     .send(Command::SetMotor {
                         motor: 2,
                         value: m2,
                     })
-                    .unwrap();
-                comm.tx
+                    .map_err(PyErr::from)?;
+                comm.mission
                     .send(Command::SetMotor {
                         motor: 3,
                         value: m3,
                     })
-                    .unwrap();
+                    .map_err(PyErr::from)?;
             },
             CommandKind::Position(thrust, roll, pitch, yaw) => {
-                comm.tx
+                comm.mission
                     .send(Command::Flight(FlightCommand {
                         thrust,
                         angles: Angles {
@@ -80,9 +422,11 @@ impl Plugin {
                             yaw,
                         },
                     }))
-                    .unwrap();
+                    .map_err(PyErr::from)?;
+            },
+            command @ CommandKind::Velocity(..) => {
+                return Err(comm.mission.reject(&command, Violation::Unsupported).into());
             },
-            _ => unimplemented!(),
         };
         Ok(())
     }
@@ -99,34 +443,326 @@ fn pymodule(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<CommandKind>()?;
     m.add_class::<Comm>()?;
     m.add_class::<Plugin>()?;
-    m.add_function(wrap_pyfunction!(register, m).unwrap())
+    m.add_class::<TelemetrySnapshot>()?;
+    m.add("MissionAborted", m.py().get_type::<MissionAborted>())?;
+    m.add("CommandRejected", m.py().get_type::<CommandRejected>())?;
+    m.add_function(wrap_pyfunction!(register, m)?)?;
+    m.add_function(wrap_pyfunction!(arm, m)?)?;
+    m.add_function(wrap_pyfunction!(disarm, m)?)?;
+    m.add_function(wrap_pyfunction!(takeoff, m)?)?;
+    m.add_function(wrap_pyfunction!(hold, m)?)?;
+    m.add_function(wrap_pyfunction!(ramp_attitude, m)?)?;
+    m.add_function(wrap_pyfunction!(land, m)?)?;
+    m.add_function(wrap_pyfunction!(wait_until, m)?)?;
+    m.add_function(wrap_pyfunction!(telemetry, m)?)
 }
 
-pub fn run_plugin<P: AsRef<Path>>(path: P, command_tx: Sender<Command>) -> Result<()> {
-    log::info!("Starting plugin {}", path.as_ref().display());
+/// Starts the Python interpreter with the `drosix` module, the module can only be added before the start
+fn init_interpreter() {
+    static INTERPRETER: Once = Once::new();
+    INTERPRETER.call_once(|| {
+        pyo3::append_to_inittab!(pymodule);
+        pyo3::prepare_freethreaded_python();
+    });
+}
+
+/// Calls a hook of the plugin, the GIL is only held during the call
+fn call_hook(plugin: &Py<PyAny>, name: &str, args: impl for<'py> IntoPyObject<'py, Target = PyTuple>) -> Result<()> {
+    Python::with_gil(|py| plugin.call_method1(py, name, args).map(|_| ())).with_context(|| format!("Plugin {}", name))
+}
 
-    pyo3::append_to_inittab!(pymodule);
-    pyo3::prepare_freethreaded_python();
-    let code = std::fs::read_to_string("plugin.py").expect("You should provide valid python plugin path");
-    Python::with_gil(|py| {
-        PyModule::from_code(
-            py,
-            CString::new(code).unwrap().as_c_str(),
-            pyo3::ffi::c_str!("plugin.py"),
-            pyo3::ffi::c_str!("plugin"),
+/// Runs the plugin until it stops itself or the stop flag is set.
+///
+/// The hooks are called cooperatively from this thread: `on_start` once, `on_tick(dt)` at the configured rate,
+/// `on_armed` and `on_disarmed` on the arming transitions reported by the telemetry and `on_stop` once at the end,
+/// even when a hook raised an exception.
+pub fn run_plugin<P: AsRef<Path>>(
+    path: P,
+    config: PluginConfig,
+    command_tx: CommandSender,
+    telemetry: TelemetryHub,
+    stop: Arc<AtomicBool>,
+) -> Result<()> {
+    let path = path.as_ref();
+    log::info!("Starting plugin {}", path.display());
+    let period = Duration::try_from_secs_f32(1.0 / config.rate)
+        .with_context(|| format!("Invalid plugin rate of {}Hz", config.rate))?;
+
+    let code = std::fs::read_to_string(path).with_context(|| format!("Reading plugin {}", path.display()))?;
+    init_interpreter();
+    let plugin = Python::with_gil(|py| -> PyResult<Py<PyAny>> {
+        let name = CString::new(path.display().to_string()).unwrap_or_default();
+        PyModule::from_code(py, CString::new(code)?.as_c_str(), name.as_c_str(), pyo3::ffi::c_str!("plugin"))?;
+        let envelope = Envelope::new(config.envelope);
+        let mission = Arc::new(Mission::new(command_tx, envelope, telemetry.clone(), stop));
+        *MISSION.lock().unwrap() = Some(Arc::clone(&mission));
+        let comm = Comm {
+            mission,
+        };
+        py.import("drosix")?.getattr(pyo3::intern!(py, "entry"))?.call1((comm,)).map(Bound::unbind)
+    })
+    .context("Loading plugin")
+    .inspect_err(|e| log::error!("{:#}", e))?;
+
+    let stopping = || Python::with_gil(|py| plugin.getattr(py, "stopping")?.extract::<bool>(py)).unwrap_or(true);
+    let result = (|| {
+        call_hook(&plugin, "on_start", ())?;
+        let mut armed = false;
+        let mut last = Instant::now();
+        while !stopping() {
+            let now = Instant::now();
+            call_hook(&plugin, "on_tick", (now.duration_since(last).as_secs_f32(),))?;
+            last = now;
+            let (_, telemetry) = telemetry.latest();
+            if telemetry.armed != armed {
+                armed = telemetry.armed;
+                call_hook(
+                    &plugin,
+                    if armed {
+                        "on_armed"
+                    } else {
+                        "on_disarmed"
+                    },
+                    (),
+                )?;
+            }
+            thread::sleep(period.saturating_sub(now.elapsed()));
+        }
+        Ok(())
+    })();
+    let result = result.and(call_hook(&plugin, "on_stop", ()));
+    *MISSION.lock().unwrap() = None;
+    match &result {
+        Ok(()) => log::info!("Plugin {} finished", path.display()),
+        Err(e) => log::error!("{:#}", e),
+    }
+    result
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::types::{command_channel, Telemetry};
+
+    /// Serializes the tests running a plugin, the module level primitives use the mission of the running plugin
+    pub(crate) static PLUGIN_TEST: Mutex<()> = Mutex::new(());
+
+    const PLUGIN: &str = r#"
+import drosix
+
+class Test(drosix.Plugin):
+    def on_start(self):
+        self.ticks = 0
+        self.send(drosix.Command.Raw(1, 2, 3, 4))
+
+    def on_tick(self, dt):
+        self.ticks += 1
+        if self.ticks == 5:
+            self.stop()
+
+    def on_armed(self):
+        self.send(drosix.Command.Position(0.5, 0.0, 0.0, 0.0))
+
+    def on_stop(self):
+        self.send(drosix.Command.Position(float(self.ticks), 0.0, 0.0, 0.0))
+
+drosix.register(Test)
+"#;
+
+    /// Runs a plugin from `on_start` only and returns the thrust of the flight commands it sent
+    fn run_script(script: &str, telemetry: Telemetry) -> Vec<f32> {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("plugin.py");
+        std::fs::write(&path, format!("import drosix\n\nclass Test(drosix.Plugin):\n    def on_start(self):\n{}\n        self.stop()\n\ndrosix.register(Test)\n", script)).unwrap();
+        let (command_tx, command_rx) = command_channel();
+        let hub = TelemetryHub::default();
+        hub.publish(telemetry);
+        run_plugin(&path, PluginConfig::default(), command_tx, hub, Arc::new(AtomicBool::new(false))).unwrap();
+        command_rx
+            .try_iter()
+            .map(|(_, command, _)| match command {
+                Command::Flight(command) => command.thrust,
+                command => panic!("Unexpected command {:?}", command),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let _plugin = PLUGIN_TEST.lock().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("plugin.py");
+        std::fs::write(&path, PLUGIN).unwrap();
+        let (command_tx, command_rx) = command_channel();
+        let telemetry = TelemetryHub::default();
+        let config = PluginConfig {
+            envelope: EnvelopeConfig {
+                allowed: vec![PluginCommand::Position, PluginCommand::Raw],
+                max_thrust: 5.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let hub = telemetry.clone();
+        let plugin =
+            thread::spawn(move || run_plugin(&path, config, command_tx, hub, Arc::new(AtomicBool::new(false))));
+
+        // The drone arms once the raw commands, only allowed when disarmed, are received
+        let mut commands: Vec<Command> =
+            (0..4).map(|_| command_rx.recv_timeout(Duration::from_secs(1)).unwrap().1).collect();
+        telemetry.publish(Telemetry {
+            armed: true,
+            ..Default::default()
+        });
+        plugin.join().unwrap().unwrap();
+        commands.extend(command_rx.try_iter().map(|(_, command, _)| command));
+        assert_eq!(commands.len(), 6, "{:?}", commands);
+        assert!(matches!(
+            commands[3],
+            Command::SetMotor {
+                motor: 3,
+                value: 4
+            }
+        ));
+        let thrust: Vec<f32> = commands[4..]
+            .iter()
+            .map(|command| match command {
+                Command::Flight(command) => command.thrust,
+                command => panic!("Unexpected command {:?}", command),
+            })
+            .collect();
+        // Armed hook then the stop hook after the 5 ticks
+        assert_eq!(thrust, [0.5, 5.0]);
+    }
+
+    #[test]
+    fn test_wait_until() {
+        let _plugin = PLUGIN_TEST.lock().unwrap();
+        let script = r#"
+        drosix.wait_until(lambda telemetry: telemetry.armed, 1.0)
+        drosix.wait_until(lambda telemetry: telemetry.armed, float("inf"))
+        self.send(drosix.Command.Position(0.1, 0.0, 0.0, 0.0))
+        try:
+            drosix.wait_until(lambda telemetry: telemetry.landing, 0.05)
+        except TimeoutError:
+            self.send(drosix.Command.Position(0.2, 0.0, 0.0, 0.0))
+        for timeout in [-1.0, float("nan")]:
+            try:
+                drosix.wait_until(lambda telemetry: True, timeout)
+            except ValueError:
+                self.send(drosix.Command.Position(0.3, 0.0, 0.0, 0.0))"#;
+        let armed = Telemetry {
+            armed: true,
+            ..Default::default()
+        };
+        assert_eq!(run_script(script, armed), [0.1, 0.2, 0.3, 0.3]);
+    }
+
+    #[test]
+    fn test_rejected() {
+        let _plugin = PLUGIN_TEST.lock().unwrap();
+        // Raw commands are not allowed by default, the thrust is limited to 1 and velocities are unsupported
+        let script = r#"
+        commands = [
+            drosix.Command.Raw(1, 2, 3, 4),
+            drosix.Command.Position(2.0, 0.0, 0.0, 0.0),
+            drosix.Command.Velocity(0.0, 0.0, 0.0, 0.0),
+        ]
+        for command in commands:
+            try:
+                self.send(command)
+            except drosix.CommandRejected:
+                self.send(drosix.Command.Position(0.5, 0.0, 0.0, 0.0))"#;
+        assert_eq!(run_script(script, Telemetry::default()), [0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_legacy_start() {
+        let _plugin = PLUGIN_TEST.lock().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("plugin.py");
+        std::fs::write(
+            &path,
+            "import drosix\n\nclass Test(drosix.Plugin):\n    def start(self):\n        \
+             self.send(drosix.Command.Position(0.5, 0.0, 0.0, 0.0))\n        self.stop()\n\ndrosix.register(Test)\n",
         )
-        .inspect_err(|e| log::error!("{}", e))?;
-        py.import("drosix")
-            .and_then(|module| module.getattr(pyo3::intern!(py, "entry")))
-            .and_then(|entry| {
-                entry.call1((Comm {
-                    tx: command_tx,
-                },))
+        .unwrap();
+        let (command_tx, command_rx) = command_channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        run_plugin(&path, PluginConfig::default(), command_tx, TelemetryHub::default(), stop).unwrap();
+        assert!(matches!(command_rx.try_recv().unwrap().1, Command::Flight(command) if command.thrust == 0.5));
+
+        for rate in [0.0, -1.0, f32::NAN] {
+            let config = PluginConfig {
+                rate,
+                ..Default::default()
+            };
+            let (command_tx, _) = command_channel();
+            let stop = Arc::new(AtomicBool::new(false));
+            assert!(run_plugin(&path, config, command_tx, TelemetryHub::default(), stop).is_err());
+        }
+    }
+
+    #[test]
+    fn test_envelope() {
+        let envelope = Envelope::new(EnvelopeConfig {
+            max_tilt: 7.5,
+            max_thrust: 0.8,
+            max_rate: 3,
+            ..Default::default()
+        });
+        let flight = |thrust, pitch| {
+            Command::Flight(FlightCommand {
+                thrust,
+                angles: Angles {
+                    pitch,
+                    ..Default::default()
+                },
             })
-            .and_then(|plugin| plugin.getattr("start"))
-            .and_then(move |start| start.call0())
-            .map(|_| log::info!("Plugin {} finished", path.as_ref().display()))
-            .map_err(|e| anyhow::anyhow!(e))
-            .inspect_err(|e| log::error!("{}", e))
-    })
+        };
+        assert_eq!(envelope.check(&flight(0.9, 0.0), true), Err(Violation::Thrust(0.9)));
+        assert_eq!(envelope.check(&flight(-0.1, 0.0), true), Err(Violation::Thrust(-0.1)));
+        assert_eq!(envelope.check(&flight(f32::NAN, 0.0), true), Err(Violation::Invalid));
+        assert_eq!(envelope.check(&flight(0.5, f32::INFINITY), true), Err(Violation::Invalid));
+        assert_eq!(envelope.check(&flight(0.5, 1.5), true), Err(Violation::Stick(1.5)));
+        assert_eq!(envelope.check(&flight(0.5, -0.6), true), Err(Violation::Tilt(9.0)));
+        let motor = Command::SetMotor {
+            motor: 0,
+            value: 215_000,
+        };
+        assert_eq!(envelope.check(&motor, false), Err(Violation::NotAllowed(PluginCommand::Raw)));
+        assert_eq!(envelope.check(&Command::Stop, false), Err(Violation::Unsupported));
+
+        // The rejected commands do not count in the rate
+        assert_eq!(envelope.check(&Command::Armed(true), false), Ok(()));
+        assert_eq!(envelope.check(&flight(0.8, 0.5), true), Ok(()));
+        assert_eq!(envelope.check(&flight(0.5, 0.0), true), Ok(()));
+        assert_eq!(envelope.check(&flight(0.5, 0.0), true), Err(Violation::Rate));
+        assert_eq!(envelope.check(&Command::Armed(false), true), Ok(()));
+
+        let envelope = Envelope::new(EnvelopeConfig {
+            allowed: vec![PluginCommand::Raw],
+            ..Default::default()
+        });
+        assert_eq!(envelope.check(&motor, false), Ok(()));
+        assert_eq!(envelope.check(&motor, true), Err(Violation::RawArmed));
+    }
+
+    #[test]
+    fn test_stubs() {
+        init_interpreter();
+        let stubs = include_str!("drosix.pyi");
+        Python::with_gil(|py| {
+            let module = py.import("drosix").unwrap();
+            let plugin = module.getattr("Plugin").unwrap();
+            let names = module.dir().unwrap().into_iter().chain(plugin.dir().unwrap());
+            for name in names.map(|name| name.extract::<String>().unwrap()) {
+                if name.starts_with('_') || name == "entry" {
+                    continue;
+                }
+                let declared = [format!("class {}", name), format!("def {}(", name)].iter().any(|x| stubs.contains(x));
+                assert!(declared, "{} is missing from drosix.pyi", name);
+            }
+        });
+    }
 }
@@ -1,7 +1,9 @@
 use anyhow::{Context, Result};
 use mio::unix::SourceFd;
 use mio::{Events, Interest, Poll, Token};
-use std::os::fd::AsRawFd;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
 use std::time::Duration;
 
 pub struct Poller {
@@ -31,3 +33,49 @@ impl Poller {
         Ok(&self.events)
     }
 }
+
+/// Periodic timer based on a timerfd so it can be registered in a [`Poller`]
+pub struct Timer {
+    fd: File,
+}
+
+impl Timer {
+    pub fn new(period: Duration) -> Result<Self> {
+        let fd = unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Error creating timer");
+        }
+        // Safety: the file descriptor was just created and is owned by nobody else
+        let fd = unsafe { File::from_raw_fd(fd) };
+        let interval = libc::timespec {
+            tv_sec: period.as_secs() as libc::time_t,
+            tv_nsec: period.subsec_nanos() as libc::c_long,
+        };
+        let spec = libc::itimerspec {
+            it_interval: interval,
+            it_value: interval,
+        };
+        if unsafe { libc::timerfd_settime(fd.as_raw_fd(), 0, &spec, std::ptr::null_mut()) } < 0 {
+            return Err(io::Error::last_os_error()).context("Error arming timer");
+        }
+        Ok(Self {
+            fd,
+        })
+    }
+
+    /// Acknowledges the timer event and returns the number of elapsed periods
+    pub fn handle_event(&mut self) -> Result<u64> {
+        let mut expirations = [0; 8];
+        match self.fd.read_exact(&mut expirations) {
+            Ok(()) => Ok(u64::from_ne_bytes(expirations)),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(err) => Err(err).context("Error reading timer"),
+        }
+    }
+}
+
+impl AsRawFd for Timer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
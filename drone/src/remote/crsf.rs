@@ -0,0 +1,122 @@
+use super::{unpack_channels, Decoder, RcFrame};
+
+/// Address of the frames sent by the receiver to the flight controller
+const FLIGHT_CONTROLLER: u8 = 0xc8;
+/// Largest frame length field (type, payload and CRC)
+const MAX_LENGTH: usize = 62;
+const LINK_STATISTICS: u8 = 0x14;
+const RC_CHANNELS_PACKED: u8 = 0x16;
+
+/// CRC-8/DVB-S2 used by CRSF over the type and the payload
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0xd5
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// CRSF decoder, a frame is an address, a length, a type, a payload and a CRC.
+/// The receiver reports the link quality in the link statistics frames, a null uplink quality means that the link
+/// with the transmitter is lost.
+#[derive(Default)]
+pub struct CrsfDecoder {
+    buffer: Vec<u8>,
+    failsafe: bool,
+}
+
+impl CrsfDecoder {
+    fn handle_frame(&mut self, kind: u8, payload: &[u8]) -> Option<RcFrame> {
+        match kind {
+            RC_CHANNELS_PACKED if payload.len() >= 22 => Some(RcFrame {
+                channels: unpack_channels(&payload[..22]),
+                frame_lost: false,
+                failsafe: self.failsafe,
+            }),
+            LINK_STATISTICS if payload.len() >= 3 => {
+                self.failsafe = payload[2] == 0;
+                None
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Decoder for CrsfDecoder {
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        if self.buffer.is_empty() && byte != FLIGHT_CONTROLLER {
+            return None;
+        }
+        self.buffer.push(byte);
+        let length = usize::from(*self.buffer.get(1)?);
+        if !(2..=MAX_LENGTH).contains(&length) {
+            // Out of sync, resumes on the next address
+            self.buffer.clear();
+            return None;
+        }
+        if self.buffer.len() < length + 2 {
+            return None;
+        }
+
+        let frame = std::mem::take(&mut self.buffer);
+        let (content, crc) = frame[2..].split_at(length - 1);
+        if crc8(content) == crc[0] {
+            self.handle_frame(content[0], &content[1..])
+        } else {
+            log::debug!("Invalid CRSF CRC");
+            // Resumes on the next address
+            frame[1..].iter().filter_map(|x| self.push(*x)).last()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Channels 1 and 5 at maximum, channel 3 at minimum, the others at center
+    const CAPTURE: [u8; 26] = [
+        0xc8, 0x18, 0x16, 0x13, 0x07, 0x1f, 0x2b, 0xc0, 0x37, 0x71, 0xf0, 0x81, 0x0f, 0x7c, 0xe0, 0x03, 0x1f, 0xf8,
+        0xc0, 0x07, 0x3e, 0xf0, 0x81, 0x0f, 0x7c, 0xbb,
+    ];
+    /// Link statistics with a null uplink quality
+    const LINK_LOST: [u8; 14] = [0xc8, 0x0c, 0x14, 0x32, 0x32, 0x00, 0x0a, 0x00, 0x02, 0x01, 0x3c, 0x64, 0x08, 0x0a];
+
+    fn decode(decoder: &mut CrsfDecoder, stream: &[u8]) -> Vec<RcFrame> {
+        stream.iter().filter_map(|x| decoder.push(*x)).collect()
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc8(b"123456789"), 0xbc);
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut decoder = CrsfDecoder::default();
+        let frames = decode(&mut decoder, &CAPTURE);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(&frames[0].channels[..5], &[1.0, 0.0, -1.0, 0.0, 1.0]);
+        assert!(!frames[0].failsafe);
+
+        // The link statistics flag the following channels
+        let frames = decode(&mut decoder, &[LINK_LOST.as_slice(), &CAPTURE].concat());
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].failsafe);
+    }
+
+    #[test]
+    fn test_resync() {
+        let mut decoder = CrsfDecoder::default();
+        let mut corrupted = CAPTURE;
+        corrupted[10] ^= 0xff;
+        let stream = [&CAPTURE[5..], &corrupted, &CAPTURE, &[0xc8, 0xff], &CAPTURE].concat();
+        let frames = decode(&mut decoder, &stream);
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|x| x.channels[0] == 1.0));
+    }
+}
@@ -1,19 +1,23 @@
+use anyhow::{anyhow, Result};
 use gilrs::{Axis, Button, Event, EventType, Gilrs};
-use std::sync::mpsc::Sender;
 use std::time::{Duration, Instant};
 
-use crate::types::{Angles, Command, FlightCommand};
+use super::{ChannelMapping, Link, CHANNELS, WATCHDOG};
+use crate::types::{Command, CommandSender};
 
 const MOTOR_OFF: u32 = 199_999;
 const MOTOR_ON: u32 = 215_000;
 
-pub fn remote(remote_tx: Sender<Command>) {
-    let mut gilrs = Gilrs::new().unwrap();
-    let mut armed = false;
+/// Gamepad remote, the events are turned into RC channels so that they share the channel mapping of the receivers:
+/// the left stick gives the roll and the pitch and the left trigger both the thrust and the arming.
+pub fn gamepad(remote_tx: CommandSender, mapping: ChannelMapping) -> Result<()> {
+    let mut gilrs = Gilrs::new().map_err(|err| anyhow!("Gamepad initialization: {}", err))?;
+    let mut link = Link::new(remote_tx, mapping);
+    let mut channels = [0.0; CHANNELS];
+    channels[mapping.thrust] = -1.0;
+    channels[mapping.arm] = -1.0;
     let mut watchdog = Instant::now();
-    let mut rate_limiter = Instant::now();
     let mut motor_on = MOTOR_ON;
-    let mut cmd = FlightCommand::default();
 
     'main: loop {
         if let Some(Event {
@@ -33,22 +37,22 @@ pub fn remote(remote_tx: Sender<Command>) {
                 EventType::ButtonChanged(Button::LeftTrigger2, value, _) => {
                     watchdog = Instant::now();
                     // First command to take off so the motors shall start
-                    if !armed && value != 0.0 {
-                        armed = true;
-                        remote_tx.send(Command::Armed(true)).expect("Cannot send armed from remote to drone");
-                    }
                     // TODO check this behavior
-                    else if armed && value == 0.0 {
-                        armed = false;
-                        remote_tx.send(Command::Armed(false)).expect("Cannot send disarmed from remote to drone");
-                    }
-                    cmd.thrust = value;
+                    channels[mapping.arm] = if value != 0.0 {
+                        1.0
+                    } else {
+                        -1.0
+                    };
+                    channels[mapping.thrust] = 2.0 * value - 1.0;
+                    link.update(&channels)?;
                 },
                 EventType::AxisChanged(Axis::LeftStickY, value, _) => {
-                    cmd.angles.pitch = value;
+                    channels[mapping.pitch] = value;
+                    link.update(&channels)?;
                 },
                 EventType::AxisChanged(Axis::LeftStickX, value, _) => {
-                    cmd.angles.roll = value;
+                    channels[mapping.roll] = value;
+                    link.update(&channels)?;
                 },
                 EventType::ButtonChanged(
                     button @ Button::North | button @ Button::South | button @ Button::East | button @ Button::West,
@@ -67,12 +71,10 @@ pub fn remote(remote_tx: Sender<Command>) {
                     } else {
                         motor_on
                     };
-                    remote_tx
-                        .send(Command::SetMotor {
-                            motor,
-                            value,
-                        })
-                        .expect("Cannot send debug command from remote to drone");
+                    link.send(Command::SetMotor {
+                        motor,
+                        value,
+                    })?;
                 },
                 EventType::ButtonPressed(Button::DPadUp, _) => {
                     motor_on += 5000;
@@ -92,19 +94,13 @@ pub fn remote(remote_tx: Sender<Command>) {
                     // println!("Not handled event: {:?}", event);
                 },
             }
-        } else {
-            // No event during the previous second so the motors shall stop
-            if armed && watchdog.elapsed().as_millis() > 10000 {
-                remote_tx.send(Command::Armed(false)).expect("Cannot send disarmed from remote to drone");
-                armed = false;
-            }
+        } else if watchdog.elapsed() > WATCHDOG {
+            // No trigger event for a while so the motors shall stop
+            link.lost()?;
         }
 
-        // Rate limiter at 20Hz (T = 50ms)
-        if rate_limiter.elapsed().as_millis() > 50 {
-            remote_tx.send(Command::Flight(cmd)).expect("Cannot send command from remote to drone");
-            rate_limiter = Instant::now();
-        }
+        link.tick()?;
         std::thread::sleep(Duration::from_millis(5));
     }
+    Ok(())
 }
@@ -0,0 +1,294 @@
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use crate::config;
+use crate::types::{Angles, Command, CommandSender, FlightCommand};
+
+mod crsf;
+mod gamepad;
+mod sbus;
+mod serial;
+mod udp;
+
+pub use crsf::CrsfDecoder;
+pub use remote_protocol::RemoteMessage;
+pub use sbus::SbusDecoder;
+pub use serial::{open_serial, serial_remote};
+pub use udp::udp_remote;
+
+/// Number of RC channels decoded from the receivers
+pub const CHANNELS: usize = 16;
+/// Flight command rate limiter period (20Hz)
+const COMMAND_PERIOD: Duration = Duration::from_millis(50);
+/// Longest time without gamepad or network event before disarming
+const WATCHDOG: Duration = Duration::from_secs(10);
+
+/// Remote backend
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteKind {
+    /// Bluetooth or USB gamepad
+    #[default]
+    Gamepad,
+    /// Futaba SBUS receiver (100000 baud 8E2, inverted signal)
+    Sbus,
+    /// Crossfire or ExpressLRS receiver (420000 baud 8N1)
+    Crsf,
+    /// Companion tool sending the commands over UDP
+    Udp,
+}
+
+/// RC channel assigned to each command, counted from 0
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct ChannelMapping {
+    pub roll: usize,
+    pub pitch: usize,
+    pub thrust: usize,
+    pub yaw: usize,
+    /// Arming switch, armed when high
+    pub arm: usize,
+}
+
+impl Default for ChannelMapping {
+    /// AETR ordering with the arming switch on the fifth channel
+    fn default() -> Self {
+        Self {
+            roll: 0,
+            pitch: 1,
+            thrust: 2,
+            yaw: 3,
+            arm: 4,
+        }
+    }
+}
+
+impl ChannelMapping {
+    /// Converts the channels normalized in [-1, 1] into a flight command and the arming switch state.
+    /// The thrust stick covers the whole channel range and the yaw channel is positive to the right.
+    pub fn command(&self, channels: &[f32; CHANNELS]) -> (FlightCommand, bool) {
+        let channel = |x: usize| channels.get(x).copied().unwrap_or(0.0);
+        let command = FlightCommand {
+            thrust: ((channel(self.thrust) + 1.0) / 2.0).clamp(0.0, 1.0),
+            angles: Angles {
+                roll: channel(self.roll),
+                pitch: channel(self.pitch),
+                yaw: -channel(self.yaw),
+            },
+        };
+        (command, channel(self.arm) > 0.5)
+    }
+}
+
+/// Remote configuration stored in the parameter file under `[remote]`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RemoteConfig {
+    pub kind: RemoteKind,
+    /// Serial port of the RC receiver
+    pub device: PathBuf,
+    /// Local UDP address of the network remote
+    pub address: String,
+    pub mapping: ChannelMapping,
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        Self {
+            kind: RemoteKind::Gamepad,
+            device: PathBuf::from("/dev/ttyS4"),
+            address: "0.0.0.0:9100".into(),
+            mapping: ChannelMapping::default(),
+        }
+    }
+}
+
+impl RemoteConfig {
+    /// Loads the remote configuration from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("remote")
+    }
+}
+
+/// Decoded RC receiver frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RcFrame {
+    /// Channels normalized in [-1, 1]
+    pub channels: [f32; CHANNELS],
+    /// The receiver missed the last frame from the transmitter
+    pub frame_lost: bool,
+    /// The receiver lost the link with the transmitter
+    pub failsafe: bool,
+}
+
+impl Default for RcFrame {
+    fn default() -> Self {
+        Self {
+            channels: [0.0; CHANNELS],
+            frame_lost: false,
+            failsafe: false,
+        }
+    }
+}
+
+/// Converts a raw 11 bits channel (172 to 1811 centered on 992) into [-1, 1]
+fn normalize(raw: u16) -> f32 {
+    let offset = f32::from(raw) - 992.0;
+    let range = if offset < 0.0 {
+        820.0
+    } else {
+        819.0
+    };
+    (offset / range).clamp(-1.0, 1.0)
+}
+
+/// Unpacks 16 channels of 11 bits, least significant bit first, shared by SBUS and CRSF
+fn unpack_channels(data: &[u8]) -> [f32; CHANNELS] {
+    let mut channels = [0.0; CHANNELS];
+    for (i, channel) in channels.iter_mut().enumerate() {
+        let bit = i * 11;
+        let raw = data[bit / 8..].iter().take(3).rev().fold(0u32, |acc, x| (acc << 8) | u32::from(*x));
+        *channel = normalize(((raw >> (bit % 8)) & 0x7ff) as u16);
+    }
+    channels
+}
+
+/// Streaming decoder of an RC receiver protocol
+pub trait Decoder {
+    /// Feeds a received byte and returns the frame it completes if any
+    fn push(&mut self, byte: u8) -> Option<RcFrame>;
+}
+
+/// Link between a remote and the flight controller turning the channels into commands.
+/// It arms and disarms on the arming switch edges and disarms when the link is lost. After a link loss the arming
+/// switch must be released before arming again.
+struct Link {
+    remote_tx: CommandSender,
+    mapping: ChannelMapping,
+    armed: bool,
+    /// Arming is refused until the arming switch is released
+    locked: bool,
+    command: FlightCommand,
+    rate_limiter: Instant,
+}
+
+impl Link {
+    fn new(remote_tx: CommandSender, mapping: ChannelMapping) -> Self {
+        Self {
+            remote_tx,
+            mapping,
+            armed: false,
+            locked: false,
+            command: FlightCommand::default(),
+            rate_limiter: Instant::now(),
+        }
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        self.remote_tx.send(command).map_err(|_| anyhow!("Cannot send command from remote to drone"))
+    }
+
+    fn update(&mut self, channels: &[f32; CHANNELS]) -> Result<()> {
+        let (command, arm) = self.mapping.command(channels);
+        self.command = command;
+        self.arm(arm)
+    }
+
+    fn arm(&mut self, arm: bool) -> Result<()> {
+        if !arm {
+            self.locked = false;
+        }
+        if arm != self.armed && !self.locked {
+            self.armed = arm;
+            self.send(Command::Armed(arm))?;
+        }
+        Ok(())
+    }
+
+    fn lost(&mut self) -> Result<()> {
+        self.command = FlightCommand::default();
+        if self.armed {
+            log::warn!("Remote link lost, disarming");
+            self.armed = false;
+            self.locked = true;
+            self.send(Command::Armed(false))?;
+        }
+        Ok(())
+    }
+
+    /// Sends the flight command at the rate limiter frequency
+    fn tick(&mut self) -> Result<()> {
+        if self.rate_limiter.elapsed() > COMMAND_PERIOD {
+            self.send(Command::Flight(self.command))?;
+            self.rate_limiter = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+/// Runs the remote backend selected in the parameter file
+pub fn remote(config: RemoteConfig, remote_tx: CommandSender) {
+    let result = match config.kind {
+        RemoteKind::Gamepad => gamepad::gamepad(remote_tx, config.mapping),
+        RemoteKind::Udp => UdpSocket::bind(&config.address)
+            .with_context(|| format!("Binding remote to {}", config.address))
+            .and_then(|socket| udp_remote(socket, remote_tx)),
+        kind => open_serial(&config.device, kind).and_then(|port| serial_remote(port, kind, config.mapping, remote_tx)),
+    };
+    if let Err(err) = result {
+        log::error!("Remote: {:#}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::command_channel;
+
+    #[test]
+    fn test_mapping() {
+        let mut channels = [0.0; CHANNELS];
+        channels[0] = 0.5;
+        channels[1] = -0.25;
+        channels[2] = -1.0;
+        channels[3] = 1.0;
+        let (command, arm) = ChannelMapping::default().command(&channels);
+        assert_eq!(command.thrust, 0.0);
+        assert_eq!(command.angles.roll, 0.5);
+        assert_eq!(command.angles.pitch, -0.25);
+        assert_eq!(command.angles.yaw, -1.0);
+        assert!(!arm);
+
+        channels[4] = 1.0;
+        channels[2] = 0.0;
+        let (command, arm) = ChannelMapping::default().command(&channels);
+        assert_eq!(command.thrust, 0.5);
+        assert!(arm);
+    }
+
+    #[test]
+    fn test_link_loss() {
+        let (remote_tx, remote_rx) = command_channel();
+        let mut link = Link::new(remote_tx, ChannelMapping::default());
+        let mut channels = [0.0; CHANNELS];
+        channels[4] = 1.0;
+
+        link.update(&channels).unwrap();
+        assert!(matches!(remote_rx.try_recv().unwrap().1, Command::Armed(true)));
+        link.lost().unwrap();
+        assert!(matches!(remote_rx.try_recv().unwrap().1, Command::Armed(false)));
+
+        // The arming switch must be released before arming again
+        link.update(&channels).unwrap();
+        assert!(remote_rx.try_recv().is_err());
+        channels[4] = -1.0;
+        link.update(&channels).unwrap();
+        channels[4] = 1.0;
+        link.update(&channels).unwrap();
+        assert!(matches!(remote_rx.try_recv().unwrap().1, Command::Armed(true)));
+    }
+}
@@ -0,0 +1,101 @@
+use super::{unpack_channels, Decoder, RcFrame};
+
+const HEADER: u8 = 0x0f;
+const FRAME_LENGTH: usize = 25;
+const FLAGS: usize = 23;
+const FRAME_LOST: u8 = 0x04;
+const FAILSAFE: u8 = 0x08;
+
+/// SBUS decoder, a frame is a 0x0f header, 16 channels of 11 bits, a flag byte and a footer byte
+#[derive(Default)]
+pub struct SbusDecoder {
+    buffer: Vec<u8>,
+}
+
+/// SBUS footer, SBUS2 receivers send 0x04, 0x14, 0x24 or 0x34 to announce their telemetry slots
+fn is_footer(byte: u8) -> bool {
+    byte == 0x00 || byte & 0xcf == 0x04
+}
+
+impl Decoder for SbusDecoder {
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        if self.buffer.is_empty() && byte != HEADER {
+            return None;
+        }
+        self.buffer.push(byte);
+        if self.buffer.len() < FRAME_LENGTH {
+            return None;
+        }
+
+        if is_footer(self.buffer[FRAME_LENGTH - 1]) {
+            let frame = &self.buffer;
+            let frame = RcFrame {
+                channels: unpack_channels(&frame[1..FLAGS]),
+                frame_lost: frame[FLAGS] & FRAME_LOST != 0,
+                failsafe: frame[FLAGS] & FAILSAFE != 0,
+            };
+            self.buffer.clear();
+            Some(frame)
+        } else {
+            // Out of sync, resumes on the next header
+            let next = self.buffer[1..].iter().position(|x| *x == HEADER);
+            match next {
+                Some(next) => {
+                    self.buffer.drain(..=next);
+                },
+                None => self.buffer.clear(),
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Channels 1 to 4 at center, maximum, minimum and 1400, the others at center
+    const CAPTURE: [u8; 25] = [
+        0x0f, 0xe0, 0x9b, 0x38, 0x2b, 0xf0, 0x0a, 0x3e, 0xf0, 0x81, 0x0f, 0x7c, 0xe0, 0x03, 0x1f, 0xf8, 0xc0, 0x07,
+        0x3e, 0xf0, 0x81, 0x0f, 0x7c, 0x00, 0x00,
+    ];
+    /// All channels at center with the frame lost and failsafe flags
+    const FAILSAFE_CAPTURE: [u8; 25] = [
+        0x0f, 0xe0, 0x03, 0x1f, 0xf8, 0xc0, 0x07, 0x3e, 0xf0, 0x81, 0x0f, 0x7c, 0xe0, 0x03, 0x1f, 0xf8, 0xc0, 0x07,
+        0x3e, 0xf0, 0x81, 0x0f, 0x7c, 0x0c, 0x00,
+    ];
+
+    fn decode(stream: &[u8]) -> Vec<RcFrame> {
+        let mut decoder = SbusDecoder::default();
+        stream.iter().filter_map(|x| decoder.push(*x)).collect()
+    }
+
+    #[test]
+    fn test_decode() {
+        let frames = decode(&CAPTURE);
+        assert_eq!(frames.len(), 1);
+        let channels = frames[0].channels;
+        assert_eq!(&channels[..3], &[0.0, 1.0, -1.0]);
+        assert!((channels[3] - 0.4979).abs() < 1e-3, "channel {}", channels[3]);
+        assert!(channels[4..].iter().all(|x| *x == 0.0));
+        assert!(!frames[0].frame_lost && !frames[0].failsafe);
+
+        let frames = decode(&FAILSAFE_CAPTURE);
+        assert!(frames[0].frame_lost && frames[0].failsafe);
+    }
+
+    #[test]
+    fn test_resync() {
+        // Starts in the middle of a frame, then a corrupted footer
+        let mut stream = CAPTURE[10..].to_vec();
+        let mut corrupted = CAPTURE;
+        corrupted[24] = 0xff;
+        stream.extend(corrupted);
+        stream.extend(CAPTURE);
+        stream.extend(FAILSAFE_CAPTURE);
+        let frames = decode(&stream);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].channels[1], 1.0);
+        assert!(frames[1].failsafe);
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+use super::{ChannelMapping, CrsfDecoder, Decoder, Link, RemoteKind, SbusDecoder};
+use crate::types::CommandSender;
+
+/// Longest time without valid frame before considering the link lost
+const LINK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Opens and configures the serial port of an RC receiver in raw mode.
+/// The reads time out after 100ms so that the link loss is detected even when the receiver is silent.
+pub fn open_serial(path: &Path, kind: RemoteKind) -> Result<File> {
+    let (baudrate, flags) = match kind {
+        RemoteKind::Sbus => (100_000, libc::PARENB | libc::CSTOPB),
+        RemoteKind::Crsf => (420_000, 0),
+        kind => bail!("{:?} is not a serial remote", kind),
+    };
+    let port = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)
+        .with_context(|| format!("Opening {}", path.display()))?;
+
+    // termios2 allows the non standard baudrates of the RC protocols
+    let mut tty: libc::termios2 = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(port.as_raw_fd(), libc::TCGETS2, &mut tty) } < 0 {
+        return Err(io::Error::last_os_error()).with_context(|| format!("Reading {} settings", path.display()));
+    }
+    tty.c_iflag = 0;
+    tty.c_oflag = 0;
+    tty.c_lflag = 0;
+    tty.c_cflag = libc::BOTHER | libc::CS8 | libc::CREAD | libc::CLOCAL | flags;
+    tty.c_ispeed = baudrate;
+    tty.c_ospeed = baudrate;
+    tty.c_cc[libc::VMIN] = 0;
+    tty.c_cc[libc::VTIME] = 1;
+    if unsafe { libc::ioctl(port.as_raw_fd(), libc::TCSETS2, &tty) } < 0 {
+        return Err(io::Error::last_os_error()).with_context(|| format!("Configuring {}", path.display()));
+    }
+    Ok(port)
+}
+
+/// Reads an RC receiver and sends its commands until the flight controller stops or the port fails.
+/// The failsafe flag of the receiver and the lack of valid frames are handled as a link loss.
+pub fn serial_remote<R: Read>(
+    mut port: R,
+    kind: RemoteKind,
+    mapping: ChannelMapping,
+    remote_tx: CommandSender,
+) -> Result<()> {
+    let mut decoder: Box<dyn Decoder> = match kind {
+        RemoteKind::Sbus => Box::<SbusDecoder>::default(),
+        RemoteKind::Crsf => Box::<CrsfDecoder>::default(),
+        kind => bail!("{:?} is not a serial remote", kind),
+    };
+    let mut link = Link::new(remote_tx, mapping);
+    let mut last_frame = Instant::now();
+    let mut frame_lost = 0u64;
+    let mut buffer = [0; 64];
+    log::info!("Remote reading {:?} receiver", kind);
+
+    loop {
+        let read = match port.read(&mut buffer) {
+            Ok(read) => read,
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => 0,
+            Err(err) => return Err(err).context("Reading RC receiver"),
+        };
+        for frame in buffer[..read].iter().filter_map(|x| decoder.push(*x)) {
+            if frame.failsafe {
+                link.lost()?;
+            } else if frame.frame_lost {
+                frame_lost += 1;
+                log::debug!("RC frames lost: {}", frame_lost);
+            } else {
+                last_frame = Instant::now();
+                link.update(&frame.channels)?;
+            }
+        }
+        if last_frame.elapsed() > LINK_TIMEOUT {
+            link.lost()?;
+        }
+        link.tick()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{command_channel, Command};
+    use std::ffi::CStr;
+    use std::io::Write;
+    use std::os::unix::io::FromRawFd;
+    use std::path::PathBuf;
+    use std::thread;
+
+    /// Opens a pseudo terminal and returns its master side and the path of its slave side
+    fn pty() -> (File, PathBuf) {
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(fd >= 0);
+            assert_eq!(libc::grantpt(fd), 0);
+            assert_eq!(libc::unlockpt(fd), 0);
+            let mut name = [0 as libc::c_char; 64];
+            assert_eq!(libc::ptsname_r(fd, name.as_mut_ptr(), name.len()), 0);
+            let path = CStr::from_ptr(name.as_ptr()).to_str().unwrap().into();
+            (File::from_raw_fd(fd), path)
+        }
+    }
+
+    /// CRSF channels with the throttle at minimum and the arming switch (channel 5) high
+    const ARMED: [u8; 26] = [
+        0xc8, 0x18, 0x16, 0xe0, 0x03, 0x1f, 0x2b, 0xc0, 0x37, 0x71, 0xf0, 0x81, 0x0f, 0x7c, 0xe0, 0x03, 0x1f, 0xf8,
+        0xc0, 0x07, 0x3e, 0xf0, 0x81, 0x0f, 0x7c, 0x01,
+    ];
+
+    #[test]
+    fn test_pty() {
+        let (mut master, path) = pty();
+        let port = open_serial(&path, RemoteKind::Crsf).unwrap();
+        let (remote_tx, remote_rx) = command_channel();
+        thread::spawn(move || serial_remote(port, RemoteKind::Crsf, ChannelMapping::default(), remote_tx));
+
+        master.write_all(&ARMED).unwrap();
+        let command = remote_rx.recv_timeout(Duration::from_secs(1)).unwrap().1;
+        assert!(matches!(command, Command::Armed(true)), "{:?}", command);
+
+        // The receiver goes silent
+        let start = Instant::now();
+        loop {
+            match remote_rx.recv_timeout(Duration::from_secs(1)).unwrap().1 {
+                Command::Armed(false) => break,
+                Command::Flight(command) => assert_eq!(command.thrust, 0.0),
+                command => panic!("Unexpected command {:?}", command),
+            }
+        }
+        assert!(start.elapsed() >= LINK_TIMEOUT - Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,134 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use remote_protocol::RemoteMessage;
+
+use super::{ChannelMapping, Link, WATCHDOG};
+use crate::types::{Angles, Command, CommandSender, FlightCommand};
+
+/// Socket read timeout so that the watchdog runs when the sender is silent
+const READ_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Receives the commands of the network remote until the flight controller stops.
+/// The session belongs to the first sender until nothing is received from it for 10s, then the drone disarms like with
+/// the gamepad and the next sender starts a new session. The packets of the other senders and the ones older than the
+/// last one received are dropped. The flight commands are forwarded at 20Hz.
+pub fn udp_remote(socket: UdpSocket, remote_tx: CommandSender) -> Result<()> {
+    socket.set_read_timeout(Some(READ_TIMEOUT))?;
+    log::info!("Remote listening on {:?}", socket.local_addr());
+    let mut link = Link::new(remote_tx, ChannelMapping::default());
+    let mut session: Option<(SocketAddr, u32)> = None;
+    let mut watchdog = Instant::now();
+    let mut buffer = [0; 64];
+
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((length, peer)) => match RemoteMessage::decode(&buffer[..length]) {
+                Ok((sequence, message)) => {
+                    let stale = session.is_some_and(|(last_peer, last)| last_peer != peer || sequence <= last);
+                    if stale {
+                        log::debug!("Dropping remote packet {} from {}", sequence, peer);
+                    } else {
+                        session = Some((peer, sequence));
+                        watchdog = Instant::now();
+                        match message {
+                            RemoteMessage::Flight {
+                                thrust,
+                                roll,
+                                pitch,
+                                yaw,
+                            } => {
+                                link.command = FlightCommand {
+                                    thrust,
+                                    angles: Angles {
+                                        roll,
+                                        pitch,
+                                        yaw,
+                                    },
+                                }
+                            },
+                            RemoteMessage::Armed(armed) => link.arm(armed)?,
+                            RemoteMessage::Motor {
+                                motor,
+                                value,
+                            } => link.send(Command::SetMotor {
+                                motor: usize::from(motor),
+                                value,
+                            })?,
+                        }
+                    }
+                },
+                Err(err) => log::debug!("{:#} from {}", err, peer),
+            },
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => (),
+            Err(err) => return Err(err).context("Receiving remote packet"),
+        }
+
+        if watchdog.elapsed() > WATCHDOG {
+            session = None;
+            link.lost()?;
+        }
+        link.tick()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::command_channel;
+    use std::thread;
+
+    #[test]
+    fn test_udp_remote() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let (remote_tx, remote_rx) = command_channel();
+        thread::spawn(move || udp_remote(socket, remote_tx));
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let flight = |thrust| RemoteMessage::Flight {
+            thrust,
+            roll: 0.0,
+            pitch: 0.0,
+            yaw: 0.0,
+        };
+        sender.send_to(&RemoteMessage::Armed(true).encode(0), address).unwrap();
+        sender.send_to(&flight(0.5).encode(2), address).unwrap();
+        // Late packet, restarted sequence and other sender while the session is alive
+        sender.send_to(&flight(0.9).encode(1), address).unwrap();
+        sender.send_to(&flight(0.8).encode(0), address).unwrap();
+        let intruder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        intruder.send_to(&flight(0.7).encode(10), address).unwrap();
+        sender
+            .send_to(
+                &RemoteMessage::Motor {
+                    motor: 1,
+                    value: 215_000,
+                }
+                .encode(3),
+                address,
+            )
+            .unwrap();
+
+        assert!(matches!(remote_rx.recv_timeout(Duration::from_secs(1)).unwrap().1, Command::Armed(true)));
+        let mut motor = false;
+        let mut thrust = Vec::new();
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(300) {
+            match remote_rx.recv_timeout(Duration::from_secs(1)).unwrap().1 {
+                Command::Flight(command) => thrust.push(command.thrust),
+                Command::SetMotor {
+                    motor: 1,
+                    value: 215_000,
+                } => motor = true,
+                command => panic!("Unexpected command {:?}", command),
+            }
+        }
+        assert!(motor);
+        // Rate limited at 20Hz and the late and foreign packets are dropped
+        assert!(thrust.len() <= 7, "{} flight commands", thrust.len());
+        assert_eq!(thrust.last(), Some(&0.5));
+    }
+}
@@ -1,5 +1,6 @@
 use std::fs;
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 
@@ -12,8 +13,21 @@ use mpu9250::I2cDevice;
 use mpu9250::{Dmp, Mpu9250};
 use mpu9250::{DmpRate, MpuConfig};
 
+use crate::barometer::{altitude, BarometerConfig, Bmp280};
+use crate::calibration::{CalibrationHealth, ImuCalibration, MagCalibration, GRAVITY};
+use crate::estimator::{Estimator, EstimatorConfig};
+use crate::magnetometer::{self, heading, Ak8963, HeadingFusion, MagnetometerConfig};
+use crate::orientation::Orientation;
+use crate::polling::Timer;
 use crate::types::{Angles, Odometry};
 
+/// Longest sample period fed to the attitude estimator in s
+const MAX_SAMPLE_PERIOD: f64 = 0.05;
+/// Number of magnetometer read errors between two warnings
+const MAG_ERROR_LOG_INTERVAL: u32 = 100;
+/// Number of barometer read errors between two warnings
+const BARO_ERROR_LOG_INTERVAL: u32 = 100;
+
 #[derive(Debug)]
 pub enum Error {
     NotCalibarated,
@@ -33,41 +47,96 @@ impl std::fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+/// Raw IMU measures as provided by the DMP
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImuSample {
+    /// Angular rate in rad/s
+    pub gyro: [f32; 3],
+    /// Acceleration in m/s²
+    pub accel: [f32; 3],
+    /// Attitude quaternion
+    pub quaternion: [f64; 4],
+}
+
 pub struct Sensors {
     imu: Mpu9250<I2cDevice<I2cdev>, Dmp>,
     imu_pin: LineEventHandle,
     imu_calibrated: bool,
+    calibration: ImuCalibration,
+    health: CalibrationHealth,
+    orientation: Orientation,
+    estimator: Option<Box<dyn Estimator>>,
+    last_sample: Instant,
+    magnetometer: Option<(Ak8963<I2cdev>, Timer)>,
+    mag_config: MagnetometerConfig,
+    mag_calibration: MagCalibration,
+    mag: Option<[f32; 3]>,
+    mag_errors: u32,
+    heading: HeadingFusion,
+    barometer: Option<(Bmp280<I2cdev>, Timer)>,
+    baro_errors: u32,
+    pressure: Option<f32>,
+    ground_pressure: Option<f32>,
 }
 
 impl Sensors {
     /// Initiates all the sensors:
     /// - IMU: MPU9250
+    /// - Magnetometer: AK8963 if enabled
+    /// - Barometer: BMP280 if enabled
     pub fn new() -> Result<Self> {
-        let i2c = I2cdev::new("/dev/i2c-2").context("Opening i2c bus")?;
-
-        let mut mpu_config = MpuConfig::dmp();
-        mpu_config
-            .dmp_rate(DmpRate::_100Hz)
-            .dmp_features_raw_gyro(true)
-            .dmp_features_raw_accel(true)
-            .dmp_features_quat6(true)
-            .dmp_features_tap(true)
-            .dmp_features_gyro_auto_calibrate(true);
-
-        let dmp_firmware = fs::read("/lib/firmware/mpu_firmware.bin")?;
-
         // 117 : gpiochip3 => 3*32 = 96. 117 - 96 = 21
         let pin_event = Chip::new("/dev/gpiochip3")
             .and_then(|mut chip| chip.get_line(21))
             .and_then(|line| line.events(LineRequestFlags::INPUT, EventRequestFlags::FALLING_EDGE, "mpu9250"))
             .context("Registering IMU interrupt")?;
 
-        let mpu9250 = Mpu9250::dmp(i2c, &mut Delay, &mut mpu_config, &dmp_firmware).map_err(|e| Error::Mpu9250(e))?;
+        let mpu9250 = init_imu()?;
+
+        let calibration = ImuCalibration::load()?;
+        if calibration.is_none() {
+            log::warn!("IMU calibration not found, run calibrate-imu");
+        }
+
+        let mag_config = MagnetometerConfig::load()?;
+        let magnetometer = if mag_config.enabled {
+            Some((init_magnetometer(mag_config.rate)?, Timer::new(mag_config.period())?))
+        } else {
+            None
+        };
+        let mag_calibration = MagCalibration::load()?;
+        if magnetometer.is_some() && mag_calibration.is_none() {
+            log::warn!("Magnetometer calibration not found, run calibrate-mag");
+        }
+
+        let baro_config = BarometerConfig::load()?;
+        let barometer = if baro_config.enabled {
+            let i2c = I2cdev::new("/dev/i2c-2").context("Opening i2c bus")?;
+            let barometer = Bmp280::new(i2c, baro_config.address).context("Configuring barometer")?;
+            Some((barometer, Timer::new(Duration::from_millis(baro_config.period))?))
+        } else {
+            None
+        };
 
         Ok(Self {
             imu: mpu9250,
             imu_pin: pin_event,
-            imu_calibrated: false,
+            imu_calibrated: calibration.is_some(),
+            calibration: calibration.unwrap_or_default(),
+            health: CalibrationHealth::default(),
+            orientation: Orientation::load()?,
+            estimator: EstimatorConfig::load()?.build(),
+            last_sample: Instant::now(),
+            magnetometer,
+            mag_config,
+            mag_calibration: mag_calibration.unwrap_or_default(),
+            mag: None,
+            mag_errors: 0,
+            heading: HeadingFusion::default(),
+            barometer,
+            baro_errors: 0,
+            pressure: None,
+            ground_pressure: None,
         })
     }
 
@@ -75,51 +144,202 @@ impl Sensors {
         self.imu_pin.as_raw_fd()
     }
 
-    /// Handle an IMU event
+    /// Magnetometer polling timer if the magnetometer is enabled
+    pub fn magnetometer_event(&self) -> Option<RawFd> {
+        self.magnetometer.as_ref().map(|(_, timer)| timer.as_raw_fd())
+    }
+
+    /// Barometer polling timer if the barometer is enabled
+    pub fn barometer_event(&self) -> Option<RawFd> {
+        self.barometer.as_ref().map(|(_, timer)| timer.as_raw_fd())
+    }
+
+    /// IMU mounting orientation
+    pub fn orientation(&self) -> &Orientation {
+        &self.orientation
+    }
+
+    /// Reads the raw IMU measures after an IMU event
     /// - Return Error::NotAvailable if called to early
-    /// - Return Error::NotCalibarated during IMU internal calibration process
-    pub fn handle_imu_event(&mut self) -> Result<Odometry> {
+    pub fn read_imu(&mut self) -> Result<ImuSample> {
         self.imu_pin.get_event().context("Accessing IMU interrupt pin")?;
         match self.imu.dmp_all::<[f32; 3], [f64; 4]>() {
-            Ok(measure) => {
-                let gyro = measure.gyro.unwrap();
-                let accel = measure.accel.unwrap();
-                if self.imu_calibrated {
-                    let attitude = quat_to_angles(&measure.quaternion.unwrap());
-                    let thrust = compute_thrust(&accel, &attitude);
-                    Ok(Odometry {
-                        attitude,
-                        rate: Angles {
-                            roll: gyro[2],
-                            pitch: gyro[0],
-                            yaw: gyro[1],
-                        },
-                        thrust,
-                    })
-                } else {
-                    self.imu_calibrated = gyro.iter().all(|x| *x < 0.005);
-                    if self.imu_calibrated {
-                        log::info!("Calibrated");
-                    }
-                    Err(Error::NotCalibarated.into())
-                }
-            },
+            Ok(measure) => Ok(ImuSample {
+                gyro: measure.gyro.unwrap(),
+                accel: measure.accel.unwrap(),
+                quaternion: measure.quaternion.unwrap(),
+            }),
             Err(mpu9250::Error::DmpDataNotReady) => Err(Error::NotAvailable.into()),
             Err(x) => Err(Error::Mpu9250(x).into()),
         }
     }
 
+    /// Reads the raw magnetometer measure if a new one is available
+    pub fn read_raw_mag(&mut self) -> Result<Option<[f32; 3]>> {
+        match self.magnetometer.as_mut().map(|(magnetometer, _)| magnetometer.read()) {
+            None | Some(Err(magnetometer::Error::NotReady)) => Ok(None),
+            Some(Ok(mag)) => Ok(Some(mag)),
+            Some(Err(err)) => Err(err.into()),
+        }
+    }
+
+    /// Last calibrated magnetic field in µT expressed in the drone frame
+    pub fn magnetometer(&self) -> [f32; 3] {
+        self.mag.unwrap_or_default()
+    }
+
+    /// Handle an IMU event
+    /// - Return Error::NotAvailable if called to early
+    /// - Return Error::NotCalibarated during IMU internal calibration process when no calibration is stored
+    pub fn handle_imu_event(&mut self) -> Result<Odometry> {
+        let sample = self.read_imu()?;
+        if !self.imu_calibrated {
+            // Without stored calibration, wait for the DMP gyro auto calibration to settle
+            self.imu_calibrated = sample.gyro.iter().all(|x| *x < 0.005);
+            if self.imu_calibrated {
+                log::info!("Calibrated");
+            }
+            return Err(Error::NotCalibarated.into());
+        }
+
+        let sample = ImuSample {
+            gyro: self.calibration.gyro(&sample.gyro),
+            accel: self.calibration.accel(&sample.accel),
+            quaternion: sample.quaternion,
+        };
+        self.health.update(&sample.gyro, &sample.accel);
+
+        let fused_mag = self.mag.filter(|_| self.mag_config.fuse_yaw);
+
+        let mut sample = self.orientation.apply(&sample);
+        let now = Instant::now();
+        if let Some(estimator) = self.estimator.as_mut() {
+            let dt = now.duration_since(self.last_sample).as_secs_f64().min(MAX_SAMPLE_PERIOD);
+            sample.quaternion = estimator.update(&sample.gyro, &sample.accel, fused_mag.as_ref(), dt);
+        }
+        self.last_sample = now;
+
+        let mut odometry = odometry(&sample, &self.calibration);
+        if let (None, Some(mag)) = (&self.estimator, fused_mag) {
+            let heading = heading(&mag, &odometry.attitude);
+            odometry.attitude.yaw = self.heading.fuse(odometry.attitude.yaw, heading, self.mag_config.gain);
+        }
+        Ok(odometry)
+    }
+
+    /// Handle a magnetometer timer event by reading a new calibrated measure if available
+    /// The read errors are only logged from time to time as the magnetometer is not critical.
+    pub fn handle_magnetometer_event(&mut self) -> Result<()> {
+        if let Some((_, timer)) = self.magnetometer.as_mut() {
+            timer.handle_event()?;
+            match self.read_raw_mag() {
+                Ok(Some(raw_mag)) => self.mag = Some(self.orientation.rotate(&self.mag_calibration.apply(&raw_mag))),
+                Ok(None) => (),
+                Err(err) => {
+                    if self.mag_errors.is_multiple_of(MAG_ERROR_LOG_INTERVAL) {
+                        log::warn!("{} ({} errors)", err, self.mag_errors + 1);
+                    }
+                    self.mag_errors += 1;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a barometer timer event by reading a new temperature compensated pressure
+    /// The read errors are only logged from time to time and the last pressure is kept.
+    pub fn handle_barometer_event(&mut self) -> Result<()> {
+        if let Some((barometer, timer)) = self.barometer.as_mut() {
+            timer.handle_event()?;
+            match barometer.read() {
+                Ok(measure) => self.pressure = Some(measure.pressure),
+                Err(err) => {
+                    if self.baro_errors.is_multiple_of(BARO_ERROR_LOG_INTERVAL) {
+                        log::warn!("{} ({} errors)", err, self.baro_errors + 1);
+                    }
+                    self.baro_errors += 1;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Captures the current pressure as the ground level reference
+    pub fn set_ground_reference(&mut self) {
+        if let Some(pressure) = self.pressure {
+            log::info!("Ground pressure: {:.0}Pa", pressure);
+            self.ground_pressure = Some(pressure);
+        }
+    }
+
+    /// Altitude above the ground reference in m or 0 without reference
+    pub fn altitude(&self) -> f32 {
+        match (self.pressure, self.ground_pressure) {
+            (Some(pressure), Some(reference)) => altitude(pressure, reference),
+            _ => 0.0,
+        }
+    }
+
     /// Reset the IMU internal state keeping the same config
     pub fn clean_imu(&mut self) -> Result<()> {
         self.imu.reset_fifo(&mut Delay).map_err(|e| Error::Mpu9250(e).into())
     }
+
+    /// Re-initializes the MPU9250 and its DMP from scratch
+    /// The magnetometer is configured again as it is accessed through the MPU9250 bypass.
+    pub fn reinit_imu(&mut self) -> Result<()> {
+        self.imu = init_imu()?;
+        if let Some((magnetometer, _)) = self.magnetometer.as_mut() {
+            *magnetometer = init_magnetometer(self.mag_config.rate)?;
+        }
+        self.last_sample = Instant::now();
+        Ok(())
+    }
+}
+
+/// Configures the MPU9250 with the DMP at 100Hz
+fn init_imu() -> Result<Mpu9250<I2cDevice<I2cdev>, Dmp>> {
+    let i2c = I2cdev::new("/dev/i2c-2").context("Opening i2c bus")?;
+
+    let mut mpu_config = MpuConfig::dmp();
+    mpu_config
+        .dmp_rate(DmpRate::_100Hz)
+        .dmp_features_raw_gyro(true)
+        .dmp_features_raw_accel(true)
+        .dmp_features_quat6(true)
+        .dmp_features_tap(true)
+        .dmp_features_gyro_auto_calibrate(true);
+
+    let dmp_firmware = fs::read("/lib/firmware/mpu_firmware.bin")?;
+
+    Mpu9250::dmp(i2c, &mut Delay, &mut mpu_config, &dmp_firmware).map_err(|e| Error::Mpu9250(e).into())
+}
+
+/// Configures the AK8963 through the MPU9250 bypass
+fn init_magnetometer(rate: u16) -> Result<Ak8963<I2cdev>> {
+    let i2c = I2cdev::new("/dev/i2c-2").context("Opening i2c bus")?;
+    Ak8963::new(i2c, rate).context("Configuring magnetometer")
+}
+
+/// Computes the odometry from a calibrated IMU sample expressed in the drone frame
+fn odometry(sample: &ImuSample, calibration: &ImuCalibration) -> Odometry {
+    let attitude = calibration.attitude(quat_to_angles(&sample.quaternion));
+    let thrust = compute_thrust(&sample.accel, &attitude);
+    Odometry {
+        attitude,
+        rate: Angles {
+            roll: sample.gyro[0],
+            pitch: sample.gyro[1],
+            yaw: sample.gyro[2],
+        },
+        thrust,
+    }
 }
 
-fn quat_to_angles(q: &[f64; 4]) -> Angles {
-    // roll and pitch are swapped due to hardware positioning of the IMU
+pub fn quat_to_angles(q: &[f64; 4]) -> Angles {
     Angles {
-        roll: f64::asin(2.0 * (q[0] * q[2] - q[1] * q[3])) as f32,
-        pitch: f64::atan2(2.0 * (q[2] * q[3] + q[0] * q[1]), 1.0 - 2.0 * (q[1] * q[1] + q[2] * q[2])) as f32,
+        roll: f64::atan2(2.0 * (q[0] * q[1] + q[2] * q[3]), 1.0 - 2.0 * (q[1] * q[1] + q[2] * q[2])) as f32,
+        pitch: f64::asin(2.0 * (q[0] * q[2] - q[1] * q[3])) as f32,
         yaw: f64::atan2(2.0 * (q[1] * q[2] + q[0] * q[3]), 1.0 - 2.0 * (q[2] * q[2] + q[3] * q[3])) as f32,
     }
 }
@@ -127,20 +347,89 @@ fn quat_to_angles(q: &[f64; 4]) -> Angles {
 // Compute the thrust with the weight removed for any orientation angles are in radian
 // TODO not used yet. Needs verification and testing
 fn compute_thrust(accel: &[f32; 3], angles: &Angles) -> f32 {
-    f32::from(accel[0]) * angles.roll.sin() * -1.0
-        + f32::from(accel[1]) * angles.pitch.sin() * angles.roll.cos()
-        + f32::from(accel[2]) * angles.pitch.cos() * angles.roll.cos()
-        - 9.5
+    -accel[0] * angles.pitch.sin()
+        + accel[1] * angles.roll.sin() * angles.pitch.cos()
+        + accel[2] * angles.roll.cos() * angles.pitch.cos()
+        - GRAVITY
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::orientation::{quat_from_euler, quat_mul, OrientationConfig, OrientationPreset};
     use crate::polling::Poller;
     use mio::{Interest, Token};
     use std::thread;
     use std::time::{Duration, Instant};
 
+    fn mounted(preset: OrientationPreset) -> Orientation {
+        Orientation::from(OrientationConfig {
+            preset,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_odometry_roll_signs() {
+        // IMU x axis points to the left of the drone while the drone rolls right side down
+        let orientation = mounted(OrientationPreset::Yaw90);
+        let roll: f32 = 0.3;
+        let sample = ImuSample {
+            gyro: [0.0, -0.5, 0.0],
+            accel: [roll.sin() * GRAVITY, 0.0, roll.cos() * GRAVITY],
+            quaternion: quat_mul(
+                &quat_from_euler(roll.into(), 0.0, 0.0),
+                &quat_from_euler(0.0, 0.0, 90f64.to_radians()),
+            ),
+        };
+        let odometry = odometry(&orientation.apply(&sample), &ImuCalibration::default());
+        assert!((odometry.attitude.roll - roll).abs() < 1e-5);
+        assert!(odometry.attitude.pitch.abs() < 1e-5);
+        assert!(odometry.attitude.yaw.abs() < 1e-5);
+        assert!((odometry.rate.roll - 0.5).abs() < 1e-5);
+        assert!(odometry.rate.pitch.abs() < 1e-5);
+        assert!(odometry.thrust.abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_odometry_yaw_signs() {
+        // IMU mounted upside down while the drone turns left
+        let orientation = mounted(OrientationPreset::Roll180);
+        let sample = ImuSample {
+            gyro: [0.0, 0.0, -0.4],
+            accel: [0.0, 0.0, -GRAVITY],
+            quaternion: quat_from_euler(std::f64::consts::PI, 0.0, 0.0),
+        };
+        let odometry = odometry(&orientation.apply(&sample), &ImuCalibration::default());
+        assert!(odometry.attitude.roll.abs() < 1e-5);
+        assert!(odometry.attitude.pitch.abs() < 1e-5);
+        assert!((odometry.rate.yaw - 0.4).abs() < 1e-5);
+        assert!(odometry.thrust.abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_odometry_pitch_signs() {
+        // Drone pitching nose down with a level trim
+        let orientation = mounted(OrientationPreset::None);
+        let pitch: f32 = 0.2;
+        let calibration = ImuCalibration {
+            level: Angles {
+                roll: 0.0,
+                pitch: 0.05,
+                yaw: 0.0,
+            },
+            ..Default::default()
+        };
+        let sample = ImuSample {
+            gyro: [0.0, 0.7, 0.0],
+            accel: [-(pitch + 0.05).sin() * GRAVITY, 0.0, (pitch + 0.05).cos() * GRAVITY],
+            quaternion: quat_from_euler(0.0, (pitch + 0.05).into(), 0.0),
+        };
+        let odometry = odometry(&orientation.apply(&sample), &calibration);
+        assert!((odometry.attitude.pitch - pitch).abs() < 1e-5);
+        assert!((odometry.rate.pitch - 0.7).abs() < 1e-5);
+    }
+
     #[test]
     fn test_sensors_config() {
         // Setup interrupt infrastructure
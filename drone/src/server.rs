@@ -0,0 +1,494 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::config;
+use crate::types::{Angles, Command, CommandSender, DebugConfig, FlightCommand, PidAxis, PidConfig, TelemetryHub};
+
+/// Largest accepted HTTP request
+const MAX_REQUEST: usize = 16 * 1024;
+/// Period at which the connections check for telemetry updates and for the stop flag
+const POLL_PERIOD: Duration = Duration::from_millis(20);
+
+/// Backend server configuration stored in the parameter file under `[server]`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub enabled: bool,
+    pub address: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "0.0.0.0:8080".into(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the server configuration from the parameter file.
+    pub fn load() -> Result<Self> {
+        config::load("server")
+    }
+}
+
+/// Command accepted by the server as JSON, e.g. `{"command": "flight", "thrust": 0.5}`
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Request {
+    Arm,
+    Disarm,
+    Flight {
+        #[serde(default)]
+        thrust: f32,
+        #[serde(default)]
+        roll: f32,
+        #[serde(default)]
+        pitch: f32,
+        #[serde(default)]
+        yaw: f32,
+    },
+    Pid {
+        axis: PidAxis,
+        #[serde(flatten)]
+        pid: PidConfig,
+    },
+}
+
+impl TryFrom<Request> for Command {
+    type Error = anyhow::Error;
+
+    /// Rejects the flight commands with a non finite value, a thrust outside of [0, 1] or a stick outside of [-1, 1]
+    fn try_from(request: Request) -> Result<Self> {
+        Ok(match request {
+            Request::Arm => Command::Armed(true),
+            Request::Disarm => Command::Armed(false),
+            Request::Flight {
+                thrust,
+                roll,
+                pitch,
+                yaw,
+            } => {
+                if !(0.0..=1.0).contains(&thrust) || [roll, pitch, yaw].iter().any(|x| !(-1.0..=1.0).contains(x)) {
+                    bail!("Invalid flight command, the thrust shall be in [0, 1] and the sticks in [-1, 1]");
+                }
+                Command::Flight(FlightCommand {
+                    thrust,
+                    angles: Angles {
+                        roll,
+                        pitch,
+                        yaw,
+                    },
+                })
+            },
+            Request::Pid {
+                axis,
+                pid,
+            } => Command::SetPid {
+                axis,
+                pid,
+            },
+        })
+    }
+}
+
+struct State {
+    command_tx: CommandSender,
+    telemetry: TelemetryHub,
+    /// Parameter file read and written by the parameter routes
+    params: PathBuf,
+}
+
+/// Backend server exposing the flight state, the telemetry and the parameters over HTTP and WebSocket.
+///
+/// - `GET /api/telemetry` last telemetry
+/// - `GET /api/params` and `GET /api/params/<key>` parameters
+/// - `PUT /api/params/<key>` stores and applies a PID or the debug configuration, the PIDs take effect at the next
+///   arming
+/// - `POST /api/command` sends a command
+/// - `GET /api/ws` WebSocket streaming the telemetry and accepting commands
+pub struct Server {
+    listener: TcpListener,
+    state: Arc<State>,
+}
+
+impl Server {
+    pub fn bind(address: &str, command_tx: CommandSender, telemetry: TelemetryHub, params: PathBuf) -> Result<Self> {
+        let listener = TcpListener::bind(address).with_context(|| format!("Binding server to {}", address))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            state: Arc::new(State {
+                command_tx,
+                telemetry,
+                params,
+            }),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves the connections until the stop flag is raised
+    pub fn run(&self, stop: Arc<AtomicBool>) {
+        log::info!("Server listening on {:?}", self.listener.local_addr());
+        while !stop.load(Ordering::Relaxed) {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    let state = Arc::clone(&self.state);
+                    let stop = Arc::clone(&stop);
+                    let _ = thread::Builder::new().name("connection".into()).spawn(move || {
+                        if let Err(err) = handle_connection(stream, &state, &stop) {
+                            log::warn!("Connection from {}: {:#}", peer, err);
+                        }
+                    });
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_PERIOD),
+                Err(err) => log::warn!("Accepting connection: {}", err),
+            }
+        }
+    }
+}
+
+pub(crate) struct HttpRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    websocket_key: Option<String>,
+    body: Vec<u8>,
+}
+
+pub(crate) fn read_request(stream: &mut TcpStream) -> Result<HttpRequest> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 1024];
+    loop {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            bail!("Connection closed");
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut request = httparse::Request::new(&mut headers);
+        if let httparse::Status::Complete(head) = request.parse(&buffer)? {
+            let header = |name: &str| {
+                request
+                    .headers
+                    .iter()
+                    .find(|header| header.name.eq_ignore_ascii_case(name))
+                    .and_then(|header| std::str::from_utf8(header.value).ok())
+            };
+            let length: usize = header("Content-Length").map(str::parse).transpose()?.unwrap_or(0);
+            if head + length > MAX_REQUEST {
+                bail!("Request too large");
+            }
+            let upgrade = header("Upgrade").is_some_and(|x| x.eq_ignore_ascii_case("websocket"));
+            let request = HttpRequest {
+                method: request.method.unwrap_or_default().into(),
+                path: request.path.unwrap_or_default().into(),
+                websocket_key: header("Sec-WebSocket-Key").filter(|_| upgrade).map(String::from),
+                body: buffer[head..].to_vec(),
+            };
+            return read_body(stream, request, length);
+        }
+        if buffer.len() > MAX_REQUEST {
+            bail!("Request too large");
+        }
+    }
+}
+
+fn read_body(stream: &mut TcpStream, mut request: HttpRequest, length: usize) -> Result<HttpRequest> {
+    if request.body.len() < length {
+        let mut rest = vec![0; length - request.body.len()];
+        stream.read_exact(&mut rest)?;
+        request.body.extend(rest);
+    }
+    request.body.truncate(length);
+    Ok(request)
+}
+
+fn handle_connection(mut stream: TcpStream, state: &State, stop: &AtomicBool) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let request = read_request(&mut stream)?;
+
+    if let (Some(key), "/api/ws") = (request.websocket_key.as_ref(), request.path.as_str()) {
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: \
+             {}\r\n\r\n",
+            derive_accept_key(key.as_bytes())
+        )?;
+        stream.set_read_timeout(Some(POLL_PERIOD))?;
+        return handle_websocket(WebSocket::from_raw_socket(stream, Role::Server, None), state, stop);
+    }
+
+    let (status, body) = match route(&request, state) {
+        Ok(body) => ("200 OK", body),
+        Err(err) if err.is::<NotFound>() => ("404 Not Found", json!({ "error": err.to_string() })),
+        Err(err) => ("400 Bad Request", json!({ "error": format!("{:#}", err) })),
+    };
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct NotFound(String);
+
+impl std::fmt::Display for NotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Not found: {}", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+fn route(request: &HttpRequest, state: &State) -> Result<Value> {
+    let param = request.path.strip_prefix("/api/params/");
+    match (request.method.as_str(), request.path.as_str(), param) {
+        ("GET", "/api/telemetry", _) => Ok(serde_json::to_value(state.telemetry.latest().1)?),
+        ("POST", "/api/command", _) => {
+            send_command(state, serde_json::from_slice(&request.body)?)?;
+            Ok(json!({ "status": "ok" }))
+        },
+        ("GET", "/api/params", _) => Ok(serde_json::to_value(read_params(state)?)?),
+        ("GET", _, Some(key)) => {
+            let params = read_params(state)?;
+            let value = params.get(key).ok_or_else(|| NotFound(key.into()))?;
+            Ok(serde_json::to_value(value)?)
+        },
+        ("PUT", _, Some(key)) => {
+            store_param(state, key, serde_json::from_slice(&request.body)?)?;
+            Ok(json!({ "status": "ok" }))
+        },
+        _ => Err(NotFound(request.path.clone()).into()),
+    }
+}
+
+fn read_params(state: &State) -> Result<toml::Table> {
+    fs::read_to_string(&state.params)
+        .context("Reading parameter file")?
+        .parse::<toml::Table>()
+        .context("Parsing parameter file")
+}
+
+/// Checks and stores one of the writable parameters
+fn store_param(state: &State, key: &str, value: Value) -> Result<()> {
+    let axis = match key {
+        "roll_pid" => PidAxis::Roll,
+        "pitch_pid" => PidAxis::Pitch,
+        "yaw_pid" => PidAxis::Yaw,
+        "debug_config" => {
+            let debug: DebugConfig = serde_json::from_value(value)?;
+            config::store(&state.params, key, &debug)?;
+            state.command_tx.send(Command::SwitchDebug(debug))?;
+            return Ok(());
+        },
+        _ => bail!("Parameter {} is not writable", key),
+    };
+    let pid: PidConfig = serde_json::from_value(value)?;
+    config::store(&state.params, key, &pid)?;
+    state.command_tx.send(Command::SetPid {
+        axis,
+        pid,
+    })?;
+    Ok(())
+}
+
+fn send_command(state: &State, request: Request) -> Result<()> {
+    log::info!("Server command: {:?}", request);
+    let command = Command::try_from(request)?;
+    state.command_tx.send(command).map_err(|_| anyhow!("Flight controller stopped"))
+}
+
+fn handle_websocket(mut websocket: WebSocket<TcpStream>, state: &State, stop: &AtomicBool) -> Result<()> {
+    let mut sequence = 0;
+    while !stop.load(Ordering::Relaxed) {
+        match websocket.read() {
+            Ok(Message::Text(text)) => {
+                let answer = match serde_json::from_str(&text) {
+                    Ok(request) => send_command(state, request).map(|()| json!({ "status": "ok" })),
+                    Err(err) => Err(err.into()),
+                }
+                .unwrap_or_else(|err| json!({ "error": format!("{:#}", err) }));
+                websocket.send(Message::Text(answer.to_string()))?;
+            },
+            Ok(Message::Close(_)) => break,
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(err))
+                if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+            Err(tungstenite::Error::ConnectionClosed) => break,
+            Err(err) => return Err(err.into()),
+        }
+
+        let (latest, telemetry) = state.telemetry.latest();
+        if latest != sequence {
+            sequence = latest;
+            websocket.send(Message::Text(serde_json::to_string(&telemetry)?))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{command_channel, CommandReceiver, Telemetry};
+    use std::io::BufRead;
+    use tempfile::TempDir;
+    use tungstenite::client;
+
+    fn start(directory: &TempDir) -> (SocketAddr, CommandReceiver, TelemetryHub, Arc<AtomicBool>) {
+        let params = directory.path().join(config::CONFIG_FILE);
+        fs::write(&params, "debug_config = \"None\"\n[roll_pid]\nkpa = 1.0\n").unwrap();
+        let (command_tx, command_rx) = command_channel();
+        let telemetry = TelemetryHub::default();
+        let server = Server::bind("127.0.0.1:0", command_tx, telemetry.clone(), params).unwrap();
+        let address = server.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let server_stop = Arc::clone(&stop);
+        thread::spawn(move || server.run(server_stop));
+        (address, command_rx, telemetry, stop)
+    }
+
+    fn http(address: SocketAddr, method: &str, path: &str, body: &str) -> (String, Value) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body).unwrap();
+        let mut response = io::BufReader::new(stream);
+        let mut status = String::new();
+        response.read_line(&mut status).unwrap();
+        let mut content = String::new();
+        response.read_to_string(&mut content).unwrap();
+        let body = content.split("\r\n\r\n").nth(1).unwrap();
+        (status.trim().into(), serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn test_http() {
+        let directory = tempfile::tempdir().unwrap();
+        let (address, command_rx, telemetry, stop) = start(&directory);
+
+        telemetry.publish(Telemetry {
+            armed: true,
+            ..Default::default()
+        });
+        let (status, body) = http(address, "GET", "/api/telemetry", "");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body["armed"], true);
+
+        let (status, _) = http(address, "POST", "/api/command", r#"{"command": "flight", "thrust": 0.5}"#);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        match command_rx.recv_timeout(Duration::from_secs(1)).unwrap().1 {
+            Command::Flight(command) => assert_eq!(command.thrust, 0.5),
+            command => panic!("Unexpected command {:?}", command),
+        }
+
+        let (status, _) = http(address, "POST", "/api/command", r#"{"command": "jump"}"#);
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        for flight in
+            [r#"{"command": "flight", "thrust": -0.1}"#, r#"{"command": "flight", "thrust": 0.5, "roll": 1.5}"#]
+        {
+            let (status, _) = http(address, "POST", "/api/command", flight);
+            assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        }
+        assert!(command_rx.try_recv().is_err());
+        let (status, _) = http(address, "GET", "/api/unknown", "");
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+        stop.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_params() {
+        let directory = tempfile::tempdir().unwrap();
+        let (address, command_rx, _, stop) = start(&directory);
+
+        let (_, body) = http(address, "GET", "/api/params", "");
+        assert_eq!(body["debug_config"], "None");
+        let (_, body) = http(address, "GET", "/api/params/roll_pid", "");
+        assert_eq!(body["kpa"], 1.0);
+        let (status, _) = http(address, "GET", "/api/params/pitch_pid", "");
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+        // Incomplete PID are rejected
+        let (status, _) = http(address, "PUT", "/api/params/pitch_pid", r#"{"kpa": 2.0, "kpr": 3.0}"#);
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        let pid =
+            r#"{"kpa": 2.0, "kpr": 3.0, "ti": 0.0, "td": 0.0, "filter": 0.0, "kaw": 0.0, "max": 1.0, "min": -1.0}"#;
+        let (status, _) = http(address, "PUT", "/api/params/pitch_pid", pid);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        match command_rx.recv_timeout(Duration::from_secs(1)).unwrap().1 {
+            Command::SetPid {
+                axis: PidAxis::Pitch,
+                pid,
+            } => assert_eq!(pid.kpr, 3.0),
+            command => panic!("Unexpected command {:?}", command),
+        }
+        let (_, body) = http(address, "GET", "/api/params/pitch_pid", "");
+        assert_eq!(body["kpr"], 3.0);
+        let (_, body) = http(address, "GET", "/api/params/debug_config", "");
+        assert_eq!(body, "None");
+
+        // Only the known parameters with a valid value are written
+        let (status, _) = http(address, "PUT", "/api/params/roll_pid", r#""x""#);
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        let (status, _) = http(address, "PUT", "/api/params/server", r#"{"enabled": true}"#);
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        let (status, _) = http(address, "PUT", "/api/params/debug_config", r#""PidLoop""#);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(matches!(
+            command_rx.recv_timeout(Duration::from_secs(1)).unwrap().1,
+            Command::SwitchDebug(DebugConfig::PidLoop)
+        ));
+        let (_, body) = http(address, "GET", "/api/params/debug_config", "");
+        assert_eq!(body, "PidLoop");
+        let (_, body) = http(address, "GET", "/api/params/roll_pid", "");
+        assert_eq!(body["kpa"], 1.0);
+
+        stop.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_websocket() {
+        let directory = tempfile::tempdir().unwrap();
+        let (address, command_rx, telemetry, stop) = start(&directory);
+
+        let stream = TcpStream::connect(address).unwrap();
+        let (mut websocket, _) = client(format!("ws://{}/api/ws", address), stream).unwrap();
+
+        websocket.send(Message::Text(r#"{"command": "arm"}"#.into())).unwrap();
+        assert!(matches!(command_rx.recv_timeout(Duration::from_secs(1)).unwrap().1, Command::Armed(true)));
+        let answer: Value = serde_json::from_str(websocket.read().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(answer["status"], "ok");
+
+        telemetry.publish(Telemetry {
+            landing: true,
+            ..Default::default()
+        });
+        let update: Value = serde_json::from_str(websocket.read().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(update["landing"], true);
+
+        stop.store(true, Ordering::Relaxed);
+    }
+}
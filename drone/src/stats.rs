@@ -0,0 +1,120 @@
+use serde::Serialize;
+use std::time::Duration;
+
+/// Inclusive upper bounds in µs of the histogram buckets like the Prometheus `le`, the last bucket holds everything
+/// above
+pub const BUCKETS: [u32; 11] = [10, 20, 50, 100, 200, 500, 1_000, 2_000, 5_000, 10_000, 20_000];
+
+/// Fixed size histogram of durations in µs with logarithmic buckets
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// Sample count per bucket, see [`BUCKETS`]
+    pub buckets: [u32; BUCKETS.len() + 1],
+    pub count: u32,
+    /// Longest duration in µs
+    pub max: u32,
+    /// Sum of the durations in µs
+    pub sum: u64,
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u32) {
+        let bucket = BUCKETS.iter().position(|bound| value <= *bound).unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.max = self.max.max(value);
+        self.sum += u64::from(value);
+    }
+
+    pub fn record_duration(&mut self, duration: Duration) {
+        self.record(u32::try_from(duration.as_micros()).unwrap_or(u32::MAX));
+    }
+
+    /// Adds the samples of another histogram
+    pub fn merge(&mut self, other: &Self) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+    }
+
+    /// Mean duration in µs
+    pub fn mean(&self) -> f32 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f32 / self.count as f32
+        }
+    }
+}
+
+/// Flight controller loop timing statistics aggregated over a period
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct LoopStats {
+    /// Time between the IMU interrupt wake up and the PRU inputs update
+    pub imu_to_pru: Histogram,
+    /// PRU PID loop duration
+    pub pru_cycle: Histogram,
+    /// PRU stall time during the PID loop
+    pub pru_stall: Histogram,
+    /// Time spent by the commands in the command channel
+    pub command_lag: Histogram,
+    /// Loop iterations longer than the IMU period
+    pub overruns: u32,
+    /// Index of the aggregation period, 0 before the first complete period
+    pub period: u32,
+}
+
+impl LoopStats {
+    /// Records the PRU timing given in PRU cycles (200MHz)
+    pub fn record_pru(&mut self, cycle: u32, stall: u32) {
+        self.pru_cycle.record(cycle / 200);
+        self.pru_stall.record(stall / 200);
+    }
+
+    /// Adds the samples of another period
+    pub fn merge(&mut self, other: &Self) {
+        self.imu_to_pru.merge(&other.imu_to_pru);
+        self.pru_cycle.merge(&other.pru_cycle);
+        self.pru_stall.merge(&other.pru_stall);
+        self.command_lag.merge(&other.command_lag);
+        self.overruns += other.overruns;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        for value in [0, 9, 10, 150, 999, 20_000, 50_000] {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.buckets, [3, 0, 0, 0, 1, 0, 1, 0, 0, 0, 1, 1]);
+        assert_eq!(histogram.count, 7);
+        assert_eq!(histogram.max, 50_000);
+        assert!((histogram.mean() - 10_166.857).abs() < 1e-3);
+
+        histogram.record_duration(Duration::from_secs(10_000));
+        assert_eq!(histogram.max, u32::MAX);
+    }
+
+    #[test]
+    fn test_pru_cycles() {
+        let mut stats = LoopStats::default();
+        stats.record_pru(20_000, 400);
+        assert_eq!(stats.pru_cycle.max, 100);
+        assert_eq!(stats.pru_stall.max, 2);
+
+        let mut total = LoopStats::default();
+        total.merge(&stats);
+        total.merge(&stats);
+        assert_eq!(total.pru_cycle.count, 2);
+        assert_eq!(total.pru_cycle.sum, 200);
+        assert_eq!(total.pru_cycle.buckets[BUCKETS.iter().position(|x| *x >= 100).unwrap()], 2);
+    }
+}
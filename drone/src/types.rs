@@ -1,4 +1,12 @@
 use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{channel, Receiver, SendError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::arbiter::Source;
+use crate::battery::BatteryState;
+use crate::health::ImuHealthCounters;
+use crate::stats::LoopStats;
 
 /// Proportional Integral Derivative controller parameters
 #[repr(C)]
@@ -50,20 +58,111 @@ pub struct Odometry {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
 pub struct FlightCommand {
     pub thrust: f32,
     pub angles: Angles,
 }
 
-#[derive(Debug)]
+/// Flight state periodically published by the flight controller
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+pub struct Telemetry {
+    pub armed: bool,
+    /// A failsafe landing is in progress
+    pub landing: bool,
+    /// Source in control of the drone
+    pub control: Option<Source>,
+    pub command: FlightCommand,
+    pub odometry: Odometry,
+    /// Altitude above the ground reference in m
+    pub altitude: f32,
+    /// `None` without battery monitoring
+    pub battery: Option<BatteryState>,
+    pub imu: ImuHealthCounters,
+    /// Loop timing statistics of the last complete period
+    pub stats: LoopStats,
+    /// Loop timing statistics accumulated over the complete periods since the start
+    pub total_stats: LoopStats,
+}
+
+/// Last telemetry shared between the network threads
+#[derive(Clone, Default)]
+pub struct TelemetryHub(Arc<Mutex<(u64, Telemetry)>>);
+
+impl TelemetryHub {
+    pub fn publish(&self, telemetry: Telemetry) {
+        let mut last = self.0.lock().unwrap();
+        *last = (last.0 + 1, telemetry);
+    }
+
+    /// Returns the last telemetry and its sequence number, 0 until the first publication
+    pub fn latest(&self) -> (u64, Telemetry) {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Attitude axis controlled by a PID
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PidAxis {
+    Roll,
+    Pitch,
+    Yaw,
+}
+
+#[derive(Serialize, Debug)]
 pub enum Command {
     Flight(FlightCommand),
+    /// Updates the attitude of the flight command keeping the current thrust
+    Attitude(Angles),
     SwitchDebug(DebugConfig),
     Armed(bool),
     SetMotor {
         motor: usize,
         value: u32,
     },
+    /// Updates a PID, applied by the PRU at the next arming
+    SetPid {
+        axis: PidAxis,
+        pid: PidConfig,
+    },
+    /// Starts a failsafe landing for the given reason
+    Failsafe(String),
     Stop,
 }
+
+/// Receiving half of the command channel, each command comes with its sending time and its source
+pub type CommandReceiver = Receiver<(Instant, Command, Source)>;
+
+/// Sending half of the command channel stamping each command with its sending time and its source
+#[derive(Clone)]
+pub struct CommandSender {
+    tx: Sender<(Instant, Command, Source)>,
+    source: Source,
+}
+
+impl CommandSender {
+    pub fn send(&self, command: Command) -> Result<(), SendError<Command>> {
+        self.tx.send((Instant::now(), command, self.source)).map_err(|SendError((_, command, _))| SendError(command))
+    }
+
+    /// Sender of the same channel for another source
+    pub fn with_source(&self, source: Source) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            source,
+        }
+    }
+}
+
+/// Creates the command channel, the returned sender is the failsafe one
+pub fn command_channel() -> (CommandSender, CommandReceiver) {
+    let (tx, rx) = channel();
+    (
+        CommandSender {
+            tx,
+            source: Source::Failsafe,
+        },
+        rx,
+    )
+}
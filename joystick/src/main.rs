@@ -0,0 +1,247 @@
+use anyhow::{anyhow, bail, Result};
+use gilrs::{Axis, Button, Event, EventType, Gilrs};
+use remote_protocol::RemoteMessage;
+use std::env;
+use std::io::{self, Read};
+use std::net::UdpSocket;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Flight command period (20Hz)
+const COMMAND_PERIOD: Duration = Duration::from_millis(50);
+/// Period at which the arming state is repeated in case a packet is lost
+const ARMED_PERIOD: Duration = Duration::from_secs(1);
+/// Time during which a key press holds an attitude command
+const KEY_HOLD: Duration = Duration::from_millis(300);
+const MOTOR_OFF: u32 = 199_999;
+const MOTOR_ON: u32 = 215_000;
+
+#[derive(Clone, Copy, Default)]
+struct FlightCommand {
+    thrust: f32,
+    roll: f32,
+    pitch: f32,
+    yaw: f32,
+}
+
+struct Sender {
+    socket: UdpSocket,
+    sequence: u32,
+    armed: bool,
+    last_armed: Instant,
+    last_command: Instant,
+}
+
+impl Sender {
+    fn new(address: &str) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(address)?;
+        Ok(Self {
+            socket,
+            sequence: 0,
+            armed: false,
+            last_armed: Instant::now(),
+            last_command: Instant::now(),
+        })
+    }
+
+    fn send(&mut self, message: RemoteMessage) -> Result<()> {
+        self.socket.send(&message.encode(self.sequence))?;
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    fn arm(&mut self, armed: bool) -> Result<()> {
+        if armed != self.armed {
+            // Carriage return for the raw terminal
+            print!(
+                "{}\r\n",
+                if armed {
+                    "Armed"
+                } else {
+                    "Disarmed"
+                }
+            );
+        }
+        self.armed = armed;
+        self.last_armed = Instant::now();
+        self.send(RemoteMessage::Armed(armed))
+    }
+
+    fn motor(&mut self, motor: u8, value: u32) -> Result<()> {
+        self.send(RemoteMessage::Motor {
+            motor,
+            value,
+        })
+    }
+
+    /// Sends the flight command at 20Hz and repeats the arming state every second
+    fn tick(&mut self, command: &FlightCommand) -> Result<()> {
+        if self.last_armed.elapsed() > ARMED_PERIOD {
+            self.arm(self.armed)?;
+        }
+        if self.last_command.elapsed() > COMMAND_PERIOD {
+            self.last_command = Instant::now();
+            self.send(RemoteMessage::Flight {
+                thrust: command.thrust,
+                roll: command.roll,
+                pitch: command.pitch,
+                yaw: command.yaw,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Same controls as the gamepad plugged on the drone: the left stick gives the roll and the pitch, the left trigger
+/// both the thrust and the arming, the face buttons spin a single motor and the D-pad adjusts its speed.
+fn gamepad(mut sender: Sender) -> Result<()> {
+    let mut gilrs = Gilrs::new().map_err(|err| anyhow!("Gamepad initialization: {}", err))?;
+    let mut command = FlightCommand::default();
+    let mut motor_on = MOTOR_ON;
+
+    loop {
+        while let Some(Event {
+            event,
+            ..
+        }) = gilrs.next_event()
+        {
+            match event {
+                EventType::Disconnected => {
+                    sender.arm(false)?;
+                    bail!("Gamepad disconnected");
+                },
+                EventType::ButtonChanged(Button::LeftTrigger2, value, _) => {
+                    command.thrust = value;
+                    sender.arm(value != 0.0)?;
+                },
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => command.pitch = value,
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => command.roll = value,
+                EventType::ButtonChanged(
+                    button @ (Button::North | Button::East | Button::South | Button::West),
+                    value,
+                    _,
+                ) => {
+                    let motor = match button {
+                        Button::North => 0,
+                        Button::East => 1,
+                        Button::South => 2,
+                        _ => 3,
+                    };
+                    sender.motor(
+                        motor,
+                        if value < 0.5 {
+                            MOTOR_OFF
+                        } else {
+                            motor_on
+                        },
+                    )?;
+                },
+                EventType::ButtonPressed(Button::DPadUp, _) => motor_on = (motor_on + 5000).min(2 * MOTOR_OFF),
+                EventType::ButtonPressed(Button::DPadDown, _) => motor_on = (motor_on - 5000).max(MOTOR_OFF),
+                _ => (),
+            }
+        }
+        sender.tick(&command)?;
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Terminal in raw non blocking mode restored on drop
+struct RawTerminal(libc::termios);
+
+impl RawTerminal {
+    fn new() -> Result<Self> {
+        let fd = io::stdin().as_raw_fd();
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let mut raw = termios;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Self(termios))
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(io::stdin().as_raw_fd(), libc::TCSANOW, &self.0) };
+    }
+}
+
+/// Keyboard controls, the terminal does not report the key releases so the attitude keys hold for a short time
+fn keyboard(mut sender: Sender) -> Result<()> {
+    println!("space: arm/disarm, r/f: thrust, w/s: pitch, a/d: roll, q/e: yaw, x: cut, esc: quit\r");
+    let _terminal = RawTerminal::new()?;
+    let mut command = FlightCommand::default();
+    let mut hold = Instant::now();
+    let mut key = [0];
+
+    loop {
+        while io::stdin().read(&mut key)? == 1 {
+            let attitude = match key[0] {
+                b' ' => {
+                    sender.arm(!sender.armed)?;
+                    None
+                },
+                b'r' => {
+                    command.thrust = (command.thrust + 0.05).min(1.0);
+                    None
+                },
+                b'f' => {
+                    command.thrust = (command.thrust - 0.05).max(0.0);
+                    None
+                },
+                b'w' => Some((0.0, 0.5, 0.0)),
+                b's' => Some((0.0, -0.5, 0.0)),
+                b'a' => Some((-0.5, 0.0, 0.0)),
+                b'd' => Some((0.5, 0.0, 0.0)),
+                b'q' => Some((0.0, 0.0, 0.5)),
+                b'e' => Some((0.0, 0.0, -0.5)),
+                b'x' => {
+                    command = FlightCommand::default();
+                    sender.arm(false)?;
+                    None
+                },
+                // Escape or ctrl-c
+                0x1b | 0x03 => {
+                    sender.arm(false)?;
+                    return Ok(());
+                },
+                _ => None,
+            };
+            if let Some((roll, pitch, yaw)) = attitude {
+                command.roll = roll;
+                command.pitch = pitch;
+                command.yaw = yaw;
+                hold = Instant::now();
+            }
+        }
+        if hold.elapsed() > KEY_HOLD {
+            command.roll = 0.0;
+            command.pitch = 0.0;
+            command.yaw = 0.0;
+        }
+        sender.tick(&command)?;
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let Some(address) = args.get(1) else {
+        bail!("Usage: joystick <drone address:port> [--keyboard]");
+    };
+    let sender = Sender::new(address)?;
+    if args.get(2).is_some_and(|arg| arg == "--keyboard") {
+        keyboard(sender)
+    } else {
+        gamepad(sender)
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, Write};
+use std::net::UdpSocket;
+use std::time::Duration;
+
+/// Magic starting the datagrams of the drone UDP log sink (`drone/src/log/sink.rs`).
+///
+/// Datagram layout: magic (2 bytes), record kind (0 logs, 1 measures, 2 stats), reserved byte, little endian sequence
+/// number (4 bytes) incremented for every record, then the record without line ending.
+const MAGIC: [u8; 2] = *b"DL";
+const HEADER_SIZE: usize = 8;
+const KINDS: [&str; 3] = ["logs", "measures", "stats"];
+/// Number of records received ahead of a missing one before it is considered lost
+const WINDOW: usize = 32;
+/// Time after which the pending records are written out when nothing is received
+const IDLE: Duration = Duration::from_millis(500);
+
+#[derive(Debug, PartialEq)]
+struct Datagram {
+    kind: u8,
+    sequence: u32,
+    record: Vec<u8>,
+}
+
+impl Datagram {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE || data[..2] != MAGIC {
+            return None;
+        }
+        Some(Self {
+            kind: data[2],
+            sequence: u32::from_le_bytes(data[4..8].try_into().ok()?),
+            record: data[HEADER_SIZE..].to_vec(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Record(Datagram),
+    /// Number of records never received
+    Lost(u32),
+    /// The sequence started over, the drone restarted
+    Restart,
+}
+
+/// Puts the records back in order and reports the missing ones.
+///
+/// Records arriving ahead of the expected one wait until it arrives or until `WINDOW` records are pending, the gap is
+/// then reported as lost. Late records are dropped.
+#[derive(Default)]
+struct Reassembler {
+    next: Option<u32>,
+    pending: HashMap<u32, Datagram>,
+}
+
+impl Reassembler {
+    fn push(&mut self, datagram: Datagram) -> Vec<Event> {
+        let mut events = Vec::new();
+        let Some(next) = self.next else {
+            self.next = Some(datagram.sequence.wrapping_add(1));
+            events.push(Event::Record(datagram));
+            return events;
+        };
+        let delta = datagram.sequence.wrapping_sub(next) as i32;
+        if delta < -(WINDOW as i32) {
+            // Far behind the expected record, the sequence was reset
+            events.extend(self.flush());
+            events.push(Event::Restart);
+            self.next = None;
+            events.extend(self.push(datagram));
+        } else if delta >= 0 {
+            self.pending.insert(datagram.sequence, datagram);
+            self.drain(&mut events);
+            if self.pending.len() > WINDOW {
+                events.extend(self.flush());
+            }
+        }
+        events
+    }
+
+    /// Writes out the pending records, skipping the missing ones
+    fn flush(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        while let Some(next) = self.next {
+            let Some(first) = self.pending.keys().copied().min_by_key(|sequence| sequence.wrapping_sub(next)) else {
+                break;
+            };
+            events.push(Event::Lost(first.wrapping_sub(next)));
+            self.next = Some(first);
+            self.drain(&mut events);
+        }
+        events
+    }
+
+    fn drain(&mut self, events: &mut Vec<Event>) {
+        while let Some(next) = self.next {
+            let Some(datagram) = self.pending.remove(&next) else {
+                break;
+            };
+            self.next = Some(next.wrapping_add(1));
+            events.push(Event::Record(datagram));
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let address = args.get(1).map_or("0.0.0.0:9000", String::as_str);
+    let kinds: Vec<u8> = match args.get(2) {
+        Some(kinds) => kinds
+            .split(',')
+            .map(|kind| match KINDS.iter().position(|x| *x == kind) {
+                Some(index) => Ok(index as u8),
+                None => bail!("Usage: log-receiver [address:port] [logs,measures,stats]"),
+            })
+            .collect::<Result<_>>()?,
+        None => vec![0, 1, 2],
+    };
+    let socket = UdpSocket::bind(address)?;
+    socket.set_read_timeout(Some(IDLE))?;
+
+    let mut reassembler = Reassembler::default();
+    let mut lost = 0;
+    let mut buffer = vec![0; 65_536];
+    let mut stdout = io::stdout().lock();
+    loop {
+        let events = match socket.recv(&mut buffer) {
+            Ok(size) => match Datagram::parse(&buffer[..size]) {
+                Some(datagram) => reassembler.push(datagram),
+                None => {
+                    eprintln!("Invalid datagram of {} bytes", size);
+                    continue;
+                },
+            },
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                reassembler.flush()
+            },
+            Err(err) => return Err(err.into()),
+        };
+        for event in events {
+            match event {
+                Event::Record(datagram) if kinds.contains(&datagram.kind) => {
+                    stdout.write_all(&datagram.record)?;
+                    stdout.write_all(b"\n")?;
+                },
+                Event::Record(_) => (),
+                Event::Lost(count) => {
+                    lost += count as u64;
+                    eprintln!("Lost {} records ({} in total)", count, lost);
+                },
+                Event::Restart => eprintln!("Drone restarted"),
+            }
+        }
+        stdout.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datagram(sequence: u32) -> Datagram {
+        Datagram {
+            kind: 0,
+            sequence,
+            record: sequence.to_string().into_bytes(),
+        }
+    }
+
+    fn sequences(events: Vec<Event>) -> Vec<i64> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                Event::Record(datagram) => datagram.sequence as i64,
+                Event::Lost(count) => -(count as i64),
+                Event::Restart => i64::MIN,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let datagram = Datagram::parse(b"DL\x01\x00\x02\x01\x00\x00MEASURE").unwrap();
+        assert_eq!(datagram.kind, 1);
+        assert_eq!(datagram.sequence, 258);
+        assert_eq!(datagram.record, b"MEASURE");
+        assert_eq!(Datagram::parse(b"DX\x01\x00\x02\x01\x00\x00"), None);
+        assert_eq!(Datagram::parse(b"DL\x01"), None);
+    }
+
+    #[test]
+    fn test_reassembler() {
+        let mut reassembler = Reassembler::default();
+        let mut events = Vec::new();
+        // Out of order, duplicated and missing records
+        for sequence in [u32::MAX - 1, 0, u32::MAX, 0, 1, 3] {
+            events.extend(reassembler.push(datagram(sequence)));
+        }
+        assert_eq!(sequences(events), [u32::MAX as i64 - 1, u32::MAX as i64, 0, 1]);
+        assert_eq!(sequences(reassembler.flush()), [-1, 3]);
+
+        // Too many records ahead of the missing one
+        let events: Vec<Event> = (6..6 + WINDOW as u32 + 1).flat_map(|x| reassembler.push(datagram(x))).collect();
+        let events = sequences(events);
+        assert_eq!(events[0], -2);
+        assert_eq!(events.len(), WINDOW + 2);
+
+        assert_eq!(sequences(reassembler.push(datagram(0))), [i64::MIN, 0]);
+    }
+}
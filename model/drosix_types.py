@@ -0,0 +1,194 @@
+# Generated by drone/build.rs from the Rust types, do not edit
+"""Layouts of the drone shared memory and log records.
+
+Each structure comes with a NumPy dtype, a `struct` format and the names of its flattened fields,
+e.g. `ODOMETRY_DTYPE`, `ODOMETRY_FORMAT` and `ODOMETRY_FIELDS` starting with `attitude.roll`.
+"""
+from enum import IntEnum
+
+import numpy as np
+
+LAYOUT_HASH = 0xccf18fa4
+
+
+class DebugConfig(IntEnum):
+    NONE = 0
+    PID_LOOP = 1
+    PID_NEW_DATA = 2
+    PWM_STEP = 3
+    PWM_CHANGE = 4
+
+
+PID_CONFIG_DTYPE = np.dtype(
+    {
+        "names": ["kpa", "kpr", "ti", "td", "filter", "kaw", "max", "min"],
+        "formats": ["<f4", "<f4", "<f4", "<f4", "<f4", "<f4", "<f4", "<f4"],
+        "offsets": [0, 4, 8, 12, 16, 20, 24, 28],
+        "itemsize": 32,
+    }
+)
+PID_CONFIG_FORMAT = "<ffffffff"
+PID_CONFIG_FIELDS = (
+    "kpa",
+    "kpr",
+    "ti",
+    "td",
+    "filter",
+    "kaw",
+    "max",
+    "min",
+)
+PID_CONFIG_SIZE = 32
+
+ANGLES_DTYPE = np.dtype(
+    {
+        "names": ["roll", "pitch", "yaw"],
+        "formats": ["<f4", "<f4", "<f4"],
+        "offsets": [0, 4, 8],
+        "itemsize": 12,
+    }
+)
+ANGLES_FORMAT = "<fff"
+ANGLES_FIELDS = (
+    "roll",
+    "pitch",
+    "yaw",
+)
+ANGLES_SIZE = 12
+
+ODOMETRY_DTYPE = np.dtype(
+    {
+        "names": ["attitude", "rate", "thrust"],
+        "formats": [ANGLES_DTYPE, ANGLES_DTYPE, "<f4"],
+        "offsets": [0, 12, 24],
+        "itemsize": 28,
+    }
+)
+ODOMETRY_FORMAT = "<fffffff"
+ODOMETRY_FIELDS = (
+    "attitude.roll",
+    "attitude.pitch",
+    "attitude.yaw",
+    "rate.roll",
+    "rate.pitch",
+    "rate.yaw",
+    "thrust",
+)
+ODOMETRY_SIZE = 28
+
+SHARED_MEM_DTYPE = np.dtype(
+    {
+        "names": ["layout_hash", "period", "pid_roll", "pid_pitch", "pid_yaw", "pid_thrust", "pid_input", "pid_output", "p_pid", "v_pid", "cycle", "stall", "debug_config"],
+        "formats": ["<u4", "<u4", PID_CONFIG_DTYPE, PID_CONFIG_DTYPE, PID_CONFIG_DTYPE, PID_CONFIG_DTYPE, ODOMETRY_DTYPE, ("<u4", (4,)), ANGLES_DTYPE, ANGLES_DTYPE, "<u4", "<u4", "<i4"],
+        "offsets": [0, 4, 8, 40, 72, 104, 136, 164, 180, 192, 204, 208, 212],
+        "itemsize": 216,
+    }
+)
+SHARED_MEM_FORMAT = "<IIfffffffffffffffffffffffffffffffffffffffIIIIffffffIIi"
+SHARED_MEM_FIELDS = (
+    "layout_hash",
+    "period",
+    "pid_roll.kpa",
+    "pid_roll.kpr",
+    "pid_roll.ti",
+    "pid_roll.td",
+    "pid_roll.filter",
+    "pid_roll.kaw",
+    "pid_roll.max",
+    "pid_roll.min",
+    "pid_pitch.kpa",
+    "pid_pitch.kpr",
+    "pid_pitch.ti",
+    "pid_pitch.td",
+    "pid_pitch.filter",
+    "pid_pitch.kaw",
+    "pid_pitch.max",
+    "pid_pitch.min",
+    "pid_yaw.kpa",
+    "pid_yaw.kpr",
+    "pid_yaw.ti",
+    "pid_yaw.td",
+    "pid_yaw.filter",
+    "pid_yaw.kaw",
+    "pid_yaw.max",
+    "pid_yaw.min",
+    "pid_thrust.kpa",
+    "pid_thrust.kpr",
+    "pid_thrust.ti",
+    "pid_thrust.td",
+    "pid_thrust.filter",
+    "pid_thrust.kaw",
+    "pid_thrust.max",
+    "pid_thrust.min",
+    "pid_input.attitude.roll",
+    "pid_input.attitude.pitch",
+    "pid_input.attitude.yaw",
+    "pid_input.rate.roll",
+    "pid_input.rate.pitch",
+    "pid_input.rate.yaw",
+    "pid_input.thrust",
+    "pid_output.0",
+    "pid_output.1",
+    "pid_output.2",
+    "pid_output.3",
+    "p_pid.roll",
+    "p_pid.pitch",
+    "p_pid.yaw",
+    "v_pid.roll",
+    "v_pid.pitch",
+    "v_pid.yaw",
+    "cycle",
+    "stall",
+    "debug_config",
+)
+SHARED_MEM_SIZE = 216
+
+FLIGHT_COMMAND_DTYPE = np.dtype(
+    {
+        "names": ["thrust", "angles"],
+        "formats": ["<f4", ANGLES_DTYPE],
+        "offsets": [0, 4],
+        "itemsize": 16,
+    }
+)
+FLIGHT_COMMAND_FORMAT = "<ffff"
+FLIGHT_COMMAND_FIELDS = (
+    "thrust",
+    "angles.roll",
+    "angles.pitch",
+    "angles.yaw",
+)
+FLIGHT_COMMAND_SIZE = 16
+
+MEASURE_RECORD_DTYPE = np.dtype(
+    {
+        "names": ["command", "sensor", "position_pid", "velocity_pid", "mag"],
+        "formats": [FLIGHT_COMMAND_DTYPE, ODOMETRY_DTYPE, ANGLES_DTYPE, ANGLES_DTYPE, ("<f4", (3,))],
+        "offsets": [0, 16, 44, 56, 68],
+        "itemsize": 80,
+    }
+)
+MEASURE_RECORD_FORMAT = "<ffffffffffffffffffff"
+MEASURE_RECORD_FIELDS = (
+    "command.thrust",
+    "command.angles.roll",
+    "command.angles.pitch",
+    "command.angles.yaw",
+    "sensor.attitude.roll",
+    "sensor.attitude.pitch",
+    "sensor.attitude.yaw",
+    "sensor.rate.roll",
+    "sensor.rate.pitch",
+    "sensor.rate.yaw",
+    "sensor.thrust",
+    "position_pid.roll",
+    "position_pid.pitch",
+    "position_pid.yaw",
+    "velocity_pid.roll",
+    "velocity_pid.pitch",
+    "velocity_pid.yaw",
+    "mag.0",
+    "mag.1",
+    "mag.2",
+)
+MEASURE_RECORD_SIZE = 80
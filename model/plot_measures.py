@@ -1,70 +1,56 @@
-import struct
 import matplotlib.pyplot as plt
+import numpy as np
 import sys
 
+from drosix_types import MEASURE_RECORD_DTYPE, MEASURE_RECORD_SIZE
+
+MARKER = b"] MEASURE "
+
 
 def read_file(f):
     with open(f, "rb") as f:
         data = f.read()
-        unpacked = []
-        try:
-            for i in range(0, len(data), 84):
-                line = struct.unpack("<QQ fff fff f IIII fff fff", data[i : i + 84])
-                unpacked.append(line)
-        except struct.error:
-            pass
+        time = []
+        records = bytearray()
+        start = data.find(MARKER)
+        while start >= 9 and start + len(MARKER) + MEASURE_RECORD_SIZE <= len(data):
+            time.append(float(data[start - 9 : start]))
+            begin = start + len(MARKER)
+            records += data[begin : begin + MEASURE_RECORD_SIZE]
+            start = data.find(MARKER, begin + MEASURE_RECORD_SIZE)
+
+        return np.array(time), np.frombuffer(bytes(records), dtype=MEASURE_RECORD_DTYPE)
+
+
+def field(data, path):
+    for name in path.split("."):
+        data = data[name]
+    return data
+
 
-        return unpacked
+def plot_axes(time, data, prefix, style="o--"):
+    for axis in ["roll", "pitch", "yaw"]:
+        plt.plot(time, field(data, f"{prefix}.{axis}"), style, label=axis)
 
 
 if __name__ == "__main__":
     if len(sys.argv) != 2:
         print(f"usage: {sys.argv[0]} FILE")
         sys.exit(-1)
-    data = list(zip(*read_file(sys.argv[1])))
-    data = {
-        "time": data[0],
-        "roll": data[2],
-        "pitch": data[3],
-        "yaw": data[4],
-        "vroll": data[5],
-        "vpitch": data[6],
-        "vyaw": data[7],
-        "thrust": data[8],
-        "motor1": data[9],
-        "motor2": data[10],
-        "motor3": data[11],
-        "motor4": data[12],
-        "px": data[13],
-        "py": data[14],
-        "pz": data[15],
-        "vx": data[16],
-        "vy": data[17],
-        "vz": data[18],
-    }
+    time, data = read_file(sys.argv[1])
 
     plt.figure("Odometry")
-    plt.plot(data["time"], data["roll"], "ro--", label="roll")
-    plt.plot(data["time"], data["pitch"], "go--", label="pitch")
-    plt.plot(data["time"], data["yaw"], "bo--", label="yaw")
-    plt.plot(data["time"], data["vroll"], "r+--", label="vroll")
-    plt.plot(data["time"], data["vpitch"], "g+--", label="vpitch")
-    plt.plot(data["time"], data["vyaw"], "b+--", label="vyaw")
+    plot_axes(time, data, "sensor.attitude")
+    plot_axes(time, data, "sensor.rate", "+--")
     plt.legend()
-    plt.figure("motor")
-    plt.plot(data["time"], data["motor1"], "o--", label="1")
-    plt.plot(data["time"], data["motor2"], "o--", label="2")
-    plt.plot(data["time"], data["motor3"], "o--", label="3")
-    plt.plot(data["time"], data["motor4"], "o--", label="4")
+    plt.figure("Command")
+    plot_axes(time, data, "command.angles")
+    plt.plot(time, field(data, "command.thrust"), "o--", label="thrust")
     plt.legend()
     plt.figure("vpid")
-    plt.plot(data["time"], data["vx"], "o--", label="x")
-    plt.plot(data["time"], data["vy"], "o--", label="y")
-    plt.plot(data["time"], data["vz"], "o--", label="z")
+    plot_axes(time, data, "velocity_pid")
     plt.legend()
     plt.figure("ppid")
-    plt.plot(data["time"], data["px"], "o--", label="x")
-    plt.plot(data["time"], data["py"], "o--", label="y")
-    plt.plot(data["time"], data["pz"], "o--", label="z")
+    plot_axes(time, data, "position_pid")
     plt.legend()
     plt.show()
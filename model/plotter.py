@@ -11,8 +11,12 @@ import queue
 import datetime
 import argparse
 
+from drosix_types import MEASURE_RECORD_FIELDS, MEASURE_RECORD_FORMAT, MEASURE_RECORD_SIZE
+
 
 TO_DEG = 180 / np.pi
+# Frame column of each measure, the first one is the time
+COLUMN = {name: 1 + i for i, name in enumerate(MEASURE_RECORD_FIELDS)}
 
 
 class DrosixSink:
@@ -51,9 +55,13 @@ class DrosixSink:
         self.socket.bind((address, port))
         while self.run.is_set():
             try:
-                data = self.socket.recv(1024)
+                data = self.socket.recv(65536)
             except socket.timeout:
                 continue
+            # Drop the header of the drone UDP log sink (magic, kind, reserved, sequence)
+            if not data.startswith(b"DL"):
+                continue
+            data = data[8:] + b"\n"
             if self.record:
                 save_fd.write(data)
             self._parse(data)
@@ -68,19 +76,16 @@ class DrosixSink:
         while len(data) > 12:
             if data[12:].startswith(b"MEASURE"):
                 time = float(data[1:10])
-                # command: thrust roll pitch yaw
-                # sensor: roll pitch yaw droll dpitch dyaw thrust
-                # pos pid: roll pitch yaw
-                # vel pid: roll pitch yaw
-                measures = list(struct.unpack("<ffff fffffff fff fff", data[20:88]))
+                end = 20 + MEASURE_RECORD_SIZE
+                measures = list(struct.unpack(MEASURE_RECORD_FORMAT, data[20:end]))
                 self.queue.put((time, measures))
-                data = data[89:]
+                data = data[end + 1 :]
             else:
                 line, data = data.split(b"\n", maxsplit=1)
                 print(f"LOG: {line.decode()}")
 
     def __iter__(self):
-        frame = np.empty((self.iter_buf_size, 18))
+        frame = np.empty((self.iter_buf_size, 1 + len(MEASURE_RECORD_FIELDS)))
         cursor = 0
         flush = False
         while self.run.is_set():
@@ -93,8 +98,10 @@ class DrosixSink:
                 flush = True
 
             if cursor >= frame.shape[0] or flush:
-                frame[:cursor, 1:4] *= 15
-                frame[:cursor, 4:10] *= TO_DEG
+                command = COLUMN["command.angles.roll"]
+                frame[:cursor, command : command + 3] *= 15
+                sensor = COLUMN["sensor.attitude.roll"]
+                frame[:cursor, sensor : sensor + 6] *= TO_DEG
                 yield frame[:cursor].T
                 cursor = 0
                 flush = False
@@ -295,11 +302,12 @@ class Plotter:
     def animate(self, frame):
         if frame.size > 0:
             self.time.extend(frame[0])
-            [self.ref[i].extend(frame[2 + i]) for i in range(0, 3)]
-            [self.pos[i].extend(frame[5 + i]) for i in range(0, 3)]
-            [self.vel[i].extend(frame[8 + i]) for i in range(0, 3)]
-            [self.p_pid[i].extend(frame[12 + i]) for i in range(0, 3)]
-            [self.v_pid[i].extend(frame[15 + i]) for i in range(0, 3)]
+            for i, axis in enumerate(["roll", "pitch", "yaw"]):
+                self.ref[i].extend(frame[COLUMN[f"command.angles.{axis}"]])
+                self.pos[i].extend(frame[COLUMN[f"sensor.attitude.{axis}"]])
+                self.vel[i].extend(frame[COLUMN[f"sensor.rate.{axis}"]])
+                self.p_pid[i].extend(frame[COLUMN[f"position_pid.{axis}"]])
+                self.v_pid[i].extend(frame[COLUMN[f"velocity_pid.{axis}"]])
 
             self.graph["Position"].set_data(self.time, self.pos, ref=self.ref)
             self.graph["Velocity"].set_data(self.time, self.vel)
@@ -88,6 +88,10 @@ struct Config {
     cm: f64,
     throttle: f64,
     w: f64,
+    /// Ratio between the battery voltage and the voltage at which the hover throttle was tuned
+    voltage: f64,
+    /// Thrust scaling applied by the flight controller voltage compensation
+    thrust_scale: f64,
 }
 
 #[derive(Default, Copy, Clone)]
@@ -112,6 +116,19 @@ impl Model {
     fn new(path: String, set_point: f64, thrust: Option<PyReadonlyArray1<f64>>) -> PyResult<Self> {
         let content = std::fs::read_to_string(path)?;
         let config: Value = toml::from_str(&content).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+
+        // Battery sag simulation and flight controller compensation, both optional
+        let compensation = config.get("thrust_compensation");
+        let enabled = compensation.and_then(|x| x.get("enabled")).and_then(Value::as_bool).unwrap_or(false);
+        let nominal = compensation.and_then(|x| x.get("nominal")).and_then(Value::as_float).unwrap_or(4.2);
+        let max_scale = compensation.and_then(|x| x.get("max")).and_then(Value::as_float).unwrap_or(1.3);
+        let cell_voltage = config["battery"].get("voltage").and_then(Value::as_float).unwrap_or(nominal);
+        let thrust_scale = if enabled {
+            (nominal / cell_voltage).min(max_scale)
+        } else {
+            1.0
+        };
+
         let config = Config {
             size: config["frame"]["size"].as_float().ok_or(PyKeyError::new_err("frame/size"))?,
             jx: config["frame"]["jx"].as_float().ok_or(PyKeyError::new_err("frame/jx"))?,
@@ -124,6 +141,8 @@ impl Model {
             cm: config["propeller"]["cm"].as_float().ok_or(PyKeyError::new_err("propeller/cm"))?,
             throttle: config["hover"]["throttle"].as_float().ok_or(PyKeyError::new_err("hover/throttle"))?,
             w: config["hover"]["w"].as_float().ok_or(PyKeyError::new_err("hover/w"))?,
+            voltage: cell_voltage / nominal,
+            thrust_scale,
         };
 
         let thrust = thrust.map(|x| x.as_slice().unwrap().to_vec());
@@ -150,7 +169,7 @@ impl Model {
         let mut drone = Drone {
             config: self.config,
             set_point,
-            throttles: [self.config.throttle; 4],
+            throttles: [self.config.throttle * self.config.thrust_scale; 4],
         };
 
         let w = if let Some(thrust) = self.thrust.as_ref() {
@@ -204,11 +223,12 @@ impl Model {
             // Motor allocation with PWM consideration
             // The output of PID is truncated to simulate cast to int
             // The output of PID is divided by 200000 to be between [0:1] instead of[0:200000]
+            let throttle = drone.config.throttle * drone.config.thrust_scale;
             drone.throttles = [
-                drone.config.throttle + (cmd_vroll.trunc() / 200_000.0),
-                drone.config.throttle + (-cmd_vroll.trunc() / 200_000.0),
-                drone.config.throttle + (-cmd_vroll.trunc() / 200_000.0),
-                drone.config.throttle + (cmd_vroll.trunc() / 200_000.0),
+                throttle + (cmd_vroll.trunc() / 200_000.0),
+                throttle + (-cmd_vroll.trunc() / 200_000.0),
+                throttle + (-cmd_vroll.trunc() / 200_000.0),
+                throttle + (cmd_vroll.trunc() / 200_000.0),
             ];
 
             for throttle in drone.throttles {
@@ -240,8 +260,9 @@ impl Model {
  */
 pub fn compute_accel(state: &mut State<f64>, env: &Drone) {
     for i in 0..4 {
-        state.deriv[i] =
-            (env.config.cr * env.throttles[i].clamp(0.0, 1.0) + env.config.wb - state.value[i]) / env.config.tm;
+        // The motor speed scales with the battery voltage
+        let w = (env.config.cr * env.throttles[i].clamp(0.0, 1.0) + env.config.wb) * env.config.voltage;
+        state.deriv[i] = (w - state.value[i]) / env.config.tm;
     }
 
     let w0 = state.value[0].powi(2);
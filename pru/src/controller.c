@@ -36,6 +36,9 @@ void main(void) {
 
     CT_CFG.SYSCFG_bit.STANDBY_INIT = 0U;    /* enable OCP master port */
 
+    /* report the shared memory layout this firmware was built with */
+    controller.layout_hash = SHARED_MEM_LAYOUT_HASH;
+
     /* wait motor to be ready */
     while(check_event0() != EVT_MOTOR_STATUS) {}
 
@@ -116,6 +119,13 @@ void main(void) {
             }
             break;
         case EVT_SET_ARMED:
+            /* PID parameters may have been updated while disarmed */
+            pid_init(&pid_roll, &controller.pid_roll, sampling_period);
+            pid_init(&pid_pitch, &controller.pid_pitch, sampling_period);
+            pid_init(&pid_yaw, &controller.pid_yaw, sampling_period);
+            kpa_roll = controller.pid_roll.kpa;
+            kpa_pitch = controller.pid_pitch.kpa;
+            kpa_yaw = controller.pid_yaw.kpa;
             set_armed();
             break;
         case EVT_CLEAR_ARMED:
@@ -2,7 +2,7 @@
 #define __DROSIX_H__
 #include <stdint.h>
 #include "util.h"
-#include "shared-memory.h"
+#include "shared-memory-layout.h"
 #pragma RESET_MISRA("all")
 
 /* Drosix related event */
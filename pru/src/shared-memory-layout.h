@@ -0,0 +1,38 @@
+/* Generated by drone/build.rs from shared-memory.h, do not edit */
+#pragma once
+#include <stddef.h>
+#include "shared-memory.h"
+
+#define SHARED_MEM_LAYOUT_HASH 0xccf18fa4U
+
+typedef char pid_config_kpa_offset[(offsetof(struct pid_config, kpa) == 0U) ? 1 : -1];
+typedef char pid_config_kpr_offset[(offsetof(struct pid_config, kpr) == 4U) ? 1 : -1];
+typedef char pid_config_ti_offset[(offsetof(struct pid_config, ti) == 8U) ? 1 : -1];
+typedef char pid_config_td_offset[(offsetof(struct pid_config, td) == 12U) ? 1 : -1];
+typedef char pid_config_filter_offset[(offsetof(struct pid_config, filter) == 16U) ? 1 : -1];
+typedef char pid_config_kaw_offset[(offsetof(struct pid_config, kaw) == 20U) ? 1 : -1];
+typedef char pid_config_max_offset[(offsetof(struct pid_config, max) == 24U) ? 1 : -1];
+typedef char pid_config_min_offset[(offsetof(struct pid_config, min) == 28U) ? 1 : -1];
+typedef char pid_config_size[(sizeof(struct pid_config) == 32U) ? 1 : -1];
+typedef char angles_roll_offset[(offsetof(struct angles, roll) == 0U) ? 1 : -1];
+typedef char angles_pitch_offset[(offsetof(struct angles, pitch) == 4U) ? 1 : -1];
+typedef char angles_yaw_offset[(offsetof(struct angles, yaw) == 8U) ? 1 : -1];
+typedef char angles_size[(sizeof(struct angles) == 12U) ? 1 : -1];
+typedef char odometry_attitude_offset[(offsetof(struct odometry, attitude) == 0U) ? 1 : -1];
+typedef char odometry_rate_offset[(offsetof(struct odometry, rate) == 12U) ? 1 : -1];
+typedef char odometry_thrust_offset[(offsetof(struct odometry, thrust) == 24U) ? 1 : -1];
+typedef char odometry_size[(sizeof(struct odometry) == 28U) ? 1 : -1];
+typedef char shared_mem_layout_hash_offset[(offsetof(struct shared_mem, layout_hash) == 0U) ? 1 : -1];
+typedef char shared_mem_period_offset[(offsetof(struct shared_mem, period) == 4U) ? 1 : -1];
+typedef char shared_mem_pid_roll_offset[(offsetof(struct shared_mem, pid_roll) == 8U) ? 1 : -1];
+typedef char shared_mem_pid_pitch_offset[(offsetof(struct shared_mem, pid_pitch) == 40U) ? 1 : -1];
+typedef char shared_mem_pid_yaw_offset[(offsetof(struct shared_mem, pid_yaw) == 72U) ? 1 : -1];
+typedef char shared_mem_pid_thrust_offset[(offsetof(struct shared_mem, pid_thrust) == 104U) ? 1 : -1];
+typedef char shared_mem_pid_input_offset[(offsetof(struct shared_mem, pid_input) == 136U) ? 1 : -1];
+typedef char shared_mem_pid_output_offset[(offsetof(struct shared_mem, pid_output) == 164U) ? 1 : -1];
+typedef char shared_mem_p_pid_offset[(offsetof(struct shared_mem, p_pid) == 180U) ? 1 : -1];
+typedef char shared_mem_v_pid_offset[(offsetof(struct shared_mem, v_pid) == 192U) ? 1 : -1];
+typedef char shared_mem_cycle_offset[(offsetof(struct shared_mem, cycle) == 204U) ? 1 : -1];
+typedef char shared_mem_stall_offset[(offsetof(struct shared_mem, stall) == 208U) ? 1 : -1];
+typedef char shared_mem_debug_config_offset[(offsetof(struct shared_mem, debug_config) == 212U) ? 1 : -1];
+typedef char shared_mem_size[(sizeof(struct shared_mem) == 216U) ? 1 : -1];
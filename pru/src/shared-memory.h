@@ -42,6 +42,7 @@ typedef struct angles angles_t;
 typedef enum debug_config debug_config_t;
 
 struct shared_mem {
+  u32 layout_hash;
   u32 period;
   pid_config_t pid_roll;
   pid_config_t pid_pitch;
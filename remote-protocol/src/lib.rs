@@ -0,0 +1,138 @@
+use anyhow::{bail, Result};
+
+/// Magic starting the network remote packets
+const MAGIC: [u8; 2] = *b"DX";
+const HEADER_LENGTH: usize = 8;
+const FLIGHT: u8 = 0;
+const ARMED: u8 = 1;
+const MOTOR: u8 = 2;
+
+/// Message sent by the network remote.
+///
+/// A packet is the `DX` magic, the message kind, a reserved byte, the sequence number as a little endian `u32` then
+/// the payload in little endian:
+/// - flight (0): thrust, roll, pitch and yaw as `f32`
+/// - armed (1): 1 to arm, 0 to disarm
+/// - motor (2): motor index as `u8` then PWM value as `u32`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RemoteMessage {
+    /// Thrust from 0 to 1 and sticks from -1 to 1
+    Flight {
+        thrust: f32,
+        roll: f32,
+        pitch: f32,
+        yaw: f32,
+    },
+    Armed(bool),
+    Motor {
+        motor: u8,
+        value: u32,
+    },
+}
+
+impl RemoteMessage {
+    pub fn encode(&self, sequence: u32) -> Vec<u8> {
+        let kind = match self {
+            Self::Flight {
+                ..
+            } => FLIGHT,
+            Self::Armed(_) => ARMED,
+            Self::Motor {
+                ..
+            } => MOTOR,
+        };
+        let mut packet = vec![MAGIC[0], MAGIC[1], kind, 0];
+        packet.extend(sequence.to_le_bytes());
+        match self {
+            Self::Flight {
+                thrust,
+                roll,
+                pitch,
+                yaw,
+            } => {
+                for x in [thrust, roll, pitch, yaw] {
+                    packet.extend(x.to_le_bytes());
+                }
+            },
+            Self::Armed(armed) => packet.push(u8::from(*armed)),
+            Self::Motor {
+                motor,
+                value,
+            } => {
+                packet.push(*motor);
+                packet.extend(value.to_le_bytes());
+            },
+        }
+        packet
+    }
+
+    /// Returns the message and its sequence number.
+    /// The flight commands with a non finite value are rejected, the others are clamped to their range.
+    pub fn decode(packet: &[u8]) -> Result<(u32, Self)> {
+        if packet.len() < HEADER_LENGTH || packet[..2] != MAGIC {
+            bail!("Invalid remote packet");
+        }
+        let sequence = u32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        let payload = &packet[HEADER_LENGTH..];
+        let f32_at = |i: usize| f32::from_le_bytes([payload[i], payload[i + 1], payload[i + 2], payload[i + 3]]);
+        let message = match packet[2] {
+            FLIGHT if payload.len() >= 16 => {
+                let [thrust, roll, pitch, yaw] = [f32_at(0), f32_at(4), f32_at(8), f32_at(12)];
+                if !(thrust.is_finite() && roll.is_finite() && pitch.is_finite() && yaw.is_finite()) {
+                    bail!("Invalid remote flight command");
+                }
+                Self::Flight {
+                    thrust: thrust.clamp(0.0, 1.0),
+                    roll: roll.clamp(-1.0, 1.0),
+                    pitch: pitch.clamp(-1.0, 1.0),
+                    yaw: yaw.clamp(-1.0, 1.0),
+                }
+            },
+            ARMED if !payload.is_empty() => Self::Armed(payload[0] != 0),
+            MOTOR if payload.len() >= 5 => Self::Motor {
+                motor: payload[0],
+                value: u32::from_le_bytes([payload[1], payload[2], payload[3], payload[4]]),
+            },
+            kind => bail!("Invalid remote message {} of {} bytes", kind, payload.len()),
+        };
+        Ok((sequence, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec() {
+        let messages = [
+            RemoteMessage::Flight {
+                thrust: 0.5,
+                roll: -0.25,
+                pitch: 0.125,
+                yaw: 1.0,
+            },
+            RemoteMessage::Armed(true),
+            RemoteMessage::Motor {
+                motor: 3,
+                value: 215_000,
+            },
+        ];
+        for (sequence, message) in messages.iter().enumerate() {
+            let packet = message.encode(sequence as u32);
+            assert_eq!(RemoteMessage::decode(&packet).unwrap(), (sequence as u32, *message));
+        }
+        assert!(RemoteMessage::decode(b"DX\x00\x00\x01\x00\x00\x00\x01").is_err());
+        assert!(RemoteMessage::decode(b"XX\x01\x00\x01\x00\x00\x00\x01").is_err());
+
+        let flight = |thrust, roll| RemoteMessage::Flight {
+            thrust,
+            roll,
+            pitch: 0.0,
+            yaw: 0.0,
+        };
+        assert!(RemoteMessage::decode(&flight(f32::NAN, 0.0).encode(0)).is_err());
+        assert!(RemoteMessage::decode(&flight(0.5, f32::INFINITY).encode(0)).is_err());
+        assert_eq!(RemoteMessage::decode(&flight(-0.5, 2.0).encode(0)).unwrap(), (0, flight(0.0, 1.0)));
+    }
+}